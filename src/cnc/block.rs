@@ -1,6 +1,19 @@
 use crate::render::generic_mesh::{CNCBlockVertex, Mesh, Triangle};
 use nalgebra::{point, vector, Vector2, Vector3};
 
+/// Rectangle of samples, `max` is exclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub min: Vector2<usize>,
+    pub max: Vector2<usize>,
+}
+
+impl DirtyRect {
+    pub fn size(&self) -> Vector2<usize> {
+        self.max - self.min
+    }
+}
+
 #[derive(Clone)]
 pub struct Block {
     sampling: Vector2<usize>,
@@ -9,6 +22,8 @@ pub struct Block {
     height: f32,
     size: Vector2<f32>,
     pub base_height: f32,
+    // Samples changed since the last `take_dirty_rect`
+    dirty_rect: Option<DirtyRect>,
 }
 
 impl Block {
@@ -20,6 +35,26 @@ impl Block {
             height: size.z,
             size: vector![size.x, size.y],
             base_height: size.z / 10.0,
+            dirty_rect: None,
+        }
+    }
+
+    pub fn from_heights(
+        sampling: Vector2<usize>,
+        size: Vector3<f32>,
+        heights: Vec<f32>,
+        base_height: f32,
+    ) -> Self {
+        assert_eq!(heights.len(), sampling.x * sampling.y);
+
+        Self {
+            sample_size: vector![size.x / sampling.x as f32, size.y / sampling.y as f32],
+            heights,
+            sampling,
+            height: size.z,
+            size: vector![size.x, size.y],
+            base_height,
+            dirty_rect: None,
         }
     }
 
@@ -40,10 +75,30 @@ impl Block {
     }
 
     pub fn height_mut(&mut self, x: usize, y: usize) -> &mut f32 {
+        self.mark_dirty(x, y);
         let idx = self.heights_idx(x, y);
         &mut self.heights[idx]
     }
 
+    fn mark_dirty(&mut self, x: usize, y: usize) {
+        let rect = self.dirty_rect.get_or_insert(DirtyRect {
+            min: vector![x, y],
+            max: vector![x + 1, y + 1],
+        });
+
+        rect.min = rect.min.inf(&vector![x, y]);
+        rect.max = rect.max.sup(&vector![x + 1, y + 1]);
+    }
+
+    pub fn dirty_rect(&self) -> Option<DirtyRect> {
+        self.dirty_rect
+    }
+
+    /// Returns the samples changed since the previous call and starts tracking anew
+    pub fn take_dirty_rect(&mut self) -> Option<DirtyRect> {
+        self.dirty_rect.take()
+    }
+
     pub fn cut(&mut self, x: usize, y: usize, height: f32) -> bool {
         if self.height(x, y) > height {
             *self.height_mut(x, y) = height;
@@ -357,6 +412,35 @@ impl Block {
         ]
     }
 
+    /// Center of the sample in mill coordinates, inverse of `mill_to_block`
+    pub fn block_to_mill(&self, x: usize, y: usize) -> Vector2<f32> {
+        vector![
+            (x as f32 + 0.5) * self.sample_size.x - 0.5 * self.size.x,
+            (y as f32 + 0.5) * self.sample_size.y - 0.5 * self.size.y
+        ]
+    }
+
+    pub fn height_at(&self, position: &Vector2<f32>) -> Option<f32> {
+        let loc = self.mill_to_block(position);
+        self.contains(&loc)
+            .then(|| self.height(loc.x as usize, loc.y as usize))
+    }
+
+    /// Lowers the stock to `depth` below its top inside of an axis aligned rectangle given in mill
+    /// coordinates
+    pub fn cut_pocket(&mut self, center: &Vector2<f32>, size: &Vector2<f32>, depth: f32) {
+        let bottom = self.height - depth;
+
+        for x in 0..self.sampling.x {
+            for y in 0..self.sampling.y {
+                let offset = self.block_to_mill(x, y) - center;
+                if offset.x.abs() <= 0.5 * size.x && offset.y.abs() <= 0.5 * size.y {
+                    self.cut(x, y, bottom);
+                }
+            }
+        }
+    }
+
     pub fn contains(&self, loc: &Vector2<i32>) -> bool {
         loc.x >= 0 && loc.y >= 0 && loc.x < self.sampling.x as i32 && loc.y < self.sampling.y as i32
     }
@@ -0,0 +1,226 @@
+use super::{
+    block::Block,
+    dexel::{Dexel, DexelBlock},
+    fixture::{Fixture, FixtureShape},
+    mill::{Cutter, Holder, SpeedLimits},
+    milling_process::{MillingError, MillingErrorKind},
+    program::Program,
+    stock::{Stock, StockModel},
+};
+use nalgebra::{vector, Vector3};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum StockState {
+    Heightmap { heights: Vec<f32> },
+    MultiDexel { dexels: Vec<Vec<Dexel>> },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum FixtureShapeState {
+    Box { size: [f32; 3] },
+    Cylinder { radius: f32, height: f32 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureState {
+    pub name: String,
+    pub position: [f32; 3],
+    pub shape: FixtureShapeState,
+}
+
+/// Problem collected in the lenient mode
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProblemState {
+    pub kind: MillingErrorKind,
+    pub instruction_idx: usize,
+    pub source_line: Option<usize>,
+    pub position: [f32; 3],
+}
+
+/// State of a half-run `MillingProcess` from which the simulation can be resumed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    pub sampling_x: usize,
+    pub sampling_y: usize,
+    pub size_x: f32,
+    pub size_y: f32,
+    pub size_z: f32,
+    pub base_height: f32,
+    pub stock: StockState,
+    pub program: Program,
+    pub current_instruction: usize,
+    /// Instruction the last problem is reported for, the current one if not given
+    pub executed_instruction: Option<usize>,
+    /// Instruction for which the listeners have been notified about the start
+    pub started_instruction: Option<usize>,
+    pub cutter: Cutter,
+    pub holder: Holder,
+    pub position: [f32; 3],
+    pub movement_speed: Option<f32>,
+    pub rotation_speed: Option<f32>,
+    pub speed_limits: SpeedLimits,
+    pub rapid_speed: f32,
+    pub default_feed_rate: f32,
+    pub machine_time: f32,
+    pub removed_volume: f32,
+    pub lenient: bool,
+    pub fixtures: Vec<FixtureState>,
+    pub report: Vec<ProblemState>,
+}
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+    #[error("checkpoint error: {0}")]
+    Json(serde_json::Error),
+    #[error("stock has {actual} samples, checkpoint declares {expected}")]
+    SampleCount { expected: usize, actual: usize },
+    #[error("current instruction {0} outside of the program")]
+    InstructionOutOfRange(usize),
+    #[error("invalid mill state: {0}")]
+    Mill(MillingErrorKind),
+}
+
+/// Stock which can be saved in a checkpoint
+pub trait CheckpointStock: Stock + Sized {
+    fn state(&self) -> StockState;
+    fn from_state(checkpoint: &Checkpoint) -> Result<Self, CheckpointError>;
+}
+
+impl Checkpoint {
+    fn validate_stock(&self) -> Result<(), CheckpointError> {
+        let expected = self.sampling_x * self.sampling_y;
+        let actual = match &self.stock {
+            StockState::Heightmap { heights } => heights.len(),
+            StockState::MultiDexel { dexels } => dexels.len(),
+        };
+
+        if actual != expected {
+            return Err(CheckpointError::SampleCount { expected, actual });
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&Fixture> for FixtureState {
+    fn from(fixture: &Fixture) -> Self {
+        Self {
+            name: fixture.name.clone(),
+            position: fixture.position.into(),
+            shape: match fixture.shape {
+                FixtureShape::Box { size } => FixtureShapeState::Box { size: size.into() },
+                FixtureShape::Cylinder { radius, height } => {
+                    FixtureShapeState::Cylinder { radius, height }
+                }
+            },
+        }
+    }
+}
+
+impl From<FixtureState> for Fixture {
+    fn from(state: FixtureState) -> Self {
+        Self {
+            name: state.name,
+            position: Vector3::from(state.position),
+            shape: match state.shape {
+                FixtureShapeState::Box { size } => FixtureShape::Box {
+                    size: Vector3::from(size),
+                },
+                FixtureShapeState::Cylinder { radius, height } => {
+                    FixtureShape::Cylinder { radius, height }
+                }
+            },
+        }
+    }
+}
+
+impl From<&MillingError> for ProblemState {
+    fn from(error: &MillingError) -> Self {
+        Self {
+            kind: error.kind.clone(),
+            instruction_idx: error.instruction_idx,
+            source_line: error.source_line,
+            position: error.position.into(),
+        }
+    }
+}
+
+impl From<ProblemState> for MillingError {
+    fn from(state: ProblemState) -> Self {
+        Self {
+            kind: state.kind,
+            instruction_idx: state.instruction_idx,
+            source_line: state.source_line,
+            position: Vector3::from(state.position),
+        }
+    }
+}
+
+impl CheckpointStock for Block {
+    fn state(&self) -> StockState {
+        StockState::Heightmap {
+            heights: self.raw_heights().clone(),
+        }
+    }
+
+    fn from_state(checkpoint: &Checkpoint) -> Result<Self, CheckpointError> {
+        Ok(StockModel::from_state(checkpoint)?.heightmap().into_owned())
+    }
+}
+
+impl CheckpointStock for DexelBlock {
+    fn state(&self) -> StockState {
+        StockState::MultiDexel {
+            dexels: self.raw_dexels().to_vec(),
+        }
+    }
+
+    fn from_state(checkpoint: &Checkpoint) -> Result<Self, CheckpointError> {
+        Ok(StockModel::from_state(checkpoint)?.into_multi_dexel())
+    }
+}
+
+impl CheckpointStock for StockModel {
+    fn state(&self) -> StockState {
+        match self {
+            StockModel::Heightmap(block) => block.state(),
+            StockModel::MultiDexel(dexel) => dexel.state(),
+        }
+    }
+
+    fn from_state(checkpoint: &Checkpoint) -> Result<Self, CheckpointError> {
+        checkpoint.validate_stock()?;
+        let sampling = vector![checkpoint.sampling_x, checkpoint.sampling_y];
+        let size = vector![checkpoint.size_x, checkpoint.size_y, checkpoint.size_z];
+
+        Ok(match &checkpoint.stock {
+            StockState::Heightmap { heights } => {
+                Block::from_heights(sampling, size, heights.clone(), checkpoint.base_height).into()
+            }
+            StockState::MultiDexel { dexels } => {
+                DexelBlock::from_dexels(sampling, size, dexels.clone(), checkpoint.base_height)
+                    .into()
+            }
+        })
+    }
+}
+
+pub fn save(checkpoint: &Checkpoint, path: &Path) -> Result<(), CheckpointError> {
+    let json = serde_json::to_string(checkpoint).map_err(CheckpointError::Json)?;
+    std::fs::write(path, json).map_err(CheckpointError::Io)
+}
+
+pub fn load(path: &Path) -> Result<Checkpoint, CheckpointError> {
+    let json = std::fs::read_to_string(path).map_err(CheckpointError::Io)?;
+    serde_json::from_str(&json).map_err(CheckpointError::Json)
+}
@@ -0,0 +1,58 @@
+use std::time::Instant;
+
+/// Source of time for the milling playback
+pub trait Clock {
+    /// Seconds elapsed since the previous tick or reset
+    fn tick(&mut self) -> f32;
+    fn reset(&mut self);
+}
+
+pub struct SystemClock {
+    last_tick: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            last_tick: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn tick(&mut self) -> f32 {
+        let now = Instant::now();
+        let delta = (now - self.last_tick).as_secs_f32();
+        self.last_tick = now;
+        delta
+    }
+
+    fn reset(&mut self) {
+        self.last_tick = Instant::now();
+    }
+}
+
+/// Clock advancing by the same amount of time on every tick, for deterministic simulations
+pub struct FixedStepClock {
+    pub step: f32,
+}
+
+impl FixedStepClock {
+    pub fn new(step: f32) -> Self {
+        Self { step }
+    }
+}
+
+impl Clock for FixedStepClock {
+    fn tick(&mut self) -> f32 {
+        self.step
+    }
+
+    fn reset(&mut self) {}
+}
@@ -0,0 +1,399 @@
+use super::{
+    block::{Block, DirtyRect},
+    stock::Stock,
+};
+use crate::render::generic_mesh::{CNCBlockVertex, Mesh, Triangle};
+use nalgebra::{point, vector, Point3, Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// Interval of material along the Z axis
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Dexel {
+    pub bottom: f32,
+    pub top: f32,
+}
+
+impl Dexel {
+    pub fn new(bottom: f32, top: f32) -> Self {
+        Self { bottom, top }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.top - self.bottom
+    }
+}
+
+/// Stock storing a sorted list of disjoint material intervals for every XY cell, which makes it
+/// possible to represent overhangs and parts flipped for a second setup
+#[derive(Clone)]
+pub struct DexelBlock {
+    sampling: Vector2<usize>,
+    sample_size: Vector2<f32>,
+    dexels: Vec<Vec<Dexel>>,
+    height: f32,
+    size: Vector2<f32>,
+    pub base_height: f32,
+    // Columns changed since the last `take_dirty_rect`
+    dirty_rect: Option<DirtyRect>,
+}
+
+impl DexelBlock {
+    // Vertices outside of the height texture get no height offset in the CNC block shader
+    const NO_TEXTURE: f32 = -1.0;
+
+    pub fn new(sampling: Vector2<usize>, size: Vector3<f32>) -> Self {
+        Self {
+            sample_size: vector![size.x / sampling.x as f32, size.y / sampling.y as f32],
+            dexels: vec![vec![Dexel::new(0.0, size.z)]; sampling.x * sampling.y],
+            sampling,
+            height: size.z,
+            size: vector![size.x, size.y],
+            base_height: size.z / 10.0,
+            dirty_rect: None,
+        }
+    }
+
+    /// Round bar lying along the X axis with the largest diameter fitting in the block
+    pub fn bar(sampling: Vector2<usize>, size: Vector3<f32>) -> Self {
+        let mut bar = Self::new(sampling, size);
+        let radius = 0.5 * f32::min(size.y, size.z);
+
+        for y in 0..sampling.y {
+            let offset = (y as f32 + 0.5) * bar.sample_size.y - 0.5 * size.y;
+            let half_chord = (radius * radius - offset * offset).max(0.0).sqrt();
+            let column = if half_chord > 0.0 {
+                vec![Dexel::new(radius - half_chord, radius + half_chord)]
+            } else {
+                Vec::new()
+            };
+
+            for x in 0..sampling.x {
+                let idx = bar.dexels_idx(x, y);
+                bar.dexels[idx] = column.clone();
+            }
+        }
+
+        bar
+    }
+
+    /// Creates the block from dexel columns stored row by row
+    pub fn from_dexels(
+        sampling: Vector2<usize>,
+        size: Vector3<f32>,
+        dexels: Vec<Vec<Dexel>>,
+        base_height: f32,
+    ) -> Self {
+        assert_eq!(dexels.len(), sampling.x * sampling.y);
+
+        Self {
+            sample_size: vector![size.x / sampling.x as f32, size.y / sampling.y as f32],
+            dexels,
+            sampling,
+            height: size.z,
+            size: vector![size.x, size.y],
+            base_height,
+            dirty_rect: None,
+        }
+    }
+
+    pub fn from_block(block: &Block) -> Self {
+        let sampling = *block.sampling();
+        let mut dexels = Vec::with_capacity(sampling.x * sampling.y);
+
+        for y in 0..sampling.y {
+            for x in 0..sampling.x {
+                let height = block.height(x, y);
+                dexels.push(if height > 0.0 {
+                    vec![Dexel::new(0.0, height)]
+                } else {
+                    Vec::new()
+                });
+            }
+        }
+
+        Self {
+            sampling,
+            sample_size: *block.sample_size(),
+            dexels,
+            height: block.block_height(),
+            size: *block.size(),
+            base_height: block.base_height,
+            dirty_rect: None,
+        }
+    }
+
+    /// Heightmap of the topmost material in every column
+    pub fn to_block(&self) -> Block {
+        let heights = (0..self.sampling.y)
+            .flat_map(|y| (0..self.sampling.x).map(move |x| (x, y)))
+            .map(|(x, y)| self.top_height(x, y))
+            .collect();
+
+        Block::from_heights(
+            self.sampling,
+            vector![self.size.x, self.size.y, self.height],
+            heights,
+            self.base_height,
+        )
+    }
+
+    fn dexels_idx(&self, x: usize, y: usize) -> usize {
+        x + y * self.sampling.x
+    }
+
+    /// Dexel columns stored row by row
+    pub fn raw_dexels(&self) -> &[Vec<Dexel>] {
+        &self.dexels
+    }
+
+    pub fn dexels(&self, x: usize, y: usize) -> &[Dexel] {
+        &self.dexels[self.dexels_idx(x, y)]
+    }
+
+    fn mark_dirty(&mut self, x: usize, y: usize) {
+        let rect = self.dirty_rect.get_or_insert(DirtyRect {
+            min: vector![x, y],
+            max: vector![x + 1, y + 1],
+        });
+
+        rect.min = rect.min.inf(&vector![x, y]);
+        rect.max = rect.max.sup(&vector![x + 1, y + 1]);
+    }
+
+    /// Returns the columns changed since the previous call and starts tracking anew
+    pub fn take_dirty_rect(&mut self) -> Option<DirtyRect> {
+        self.dirty_rect.take()
+    }
+
+    /// Dexel columns of the rectangle stored row by row
+    pub fn region(&self, rect: &DirtyRect) -> Vec<Vec<Dexel>> {
+        (rect.min.y..rect.max.y)
+            .flat_map(|y| (rect.min.x..rect.max.x).map(move |x| (x, y)))
+            .map(|(x, y)| self.dexels(x, y).to_vec())
+            .collect()
+    }
+
+    /// Replaces the dexel columns of the rectangle with the ones returned by `region`
+    pub fn set_region(&mut self, rect: &DirtyRect, columns: Vec<Vec<Dexel>>) {
+        assert_eq!(columns.len(), rect.size().x * rect.size().y);
+
+        let positions =
+            (rect.min.y..rect.max.y).flat_map(|y| (rect.min.x..rect.max.x).map(move |x| (x, y)));
+        for ((x, y), column) in positions.zip(columns) {
+            let idx = self.dexels_idx(x, y);
+            self.dexels[idx] = column;
+        }
+    }
+
+    pub fn top_height(&self, x: usize, y: usize) -> f32 {
+        self.dexels(x, y).last().map_or(0.0, |d| d.top)
+    }
+
+    /// Turns the stock upside down by rotating it by 180 degrees around the X axis going through
+    /// the middle of the block
+    pub fn flip(&mut self) {
+        let mut flipped = Vec::with_capacity(self.dexels.len());
+
+        for y in 0..self.sampling.y {
+            for x in 0..self.sampling.x {
+                flipped.push(
+                    self.dexels(x, self.sampling.y - 1 - y)
+                        .iter()
+                        .rev()
+                        .map(|d| Dexel::new(self.height - d.top, self.height - d.bottom))
+                        .collect(),
+                );
+            }
+        }
+
+        self.dexels = flipped;
+        self.dirty_rect = Some(DirtyRect {
+            min: vector![0, 0],
+            max: self.sampling,
+        });
+    }
+
+    /// Removes the interval from the dexels, returns the removed length
+    fn subtract(dexels: &mut Vec<Dexel>, bottom: f32, top: f32) -> f32 {
+        let mut removed = 0.0;
+        let mut result = Vec::with_capacity(dexels.len() + 1);
+
+        for &dexel in dexels.iter() {
+            if dexel.top <= bottom || dexel.bottom >= top {
+                result.push(dexel);
+                continue;
+            }
+
+            removed += dexel.top.min(top) - dexel.bottom.max(bottom);
+
+            if dexel.bottom < bottom {
+                result.push(Dexel::new(dexel.bottom, bottom));
+            }
+
+            if dexel.top > top {
+                result.push(Dexel::new(top, dexel.top));
+            }
+        }
+
+        *dexels = result;
+        removed
+    }
+
+    /// Parts of `dexel` not covered by any of `others`
+    fn uncovered(dexel: Dexel, others: &[Dexel]) -> Vec<Dexel> {
+        let mut parts = vec![dexel];
+        for other in others {
+            Self::subtract(&mut parts, other.bottom, other.top);
+        }
+
+        parts
+    }
+
+    fn neighbor_dexels(&self, x: usize, y: usize, offset: Vector2<i32>) -> &[Dexel] {
+        let nx = x as i32 + offset.x;
+        let ny = y as i32 + offset.y;
+
+        if nx < 0 || ny < 0 || nx >= self.sampling.x as i32 || ny >= self.sampling.y as i32 {
+            &[]
+        } else {
+            self.dexels(nx as usize, ny as usize)
+        }
+    }
+
+    fn push_quad(
+        vertices: &mut Vec<CNCBlockVertex>,
+        triangles: &mut Vec<Triangle>,
+        corners: [Point3<f32>; 4],
+        normal: Vector3<f32>,
+    ) {
+        let offset = vertices.len() as u32;
+        vertices.extend(
+            corners
+                .into_iter()
+                .map(|c| CNCBlockVertex::new(c, normal, Self::NO_TEXTURE, Self::NO_TEXTURE)),
+        );
+
+        triangles.push(Triangle([offset, offset + 1, offset + 2]));
+        triangles.push(Triangle([offset + 3, offset + 2, offset + 1]));
+    }
+
+    fn mesh_cell(
+        &self,
+        vertices: &mut Vec<CNCBlockVertex>,
+        triangles: &mut Vec<Triangle>,
+        x: usize,
+        y: usize,
+    ) {
+        let sx = self.sample_size.x;
+        let sy = self.sample_size.y;
+        let base = point![x as f32 * sx, y as f32 * sy, 0.0];
+
+        // Corners in the XY plane
+        //
+        // 2   3
+        //
+        // 0   1
+        let c = [
+            base,
+            base + vector![sx, 0.0, 0.0],
+            base + vector![0.0, sy, 0.0],
+            base + vector![sx, sy, 0.0],
+        ];
+        let at = |p: Point3<f32>, z: f32| p + vector![0.0, 0.0, z];
+
+        // Side corner indices into `c` and outward normals
+        let sides = [
+            ([0, 1], vector![0, -1], vector![0.0, -1.0, 0.0]),
+            ([3, 2], vector![0, 1], vector![0.0, 1.0, 0.0]),
+            ([2, 0], vector![-1, 0], vector![-1.0, 0.0, 0.0]),
+            ([1, 3], vector![1, 0], vector![1.0, 0.0, 0.0]),
+        ];
+
+        for &dexel in self.dexels(x, y) {
+            Self::push_quad(
+                vertices,
+                triangles,
+                c.map(|p| at(p, dexel.top)),
+                vector![0.0, 0.0, 1.0],
+            );
+            Self::push_quad(
+                vertices,
+                triangles,
+                [c[0], c[2], c[1], c[3]].map(|p| at(p, dexel.bottom)),
+                vector![0.0, 0.0, -1.0],
+            );
+
+            for ([a, b], offset, normal) in sides {
+                for part in Self::uncovered(dexel, self.neighbor_dexels(x, y, offset)) {
+                    Self::push_quad(
+                        vertices,
+                        triangles,
+                        [
+                            at(c[a], part.bottom),
+                            at(c[b], part.bottom),
+                            at(c[a], part.top),
+                            at(c[b], part.top),
+                        ],
+                        normal,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Stock for DexelBlock {
+    fn sampling(&self) -> &Vector2<usize> {
+        &self.sampling
+    }
+
+    fn sample_size(&self) -> &Vector2<f32> {
+        &self.sample_size
+    }
+
+    fn size(&self) -> &Vector2<f32> {
+        &self.size
+    }
+
+    fn block_height(&self) -> f32 {
+        self.height
+    }
+
+    fn base_height(&self) -> f32 {
+        self.base_height
+    }
+
+    fn base_height_mut(&mut self) -> &mut f32 {
+        &mut self.base_height
+    }
+
+    fn material_above(&self, x: usize, y: usize, height: f32) -> bool {
+        self.top_height(x, y) > height
+    }
+
+    fn remove(&mut self, x: usize, y: usize, bottom: f32, top: f32) -> f32 {
+        let idx = self.dexels_idx(x, y);
+        let removed = Self::subtract(&mut self.dexels[idx], bottom, top);
+        if removed > 0.0 {
+            self.mark_dirty(x, y);
+        }
+
+        removed
+    }
+
+    fn generate_mesh(&self) -> Mesh<CNCBlockVertex> {
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+
+        for y in 0..self.sampling.y {
+            for x in 0..self.sampling.x {
+                self.mesh_cell(&mut vertices, &mut triangles, x, y);
+            }
+        }
+
+        Mesh {
+            vertices,
+            triangles,
+        }
+    }
+}
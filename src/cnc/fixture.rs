@@ -0,0 +1,114 @@
+use super::mill::{Cutter, CutterShape, Holder};
+use nalgebra::{vector, Vector2, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FixtureShape {
+    /// Axis aligned box
+    Box { size: Vector3<f32> },
+    /// Cylinder with a vertical axis
+    Cylinder { radius: f32, height: f32 },
+}
+
+/// Vise, clamp or spoil board which the mill must never enter. `position` is the center of the
+/// bottom face in mill coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixture {
+    pub name: String,
+    pub position: Vector3<f32>,
+    pub shape: FixtureShape,
+}
+
+impl Fixture {
+    pub fn new_box(name: &str, position: Vector3<f32>, size: Vector3<f32>) -> Self {
+        Self {
+            name: String::from(name),
+            position,
+            shape: FixtureShape::Box { size },
+        }
+    }
+
+    pub fn new_cylinder(name: &str, position: Vector3<f32>, radius: f32, height: f32) -> Self {
+        Self {
+            name: String::from(name),
+            position,
+            shape: FixtureShape::Cylinder { radius, height },
+        }
+    }
+
+    pub fn height(&self) -> f32 {
+        match self.shape {
+            FixtureShape::Box { size } => size.z,
+            FixtureShape::Cylinder { height, .. } => height,
+        }
+    }
+
+    /// Distance from `point` to the fixture projected onto the XY plane, 0 inside of it
+    fn xy_distance(&self, point: &Vector2<f32>) -> f32 {
+        let offset = point - self.position.xy();
+
+        match self.shape {
+            FixtureShape::Box { size } => {
+                let outside = vector![
+                    (offset.x.abs() - 0.5 * size.x).max(0.0),
+                    (offset.y.abs() - 0.5 * size.y).max(0.0)
+                ];
+                outside.norm()
+            }
+            FixtureShape::Cylinder { radius, .. } => (offset.norm() - radius).max(0.0),
+        }
+    }
+
+    fn z_distance(&self, z: f32) -> f32 {
+        (self.position.z - z)
+            .max(z - self.position.z - self.height())
+            .max(0.0)
+    }
+
+    fn collides_with_vertical_cylinder(
+        &self,
+        center: &Vector2<f32>,
+        radius: f32,
+        bottom: f32,
+        top: f32,
+    ) -> bool {
+        bottom < self.position.z + self.height()
+            && top > self.position.z
+            && self.xy_distance(center) < radius
+    }
+
+    fn collides_with_sphere(&self, center: &Vector3<f32>, radius: f32) -> bool {
+        let xy = self.xy_distance(&center.xy());
+        let z = self.z_distance(center.z);
+        xy * xy + z * z < radius * radius
+    }
+
+    /// Whether the cutter with its tip at `position` or its holder intersects the fixture
+    pub fn collides(&self, position: &Vector3<f32>, cutter: &Cutter, holder: &Holder) -> bool {
+        let radius = 0.5 * cutter.diameter;
+        let cutter_top = position.z + cutter.height;
+
+        let cutter_collision = match cutter.shape {
+            CutterShape::Ball => {
+                self.collides_with_sphere(&(position + vector![0.0, 0.0, radius]), radius)
+                    || self.collides_with_vertical_cylinder(
+                        &position.xy(),
+                        radius,
+                        position.z + radius,
+                        cutter_top,
+                    )
+            }
+            // The rounded corner is bounded by the cylinder of the whole cutter
+            CutterShape::Cylinder | CutterShape::BullNose { .. } => {
+                self.collides_with_vertical_cylinder(&position.xy(), radius, position.z, cutter_top)
+            }
+        };
+
+        cutter_collision
+            || self.collides_with_vertical_cylinder(
+                &position.xy(),
+                0.5 * holder.diameter,
+                cutter_top,
+                cutter_top + holder.length,
+            )
+    }
+}
@@ -0,0 +1,261 @@
+use super::block::Block;
+use nalgebra::vector;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HeightmapFormat {
+    Png16,
+    RawF32,
+}
+
+impl HeightmapFormat {
+    pub fn from_path(path: &Path) -> Result<Self, HeightmapError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => Ok(Self::Png16),
+            Some("raw") | Some("f32") => Ok(Self::RawF32),
+            Some(_) => Err(HeightmapError::InvalidExtension),
+            None => Err(HeightmapError::NoExtension),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HeightmapMetadata {
+    pub format: HeightmapFormat,
+    pub sampling_x: usize,
+    pub sampling_y: usize,
+    pub size_x: f32,
+    pub size_y: f32,
+    pub size_z: f32,
+    pub base_height: f32,
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+#[derive(Error, Debug)]
+pub enum HeightmapError {
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+    #[error("image error: {0}")]
+    Image(image::ImageError),
+    #[error("metadata error: {0}")]
+    Metadata(serde_json::Error),
+    #[error("file without extension")]
+    NoExtension,
+    #[error("invalid extension")]
+    InvalidExtension,
+    #[error("heightmap format does not match its metadata")]
+    FormatMismatch,
+    #[error("heightmap has {actual} samples, metadata declares {expected}")]
+    SampleCount { expected: usize, actual: usize },
+    #[error("raw heightmap has {actual} bytes, metadata declares {expected}")]
+    ByteCount { expected: usize, actual: usize },
+}
+
+pub fn metadata_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+pub fn save(block: &Block, path: &Path) -> Result<(), HeightmapError> {
+    let format = HeightmapFormat::from_path(path)?;
+    let heights = block.raw_heights();
+    let (min_height, max_height) = heights
+        .iter()
+        .fold((f32::INFINITY, -f32::INFINITY), |(min, max), &h| {
+            (min.min(h), max.max(h))
+        });
+
+    let metadata = HeightmapMetadata {
+        format,
+        sampling_x: block.sampling().x,
+        sampling_y: block.sampling().y,
+        size_x: block.size().x,
+        size_y: block.size().y,
+        size_z: block.block_height(),
+        base_height: block.base_height,
+        min_height,
+        max_height,
+    };
+
+    match format {
+        HeightmapFormat::Png16 => save_png(heights, &metadata, path)?,
+        HeightmapFormat::RawF32 => save_raw(heights, path)?,
+    }
+
+    let metadata = serde_json::to_string_pretty(&metadata).map_err(HeightmapError::Metadata)?;
+    std::fs::write(metadata_path(path), metadata).map_err(HeightmapError::Io)
+}
+
+pub fn load(path: &Path) -> Result<Block, HeightmapError> {
+    let format = HeightmapFormat::from_path(path)?;
+    let metadata = std::fs::read_to_string(metadata_path(path)).map_err(HeightmapError::Io)?;
+    let metadata: HeightmapMetadata =
+        serde_json::from_str(&metadata).map_err(HeightmapError::Metadata)?;
+
+    if metadata.format != format {
+        return Err(HeightmapError::FormatMismatch);
+    }
+
+    let heights = match format {
+        HeightmapFormat::Png16 => load_png(&metadata, path)?,
+        HeightmapFormat::RawF32 => load_raw(&metadata, path)?,
+    };
+
+    let expected = metadata.sampling_x * metadata.sampling_y;
+    if heights.len() != expected {
+        return Err(HeightmapError::SampleCount {
+            expected,
+            actual: heights.len(),
+        });
+    }
+
+    Ok(Block::from_heights(
+        vector![metadata.sampling_x, metadata.sampling_y],
+        vector![metadata.size_x, metadata.size_y, metadata.size_z],
+        heights,
+        metadata.base_height,
+    ))
+}
+
+fn save_png(
+    heights: &[f32],
+    metadata: &HeightmapMetadata,
+    path: &Path,
+) -> Result<(), HeightmapError> {
+    let range = metadata.max_height - metadata.min_height;
+    let pixels = heights
+        .iter()
+        .map(|h| {
+            if range > 0.0 {
+                ((h - metadata.min_height) / range * u16::MAX as f32).round() as u16
+            } else {
+                0
+            }
+        })
+        .collect();
+
+    let image = image::ImageBuffer::<image::Luma<u16>, Vec<u16>>::from_raw(
+        metadata.sampling_x as u32,
+        metadata.sampling_y as u32,
+        pixels,
+    )
+    .unwrap();
+
+    image.save(path).map_err(HeightmapError::Image)
+}
+
+fn load_png(metadata: &HeightmapMetadata, path: &Path) -> Result<Vec<f32>, HeightmapError> {
+    let image = image::open(path)
+        .map_err(HeightmapError::Image)?
+        .into_luma16();
+    if image.width() as usize != metadata.sampling_x
+        || image.height() as usize != metadata.sampling_y
+    {
+        return Err(HeightmapError::SampleCount {
+            expected: metadata.sampling_x * metadata.sampling_y,
+            actual: (image.width() * image.height()) as usize,
+        });
+    }
+
+    let range = metadata.max_height - metadata.min_height;
+    Ok(image
+        .into_raw()
+        .into_iter()
+        .map(|p| metadata.min_height + p as f32 / u16::MAX as f32 * range)
+        .collect())
+}
+
+fn save_raw(heights: &[f32], path: &Path) -> Result<(), HeightmapError> {
+    let bytes: Vec<u8> = heights.iter().flat_map(|h| h.to_le_bytes()).collect();
+    std::fs::write(path, bytes).map_err(HeightmapError::Io)
+}
+
+fn load_raw(metadata: &HeightmapMetadata, path: &Path) -> Result<Vec<f32>, HeightmapError> {
+    let bytes = std::fs::read(path).map_err(HeightmapError::Io)?;
+    let expected = 4 * metadata.sampling_x * metadata.sampling_y;
+    if bytes.len() != expected {
+        return Err(HeightmapError::ByteCount {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sloped_block() -> Block {
+        let sampling = vector![4, 3];
+        let heights = (0..12).map(|i| 10.0 + 0.5 * i as f32).collect();
+        Block::from_heights(sampling, vector![40.0, 30.0, 20.0], heights, 2.0)
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("heightmap-{}-{name}", std::process::id()))
+    }
+
+    fn round_trip(name: &str) -> Block {
+        let path = temp_path(name);
+        save(&sloped_block(), &path).unwrap();
+        let block = load(&path).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(metadata_path(&path)).unwrap();
+        block
+    }
+
+    fn assert_same_block(block: &Block, tolerance: f32) {
+        let original = sloped_block();
+        assert_eq!(block.sampling(), original.sampling());
+        assert_eq!(block.size(), original.size());
+        assert_eq!(block.block_height(), original.block_height());
+        assert_eq!(block.base_height, original.base_height);
+
+        for (loaded, saved) in block.raw_heights().iter().zip(original.raw_heights()) {
+            assert!((loaded - saved).abs() <= tolerance, "{loaded} != {saved}");
+        }
+    }
+
+    #[test]
+    fn raw_heightmap_round_trips_exactly() {
+        assert_same_block(&round_trip("exact.raw"), 0.0);
+    }
+
+    #[test]
+    fn png_heightmap_round_trips_within_quantization() {
+        assert_same_block(&round_trip("quantized.png"), 5.5 / u16::MAX as f32);
+    }
+
+    #[test]
+    fn truncated_raw_heightmap_is_rejected() {
+        let path = temp_path("truncated.raw");
+        save(&sloped_block(), &path).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.pop();
+        std::fs::write(&path, bytes).unwrap();
+        let loaded = load(&path);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(metadata_path(&path)).unwrap();
+        assert!(matches!(
+            loaded,
+            Err(HeightmapError::ByteCount {
+                expected: 48,
+                actual: 47
+            })
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+use super::{
+    milling_process::MillingError,
+    program::{Program, ProgramLoadError},
+};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// Finished, with the problems collected in the lenient mode
+    Finished {
+        machine_time: f32,
+        problems: Vec<MillingError>,
+    },
+    Failed(MillingError),
+}
+
+/// Program milled as a single step of a sequence, with the cutter given by the file extension
+pub struct Job {
+    pub path: PathBuf,
+    pub program: Program,
+    pub status: JobStatus,
+}
+
+/// Sequence of programs run one after another on the same stock
+#[derive(Default)]
+pub struct JobQueue {
+    jobs: Vec<Job>,
+    current: Option<usize>,
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, path: &Path) -> Result<(), ProgramLoadError> {
+        let program = Program::from_file(path, true)?;
+        self.jobs.push(Job {
+            path: path.to_owned(),
+            program,
+            status: JobStatus::Queued,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, idx: usize) {
+        if self.current.is_none() {
+            self.jobs.remove(idx);
+        }
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn running(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Queues all jobs again
+    pub fn reset(&mut self) {
+        self.current = None;
+        for job in &mut self.jobs {
+            job.status = JobStatus::Queued;
+        }
+    }
+
+    /// Marks the next queued job as running and returns its program, `None` when there are no
+    /// jobs left
+    pub fn start_next(&mut self) -> Option<Program> {
+        let idx = self
+            .jobs
+            .iter()
+            .position(|job| matches!(job.status, JobStatus::Queued));
+        self.current = idx;
+
+        let job = &mut self.jobs[idx?];
+        job.status = JobStatus::Running;
+        Some(job.program.clone())
+    }
+
+    pub fn finish_current(&mut self, machine_time: f32, problems: Vec<MillingError>) {
+        if let Some(idx) = self.current.take() {
+            self.jobs[idx].status = JobStatus::Finished {
+                machine_time,
+                problems,
+            };
+        }
+    }
+
+    /// Stops the queue, queueing the running job again
+    pub fn stop(&mut self) {
+        if let Some(idx) = self.current.take() {
+            self.jobs[idx].status = JobStatus::Queued;
+        }
+    }
+
+    /// Marks the running job as failed and stops the queue
+    pub fn fail_current(&mut self, error: MillingError) {
+        if let Some(idx) = self.current.take() {
+            self.jobs[idx].status = JobStatus::Failed(error);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use super::{
+    machine_profile::{Axis, MachineProfile},
+    milling_process::MillInstruction,
+    program::Program,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ViolationKind {
+    #[error("{axis} coordinate {value} outside of the machine travel")]
+    Travel { axis: char, value: f32 },
+    #[error("feed rate {0} not in the machine range")]
+    FeedRate(f32),
+    #[error("spindle speed {0} not in the machine range")]
+    SpindleSpeed(f32),
+    #[error("plunging with feed rate {0} exceeding the machine limit")]
+    PlungeRate(f32),
+    #[error("tool length {0} not in the machine range")]
+    ToolLength(f32),
+}
+
+/// Program instruction breaking the limits of a machine
+#[derive(Error, Debug, Clone)]
+#[error(
+    "{}{kind}",
+    .source_line.map(|l| format!("line {l}: ")).unwrap_or_default()
+)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    /// `None` for problems with the whole program
+    pub instruction_idx: Option<usize>,
+    pub source_line: Option<usize>,
+}
+
+/// Checks the program against the machine limits without simulating it
+pub fn lint(program: &Program, profile: &MachineProfile) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut report = |kind, instruction_idx: Option<usize>| {
+        violations.push(Violation {
+            kind,
+            instruction_idx,
+            source_line: instruction_idx.and_then(|idx| program.source_line(idx)),
+        })
+    };
+
+    let tool_length = program.shape().height;
+    if !(profile.min_tool_length..=profile.max_tool_length).contains(&tool_length) {
+        report(ViolationKind::ToolLength(tool_length), None);
+    }
+
+    let mut feed_rate = None;
+    let mut z = None;
+
+    for (idx, instruction) in program.instructions().iter().enumerate() {
+        match instruction {
+            MillInstruction::MovementSpeed(speed) => {
+                if !(profile.min_feed_rate..=profile.max_feed_rate).contains(speed) {
+                    report(ViolationKind::FeedRate(*speed), Some(idx));
+                }
+
+                feed_rate = Some(*speed);
+            }
+            MillInstruction::RotationSpeed(speed) => {
+                if !(profile.min_spindle_speed..=profile.max_spindle_speed).contains(speed) {
+                    report(ViolationKind::SpindleSpeed(*speed), Some(idx));
+                }
+            }
+            MillInstruction::MoveFast(location) | MillInstruction::MoveSlow(location) => {
+                let coordinates = location.coordinates();
+
+                for (value, axis) in coordinates.iter().zip(Axis::ALL) {
+                    if let Some(value) = value {
+                        if !profile.travel_range(axis).contains(value) {
+                            report(
+                                ViolationKind::Travel {
+                                    axis: axis.name(),
+                                    value: *value,
+                                },
+                                Some(idx),
+                            );
+                        }
+                    }
+                }
+
+                let plunge = matches!(instruction, MillInstruction::MoveSlow(_))
+                    && matches!((z, coordinates[2]), (Some(from), Some(to)) if to < from);
+                if let (true, Some(feed_rate)) = (plunge, feed_rate) {
+                    if feed_rate > profile.max_plunge_rate {
+                        report(ViolationKind::PlungeRate(feed_rate), Some(idx));
+                    }
+                }
+
+                z = coordinates[2].or(z);
+            }
+        }
+    }
+
+    violations
+}
@@ -0,0 +1,69 @@
+use super::{
+    mill::Cutter,
+    milling_process::{MillInstruction, MillingError},
+};
+use nalgebra::Vector3;
+use std::{cell::RefCell, rc::Rc};
+
+/// Receives events from a `MillingProcess`. All methods do nothing by default.
+pub trait MillingListener {
+    fn instruction_started(&mut self, _idx: usize, _instruction: &MillInstruction) {}
+    fn instruction_finished(&mut self, _idx: usize) {}
+    fn tool_changed(&mut self, _cutter: &Cutter) {}
+    /// Called after every cut with the volume of the removed material in mm^3
+    fn cut(&mut self, _position: &Vector3<f32>, _removed_volume: f32) {}
+    fn error(&mut self, _error: &MillingError) {}
+}
+
+/// Makes it possible to read the listener state while it is attached to a process
+impl<L: MillingListener> MillingListener for Rc<RefCell<L>> {
+    fn instruction_started(&mut self, idx: usize, instruction: &MillInstruction) {
+        self.borrow_mut().instruction_started(idx, instruction);
+    }
+
+    fn instruction_finished(&mut self, idx: usize) {
+        self.borrow_mut().instruction_finished(idx);
+    }
+
+    fn tool_changed(&mut self, cutter: &Cutter) {
+        self.borrow_mut().tool_changed(cutter);
+    }
+
+    fn cut(&mut self, position: &Vector3<f32>, removed_volume: f32) {
+        self.borrow_mut().cut(position, removed_volume);
+    }
+
+    fn error(&mut self, error: &MillingError) {
+        self.borrow_mut().error(error);
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct MillingStatistics {
+    pub instructions: usize,
+    pub tool_changes: usize,
+    pub cuts: usize,
+    pub removed_volume: f32,
+    pub errors: usize,
+}
+
+impl MillingListener for MillingStatistics {
+    fn instruction_finished(&mut self, _idx: usize) {
+        self.instructions += 1;
+    }
+
+    fn tool_changed(&mut self, _cutter: &Cutter) {
+        self.tool_changes += 1;
+    }
+
+    fn cut(&mut self, _position: &Vector3<f32>, removed_volume: f32) {
+        if removed_volume > 0.0 {
+            self.cuts += 1;
+            self.removed_volume += removed_volume;
+        }
+    }
+
+    fn error(&mut self, _error: &MillingError) {
+        self.errors += 1;
+    }
+}
@@ -1,5 +1,6 @@
 use crate::cnc::number::Number;
 use nalgebra::{vector, Vector3};
+use serde::{Deserialize, Serialize};
 
 enum Coordinate {
     X,
@@ -13,7 +14,7 @@ struct CoordinateParseResult<'a> {
     left: &'a str,
 }
 
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct Location {
     x: Option<Number>,
     y: Option<Number>,
@@ -29,6 +30,11 @@ impl Location {
         ))
     }
 
+    /// X, Y and Z coordinates, `None` for the ones left unchanged
+    pub fn coordinates(&self) -> [Option<f32>; 3] {
+        [&self.x, &self.y, &self.z].map(|c| c.as_ref().map(|n| n.to_f32()))
+    }
+
     pub fn from_f32(location: &Vector3<f32>) -> Self {
         Self {
             x: Some(Number::from_f32(location.x)),
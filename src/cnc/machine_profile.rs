@@ -0,0 +1,104 @@
+use super::mill::{Mill, SpeedLimits};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+
+/// Limits of a single milling machine. Speeds are given in the same units as in the programs
+/// after scaling by 1/1000, positions and lengths in mm.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MachineProfile {
+    pub name: String,
+    pub travel_min_x: f32,
+    pub travel_max_x: f32,
+    pub travel_min_y: f32,
+    pub travel_max_y: f32,
+    pub travel_min_z: f32,
+    pub travel_max_z: f32,
+    pub min_feed_rate: f32,
+    pub max_feed_rate: f32,
+    pub min_spindle_speed: f32,
+    pub max_spindle_speed: f32,
+    /// Highest feed rate of slow moves going down
+    pub max_plunge_rate: f32,
+    pub min_tool_length: f32,
+    pub max_tool_length: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    pub fn name(&self) -> char {
+        match self {
+            Axis::X => 'X',
+            Axis::Y => 'Y',
+            Axis::Z => 'Z',
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MachineProfileError {
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+    #[error("profile error: {0}")]
+    Json(serde_json::Error),
+}
+
+impl Default for MachineProfile {
+    fn default() -> Self {
+        Self {
+            name: String::from("Default"),
+            travel_min_x: -200.0,
+            travel_max_x: 200.0,
+            travel_min_y: -200.0,
+            travel_max_y: 200.0,
+            travel_min_z: 0.0,
+            travel_max_z: 150.0,
+            min_feed_rate: Mill::MIN_MOVEMENT_SPEED,
+            max_feed_rate: Mill::MAX_MOVEMENT_SPEED,
+            min_spindle_speed: Mill::MIN_ROTATION_SPEED,
+            max_spindle_speed: Mill::MAX_ROTATION_SPEED,
+            max_plunge_rate: Mill::MAX_MOVEMENT_SPEED,
+            min_tool_length: 0.0,
+            max_tool_length: 100.0,
+        }
+    }
+}
+
+impl MachineProfile {
+    pub fn load(path: &Path) -> Result<Self, MachineProfileError> {
+        let json = std::fs::read_to_string(path).map_err(MachineProfileError::Io)?;
+        serde_json::from_str(&json).map_err(MachineProfileError::Json)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MachineProfileError> {
+        let json = serde_json::to_string_pretty(self).map_err(MachineProfileError::Json)?;
+        std::fs::write(path, json).map_err(MachineProfileError::Io)
+    }
+
+    pub fn travel_range(&self, axis: Axis) -> std::ops::RangeInclusive<f32> {
+        match axis {
+            Axis::X => self.travel_min_x..=self.travel_max_x,
+            Axis::Y => self.travel_min_y..=self.travel_max_y,
+            Axis::Z => self.travel_min_z..=self.travel_max_z,
+        }
+    }
+
+    /// Limits checked by the simulated mill
+    pub fn speed_limits(&self) -> SpeedLimits {
+        SpeedLimits {
+            min_movement: self.min_feed_rate,
+            max_movement: self.max_feed_rate,
+            min_rotation: self.min_spindle_speed,
+            max_rotation: self.max_spindle_speed,
+        }
+    }
+}
@@ -1,34 +1,111 @@
 use super::{
-    block::Block,
-    milling_process::{MillingError, MillingResult},
+    milling_process::{MillResult, MillingErrorKind},
+    stock::Stock,
 };
 use nalgebra::{vector, Vector3};
+use serde::{Deserialize, Serialize};
 
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum CutterShape {
     #[default]
     Ball,
     Cylinder,
+    /// Flat end with the cutting edge rounded by `corner_radius`
+    BullNose {
+        corner_radius: f32,
+    },
 }
 
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Cutter {
     pub height: f32,
     pub shape: CutterShape,
     pub diameter: f32,
 }
 
+impl Cutter {
+    /// Radius of the rounded part of the cutting edge, the whole radius for ball cutters
+    pub fn corner_radius(&self) -> f32 {
+        match self.shape {
+            CutterShape::Ball => 0.5 * self.diameter,
+            CutterShape::Cylinder => 0.0,
+            CutterShape::BullNose { corner_radius } => {
+                corner_radius.clamp(0.0, 0.5 * self.diameter)
+            }
+        }
+    }
+
+    /// Height of the cutting edge above the tip at `distance` from the axis
+    pub fn profile_height(&self, distance: f32) -> f32 {
+        let corner_radius = self.corner_radius();
+        let flat_radius = 0.5 * self.diameter - corner_radius;
+
+        if distance <= flat_radius {
+            0.0
+        } else {
+            let offset = distance - flat_radius;
+            corner_radius - (corner_radius * corner_radius - offset * offset).sqrt()
+        }
+    }
+}
+
+/// Non-cutting tool holder mounted above the cutting part of the cutter
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Holder {
+    pub diameter: f32,
+    pub length: f32,
+}
+
+impl Default for Holder {
+    fn default() -> Self {
+        Self {
+            diameter: 40.0,
+            length: 60.0,
+        }
+    }
+}
+
+/// Outcome of a single cut
+pub struct Cut {
+    pub removed_volume: f32,
+    pub result: MillResult,
+}
+
+/// Speeds accepted by the machine, in the same units as in the programs
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpeedLimits {
+    pub min_movement: f32,
+    pub max_movement: f32,
+    pub min_rotation: f32,
+    pub max_rotation: f32,
+}
+
+impl Default for SpeedLimits {
+    fn default() -> Self {
+        Self {
+            min_movement: Mill::MIN_MOVEMENT_SPEED,
+            max_movement: Mill::MAX_MOVEMENT_SPEED,
+            min_rotation: Mill::MIN_ROTATION_SPEED,
+            max_rotation: Mill::MAX_ROTATION_SPEED,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Mill {
     movement_speed: Option<f32>,
     rotation_speed: Option<f32>,
     position: Vector3<f32>,
     pub cutter: Cutter,
+    pub holder: Holder,
+    pub limits: SpeedLimits,
 }
 
 impl Mill {
     pub const BALL_DOWN_ALLOWED_DOT: f32 = 1.0;
 
+    // Limits of the default machine
     pub const MIN_MOVEMENT_SPEED: f32 = 2.0;
     pub const MAX_MOVEMENT_SPEED: f32 = 60.0;
 
@@ -42,25 +119,38 @@ impl Mill {
         }
     }
 
-    pub fn set_movement_speed(&mut self, speed: f32) -> MillingResult {
-        if !(Self::MIN_MOVEMENT_SPEED..=Self::MAX_MOVEMENT_SPEED).contains(&speed) {
-            return Err(MillingError::MovementSpeed(speed));
+    pub fn set_movement_speed(&mut self, speed: f32) -> MillResult {
+        if !(self.limits.min_movement..=self.limits.max_movement).contains(&speed) {
+            return Err(MillingErrorKind::MovementSpeed(speed));
         }
 
         self.movement_speed = Some(speed);
         Ok(())
     }
 
-    pub fn set_rotation_speed(&mut self, speed: f32) -> MillingResult {
-        if !(Mill::MIN_ROTATION_SPEED..=Self::MAX_ROTATION_SPEED).contains(&speed) {
-            return Err(MillingError::RotationSpeed(speed));
+    pub fn set_rotation_speed(&mut self, speed: f32) -> MillResult {
+        if !(self.limits.min_rotation..=self.limits.max_rotation).contains(&speed) {
+            return Err(MillingErrorKind::RotationSpeed(speed));
         }
 
         self.rotation_speed = Some(speed);
         Ok(())
     }
 
-    pub fn move_to(&mut self, position: Vector3<f32>) -> MillingResult {
+    pub fn movement_speed(&self) -> Option<f32> {
+        self.movement_speed
+    }
+
+    pub fn rotation_speed(&self) -> Option<f32> {
+        self.rotation_speed
+    }
+
+    /// Movement speed set by the program converted from m/min to mm/s
+    pub fn feed_rate(&self) -> Option<f32> {
+        self.movement_speed.map(|speed| speed * 1000.0 / 60.0)
+    }
+
+    pub fn move_to(&mut self, position: Vector3<f32>) -> MillResult {
         // self.ensure_movement_and_rotation_speeds()?;
         self.position = position;
         Ok(())
@@ -73,7 +163,7 @@ impl Mill {
     //  ||||||
     //   ||||
     //
-    fn milling_points(&self, block: &Block) -> Vec<(usize, usize, f32, f32)> {
+    fn milling_points<S: Stock>(&self, block: &S) -> Vec<(usize, usize, f32, f32)> {
         let x_diameter = self.cutter.diameter;
         let x_radius = 0.5 * x_diameter;
         // 2 * is a quickfix for too sparse sampling with k16 cutter
@@ -118,14 +208,45 @@ impl Mill {
         points
     }
 
-    pub fn cut(&self, block: &mut Block, direction: &Vector3<f32>) -> MillingResult {
-        match self.cutter.shape {
-            CutterShape::Ball => self.cut_ball(block, direction),
-            CutterShape::Cylinder => self.cut_cylinder(block, direction),
+    /// Removes the material under the cutter. In the `lenient` mode the whole cut is performed
+    /// even if it is erroneous and only the first problem is reported.
+    pub fn cut<S: Stock>(&self, block: &mut S, direction: &Vector3<f32>, lenient: bool) -> Cut {
+        let mut removed_height = 0.0;
+        let result = match self.cutter.shape {
+            CutterShape::Ball | CutterShape::BullNose { .. } => {
+                self.cut_rounded(block, direction, lenient, &mut removed_height)
+            }
+            CutterShape::Cylinder => {
+                self.cut_cylinder(block, direction, lenient, &mut removed_height)
+            }
+        };
+
+        Cut {
+            removed_volume: removed_height * block.sample_size().x * block.sample_size().y,
+            result,
+        }
+    }
+
+    fn report(
+        error: MillingErrorKind,
+        first_error: &mut Option<MillingErrorKind>,
+        lenient: bool,
+    ) -> MillResult {
+        if !lenient {
+            return Err(error);
         }
+
+        first_error.get_or_insert(error);
+        Ok(())
     }
 
-    fn cut_ball(&self, block: &mut Block, _direction: &Vector3<f32>) -> MillingResult {
+    fn cut_rounded<S: Stock>(
+        &self,
+        block: &mut S,
+        _direction: &Vector3<f32>,
+        lenient: bool,
+        removed_height: &mut f32,
+    ) -> MillResult {
         // let block_position = block.mill_to_block(&self.position.xy());
 
         // if block.contains(&block_position)
@@ -135,60 +256,84 @@ impl Mill {
         //     return Err(MillingError::LowerDeadZoneCollision);
         // }
 
-        let radius = 0.5 * self.cutter.diameter;
-        let radius_sq = radius * radius;
+        let cutter_top = self.cutter.height + self.position.z;
+        let mut first_error = None;
 
         for (x_r, y_r, x, y) in self.milling_points(block) {
-            if block.height(x_r, y_r) > self.cutter.height + self.position.z {
-                return Err(MillingError::UpperDeadZoneCollision);
+            if block.material_above(x_r, y_r, cutter_top) {
+                Self::report(
+                    MillingErrorKind::UpperDeadZoneCollision,
+                    &mut first_error,
+                    lenient,
+                )?;
             }
 
-            let depth = radius + self.position.z
-                - (radius_sq
-                    - (x - self.position.x) * (x - self.position.x)
-                    - (y - self.position.y) * (y - self.position.y))
-                    .sqrt();
+            let distance = (vector![x, y] - self.position.xy()).norm();
+            let depth = self.position.z + self.cutter.profile_height(distance);
 
-            if depth < block.base_height {
-                return Err(MillingError::CutTooDeep(depth));
+            if depth < block.base_height() {
+                Self::report(
+                    MillingErrorKind::CutTooDeep(depth),
+                    &mut first_error,
+                    lenient,
+                )?;
             }
 
-            if block.height(x_r, y_r) > depth {
-                *block.height_mut(x_r, y_r) = depth;
-            }
+            *removed_height += block.remove(x_r, y_r, depth, cutter_top);
         }
 
-        Ok(())
+        first_error.map_or(Ok(()), Err)
     }
 
-    fn cut_cylinder(&self, block: &mut Block, direction: &Vector3<f32>) -> MillingResult {
+    fn cut_cylinder<S: Stock>(
+        &self,
+        block: &mut S,
+        direction: &Vector3<f32>,
+        lenient: bool,
+        removed_height: &mut f32,
+    ) -> MillResult {
+        let cutter_top = self.cutter.height + self.position.z;
+        let mut first_error = None;
+
         for (x, y, _, _) in self.milling_points(block) {
-            if block.height(x, y) > self.cutter.height + self.position.z {
-                return Err(MillingError::UpperDeadZoneCollision);
+            if block.material_above(x, y, cutter_top) {
+                Self::report(
+                    MillingErrorKind::UpperDeadZoneCollision,
+                    &mut first_error,
+                    lenient,
+                )?;
             }
 
-            if block.height(x, y) > self.position.z {
+            if block.material_above(x, y, self.position.z) {
                 if direction.z < 0.0 {
-                    return Err(MillingError::LowerDeadZoneCollision);
+                    Self::report(
+                        MillingErrorKind::LowerDeadZoneCollision,
+                        &mut first_error,
+                        lenient,
+                    )?;
                 }
 
-                if self.position.z < block.base_height {
-                    return Err(MillingError::CutTooDeep(self.position.z));
+                if self.position.z < block.base_height() {
+                    Self::report(
+                        MillingErrorKind::CutTooDeep(self.position.z),
+                        &mut first_error,
+                        lenient,
+                    )?;
                 }
 
-                *block.height_mut(x, y) = self.position.z;
+                *removed_height += block.remove(x, y, self.position.z, cutter_top);
             }
         }
 
-        Ok(())
+        first_error.map_or(Ok(()), Err)
     }
 
     #[allow(dead_code)]
-    fn ensure_movement_and_rotation_speeds(&self) -> MillingResult {
+    fn ensure_movement_and_rotation_speeds(&self) -> MillResult {
         if self.movement_speed.is_none() {
-            Err(MillingError::NoMovementSpeed)
+            Err(MillingErrorKind::NoMovementSpeed)
         } else if self.rotation_speed.is_none() {
-            Err(MillingError::NoRotationSpeed)
+            Err(MillingErrorKind::NoRotationSpeed)
         } else {
             Ok(())
         }
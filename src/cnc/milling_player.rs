@@ -1,58 +1,187 @@
-use super::milling_process::{MillingProcess, MillingResult};
-use std::time::Instant;
+use super::{
+    block::Block,
+    clock::{Clock, SystemClock},
+    milling_process::{MillingError, MillingProcess, MillingResult},
+    stock::Stock,
+};
 
-pub struct MillingPlayer {
-    milling_process: MillingProcess,
-    pub slow_speed: f32,
-    last_step: Instant,
+/// Condition on which the playback is paused
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// Before the instruction with the given index is executed
+    Instruction(usize),
+    /// Before the first instruction from the given line of the source file is executed
+    SourceLine(usize),
+    /// When the tip of the cutter drops below the given height
+    BelowZ(f32),
+    /// When the mill removes material for the first time
+    FirstCut,
+    /// When the first problem is reported in the lenient mode
+    FirstError,
 }
 
-impl MillingPlayer {
-    const DEFAULT_SLOW_SPEED: f32 = 10.0;
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Instruction(idx) => write!(f, "instruction {idx}"),
+            Breakpoint::SourceLine(line) => write!(f, "line {line}"),
+            Breakpoint::BelowZ(z) => write!(f, "Z below {z}"),
+            Breakpoint::FirstCut => write!(f, "first cut"),
+            Breakpoint::FirstError => write!(f, "first error"),
+        }
+    }
+}
+
+pub struct MillingPlayer<S: Stock = Block> {
+    milling_process: MillingProcess<S>,
+    /// How many times faster than the real machine the playback goes
+    pub speed_multiplier: f32,
+    clock: Box<dyn Clock>,
+    breakpoints: Vec<Breakpoint>,
+    // Instruction whose breakpoints have already been checked, so that the playback can be
+    // resumed after pausing before it
+    checked_instruction: Option<usize>,
+}
+
+impl<S: Stock> MillingPlayer<S> {
+    const DEFAULT_SPEED_MULTIPLIER: f32 = 1.0;
+
+    pub fn new(milling_process: MillingProcess<S>) -> Self {
+        Self::with_clock(milling_process, Box::new(SystemClock::new()))
+    }
 
-    pub fn new(milling_process: MillingProcess) -> Self {
+    pub fn with_clock(milling_process: MillingProcess<S>, clock: Box<dyn Clock>) -> Self {
         Self {
             milling_process,
-            slow_speed: Self::DEFAULT_SLOW_SPEED,
-            last_step: Instant::now(),
+            speed_multiplier: Self::DEFAULT_SPEED_MULTIPLIER,
+            clock,
+            breakpoints: Vec::new(),
+            checked_instruction: None,
         }
     }
 
+    pub fn with_breakpoints(mut self, breakpoints: Vec<Breakpoint>) -> Self {
+        self.breakpoints = breakpoints;
+        self
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Vec<Breakpoint> {
+        &mut self.breakpoints
+    }
+
     pub fn full_step(&mut self) -> MillingResult {
         self.milling_process.execute_next_instruction()
     }
 
     pub fn reset_timer(&mut self) {
-        self.last_step = Instant::now();
+        self.clock.reset();
     }
 
-    pub fn step(&mut self) -> MillingResult {
-        let now = Instant::now();
-        let delta = (now - self.last_step).as_secs_f32();
-        self.last_step = now;
-        self.milling_process
-            .execute_next_instruction_partially(delta * self.slow_speed)?;
+    /// Plays the program for the time elapsed since the previous step, returns the breakpoint at
+    /// which the playback stopped
+    pub fn step(&mut self) -> Result<Option<Breakpoint>, MillingError> {
+        let delta = self.clock.tick();
+        self.play_for(delta * self.speed_multiplier)
+    }
 
-        Ok(())
+    /// Plays the program until it ends or a breakpoint is hit
+    pub fn complete(&mut self) -> Result<Option<Breakpoint>, MillingError> {
+        self.play_for(f32::INFINITY)
     }
 
-    pub fn complete(&mut self) -> MillingResult {
-        while !self.milling_process.done() {
-            self.milling_process.execute_next_instruction()?;
+    fn play_for(&mut self, mut time: f32) -> Result<Option<Breakpoint>, MillingError> {
+        while time > 0.0 && !self.milling_process.done() {
+            let idx = self.milling_process.current_instruction_idx();
+            if self.checked_instruction != Some(idx) {
+                self.checked_instruction = Some(idx);
+
+                if let Some(breakpoint) = self.instruction_breakpoint(idx) {
+                    return Ok(Some(breakpoint));
+                }
+            }
+
+            // Progress is checked after every sample of movement only when it can hit a
+            // breakpoint, as executing moves in pieces is slower
+            let chunk = if self.watches_progress() {
+                time.min(self.milling_process.sample_time().unwrap_or(time))
+            } else {
+                time
+            };
+
+            let z = self.milling_process.mill().position().z;
+            let material_removed = self.milling_process.material_removed();
+            let error_count = self.milling_process.report().len();
+
+            time -= self.milling_process.execute_instruction_for(chunk)?;
+
+            if let Some(breakpoint) = self.progress_breakpoint(z, material_removed, error_count) {
+                return Ok(Some(breakpoint));
+            }
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    fn instruction_breakpoint(&self, idx: usize) -> Option<Breakpoint> {
+        let program = self.milling_process.program();
+        let line = program.source_line(idx);
+        let first_of_line = idx == 0 || program.source_line(idx - 1) != line;
+
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| match breakpoint {
+                Breakpoint::Instruction(i) => *i == idx,
+                Breakpoint::SourceLine(l) => first_of_line && line == Some(*l),
+                _ => false,
+            })
+            .cloned()
+    }
+
+    fn watches_progress(&self) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            matches!(
+                breakpoint,
+                Breakpoint::BelowZ(_) | Breakpoint::FirstCut | Breakpoint::FirstError
+            )
+        })
+    }
+
+    fn progress_breakpoint(
+        &self,
+        previous_z: f32,
+        material_removed: bool,
+        error_count: usize,
+    ) -> Option<Breakpoint> {
+        let z = self.milling_process.mill().position().z;
+
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| match breakpoint {
+                Breakpoint::BelowZ(threshold) => previous_z >= *threshold && z < *threshold,
+                Breakpoint::FirstCut => {
+                    !material_removed && self.milling_process.material_removed()
+                }
+                Breakpoint::FirstError => {
+                    error_count == 0 && !self.milling_process.report().is_empty()
+                }
+                _ => false,
+            })
+            .cloned()
     }
 
-    pub fn milling_process(&self) -> &MillingProcess {
+    pub fn milling_process(&self) -> &MillingProcess<S> {
         &self.milling_process
     }
 
-    pub fn milling_process_mut(&mut self) -> &mut MillingProcess {
+    pub fn milling_process_mut(&mut self) -> &mut MillingProcess<S> {
         &mut self.milling_process
     }
 
-    pub fn take(self) -> MillingProcess {
+    pub fn take(self) -> MillingProcess<S> {
         self.milling_process
     }
 }
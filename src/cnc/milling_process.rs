@@ -1,8 +1,18 @@
-use super::{block::Block, location::Location, mill::Mill, program::Program};
+use super::{
+    block::Block,
+    checkpoint::{Checkpoint, CheckpointError, CheckpointStock, FixtureState, ProblemState},
+    fixture::Fixture,
+    listener::MillingListener,
+    location::Location,
+    mill::{Cutter, Mill},
+    program::Program,
+    stock::Stock,
+};
 use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MillInstruction {
     RotationSpeed(f32),
     MovementSpeed(f32),
@@ -23,8 +33,8 @@ impl MillInstruction {
     }
 }
 
-#[derive(Error, Debug)]
-pub enum MillingError {
+#[derive(Error, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MillingErrorKind {
     #[error("moving a mill which has no movement speed")]
     NoMovementSpeed,
     #[error("moving a mill without rotation speed")]
@@ -39,24 +49,168 @@ pub enum MillingError {
     MovementSpeed(f32),
     #[error("rotation speed {0} not in allowed range")]
     RotationSpeed(f32),
+    #[error("the mill collides with fixture \"{0}\"")]
+    FixtureCollision(String),
 }
 
+/// Milling problem together with the place in the program where it occured
+#[derive(Error, Debug, Clone)]
+#[error(
+    "{}instruction {instruction_idx}, mill at [{}, {}, {}]: {kind}",
+    .source_line.map(|l| format!("line {l}, ")).unwrap_or_default(),
+    .position.x,
+    .position.y,
+    .position.z
+)]
+pub struct MillingError {
+    pub kind: MillingErrorKind,
+    pub instruction_idx: usize,
+    pub source_line: Option<usize>,
+    pub position: Vector3<f32>,
+}
+
+pub type MillResult = Result<(), MillingErrorKind>;
 pub type MillingResult = Result<(), MillingError>;
 
-pub struct MillingProcess {
+pub struct MillingProcess<S: Stock = Block> {
+    /// Speed of rapid moves in mm/s
+    pub rapid_speed: f32,
+    /// Speed in mm/s of slow moves made before the program sets its own movement speed
+    pub default_feed_rate: f32,
+    machine_time: f32,
+    removed_volume: f32,
     mill: Mill,
     program: Program,
-    block: Block,
+    block: S,
+    fixtures: Vec<Fixture>,
     current_instruction: usize,
+    executed_instruction: usize,
+    lenient: bool,
+    report: Vec<MillingError>,
+    listeners: Vec<Box<dyn MillingListener>>,
+    // Instruction for which the listeners have been notified about the start
+    started_instruction: Option<usize>,
 }
 
-impl MillingProcess {
-    pub fn new(mill: Mill, program: Program, block: Block) -> Self {
+impl<S: Stock> MillingProcess<S> {
+    pub const DEFAULT_RAPID_SPEED: f32 = 160.0;
+    pub const DEFAULT_FEED_RATE: f32 = 20.0;
+
+    pub fn new(mill: Mill, program: Program, block: S) -> Self {
         Self {
+            rapid_speed: Self::DEFAULT_RAPID_SPEED,
+            default_feed_rate: Self::DEFAULT_FEED_RATE,
+            machine_time: 0.0,
+            removed_volume: 0.0,
             mill,
             program,
             current_instruction: 0,
+            executed_instruction: 0,
             block,
+            fixtures: Vec::new(),
+            lenient: false,
+            report: Vec::new(),
+            listeners: Vec::new(),
+            started_instruction: None,
+        }
+    }
+
+    pub fn with_listener(mut self, listener: Box<dyn MillingListener>) -> Self {
+        self.add_listener(listener);
+        self
+    }
+
+    pub fn add_listener(&mut self, listener: Box<dyn MillingListener>) {
+        self.listeners.push(listener);
+    }
+
+    /// Replaces the cutter mounted in the mill
+    pub fn change_tool(&mut self, cutter: Cutter) {
+        self.mill.cutter = cutter;
+        for listener in &mut self.listeners {
+            listener.tool_changed(&cutter);
+        }
+    }
+
+    /// In the lenient mode problems do not stop the process, they are collected in the report
+    /// instead
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    pub fn report(&self) -> &[MillingError] {
+        &self.report
+    }
+
+    pub fn take_report(&mut self) -> Vec<MillingError> {
+        std::mem::take(&mut self.report)
+    }
+
+    fn error(&self, kind: MillingErrorKind) -> MillingError {
+        MillingError {
+            kind,
+            instruction_idx: self.executed_instruction,
+            source_line: self.program.source_line(self.executed_instruction),
+            position: *self.mill.position(),
+        }
+    }
+
+    /// Converts the problem into an error or, in the lenient mode, adds it to the report. The
+    /// same kind of problem is reported only once per instruction.
+    fn handle(&mut self, result: MillResult) -> MillingResult {
+        let Err(kind) = result else {
+            return Ok(());
+        };
+
+        let error = self.error(kind);
+        if !self.lenient {
+            for listener in &mut self.listeners {
+                listener.error(&error);
+            }
+
+            return Err(error);
+        }
+
+        let repeated = self.report.last().is_some_and(|last| {
+            last.instruction_idx == error.instruction_idx
+                && std::mem::discriminant(&last.kind) == std::mem::discriminant(&error.kind)
+        });
+
+        if !repeated {
+            for listener in &mut self.listeners {
+                listener.error(&error);
+            }
+
+            self.report.push(error);
+        }
+
+        Ok(())
+    }
+
+    pub fn with_fixtures(mut self, fixtures: Vec<Fixture>) -> Self {
+        self.fixtures = fixtures;
+        self
+    }
+
+    fn start_instruction(&mut self) {
+        if self.started_instruction == Some(self.current_instruction) {
+            return;
+        }
+
+        self.started_instruction = Some(self.current_instruction);
+        let instruction = &self.program.instructions()[self.current_instruction];
+        for listener in &mut self.listeners {
+            listener.instruction_started(self.current_instruction, instruction);
+        }
+    }
+
+    fn finish_instruction(&mut self, idx: usize) {
+        for listener in &mut self.listeners {
+            listener.instruction_finished(idx);
         }
     }
 
@@ -65,29 +219,75 @@ impl MillingProcess {
             return Ok(());
         }
 
+        self.start_instruction();
         let instruction = self.current_instruction().clone();
+        let idx = self.current_instruction;
+        self.executed_instruction = idx;
         self.current_instruction += 1;
 
+        if let MillInstruction::MoveSlow(location) | MillInstruction::MoveFast(location) =
+            &instruction
+        {
+            self.machine_time += location.f32_dist(self.mill.position())
+                / self.instruction_speed(&instruction).unwrap();
+        }
+
         match instruction {
-            MillInstruction::RotationSpeed(speed) => self.mill.set_rotation_speed(speed),
-            MillInstruction::MovementSpeed(speed) => self.mill.set_movement_speed(speed),
+            MillInstruction::RotationSpeed(speed) => {
+                let result = self.mill.set_rotation_speed(speed);
+                self.handle(result)?;
+            }
+            MillInstruction::MovementSpeed(speed) => {
+                let result = self.mill.set_movement_speed(speed);
+                self.handle(result)?;
+            }
             MillInstruction::MoveFast(location) => {
-                self.move_fast_to(&location.relative_to(self.mill.position()))
+                self.move_fast_to(&location.relative_to(self.mill.position()))?;
             }
             MillInstruction::MoveSlow(location) => {
-                self.move_slow_to(&location.relative_to(self.mill.position()))
+                self.move_slow_to(&location.relative_to(self.mill.position()))?;
             }
         }
+
+        self.finish_instruction(idx);
+        Ok(())
     }
 
-    fn move_fast_to(&mut self, _location: &Vector3<f32>) -> MillingResult {
-        unimplemented!("Fast moves are not supported")
+    fn check_fixtures(&self) -> MillResult {
+        let position = self.mill.position();
+
+        match self
+            .fixtures
+            .iter()
+            .find(|f| f.collides(position, &self.mill.cutter, &self.mill.holder))
+        {
+            Some(fixture) => Err(MillingErrorKind::FixtureCollision(fixture.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn cut_at(&mut self, position: Vector3<f32>, direction: &Vector3<f32>) -> MillingResult {
+        let result = self.mill.move_to(position);
+        self.handle(result)?;
+        let result = self.check_fixtures();
+        self.handle(result)?;
+        let cut = self.mill.cut(&mut self.block, direction, self.lenient);
+        self.removed_volume += cut.removed_volume;
+        for listener in &mut self.listeners {
+            listener.cut(self.mill.position(), cut.removed_volume);
+        }
+
+        self.handle(cut.result)
+    }
+
+    fn move_fast_to(&mut self, location: &Vector3<f32>) -> MillingResult {
+        // Rapid moves are simulated along a straight line, just like the slow ones
+        self.move_slow_to(location)
     }
 
     fn move_slow_to(&mut self, location: &Vector3<f32>) -> MillingResult {
         let Some(direction) = (location - self.mill.position()).try_normalize(0.0) else {
-            self.mill.cut(&mut self.block, &Vector3::zeros())?;
-            return Ok(());
+            return self.cut_at(*location, &Vector3::zeros());
         };
         let min_sample = self.block.sample_size().min();
         let distance = Vector3::metric_distance(location, self.mill.position());
@@ -97,15 +297,11 @@ impl MillingProcess {
 
         for step_idx in 0..=step_count {
             let position = initial_position + direction * step_idx as f32 * step;
-            self.mill.move_to(position)?;
-            self.mill.cut(&mut self.block, &direction)?;
+            self.cut_at(position, &direction)?;
         }
 
         // Make up for numerical errors
-        self.mill.move_to(*location)?;
-        self.mill.cut(&mut self.block, &direction)?;
-
-        Ok(())
+        self.cut_at(*location, &direction)
     }
 
     fn current_instruction(&self) -> &MillInstruction {
@@ -120,55 +316,106 @@ impl MillingProcess {
         &self.program
     }
 
-    fn current_instruction_length(&self) -> f32 {
-        if let MillInstruction::MoveSlow(location) =
-            &self.program.instructions()[self.current_instruction]
-        {
-            location.f32_dist(self.mill.position())
-        } else {
-            0.0
+    /// Speed in mm/s with which the instruction is executed, `None` if it is not a move
+    fn instruction_speed(&self, instruction: &MillInstruction) -> Option<f32> {
+        match instruction {
+            MillInstruction::MoveSlow(_) => {
+                Some(self.mill.feed_rate().unwrap_or(self.default_feed_rate))
+            }
+            MillInstruction::MoveFast(_) => Some(self.rapid_speed),
+            MillInstruction::RotationSpeed(_) | MillInstruction::MovementSpeed(_) => None,
         }
     }
 
-    pub fn execute_next_instruction_partially(&mut self, mut dist_left: f32) -> MillingResult {
+    /// Executes the current instruction for at most `time` seconds of machine time, returns the
+    /// time actually used. Instructions other than moves take no time.
+    pub fn execute_instruction_for(&mut self, time: f32) -> Result<f32, MillingError> {
         if self.done() {
-            return Ok(());
+            return Ok(0.0);
+        }
+
+        let instruction = self.current_instruction().clone();
+        let (MillInstruction::MoveSlow(location) | MillInstruction::MoveFast(location)) =
+            &instruction
+        else {
+            self.execute_next_instruction()?;
+            return Ok(0.0);
+        };
+
+        self.start_instruction();
+        let speed = self.instruction_speed(&instruction).unwrap();
+        let length = location.f32_dist(self.mill.position());
+        let dist = f32::min(length, time * speed);
+        self.machine_time += dist / speed;
+
+        let idx = self.current_instruction;
+        self.executed_instruction = idx;
+        let finished = dist >= length;
+        if finished {
+            self.current_instruction += 1;
         }
 
-        while dist_left > 0.0 && !self.done() {
-            let instruction = self.current_instruction().clone();
-            let current_instruction_length = self.current_instruction_length();
+        let target = location.move_toward(self.mill.position(), dist);
+        match instruction {
+            MillInstruction::MoveFast(_) => self.move_fast_to(&target)?,
+            _ => self.move_slow_to(&target)?,
+        }
 
-            if dist_left >= current_instruction_length {
-                self.current_instruction += 1;
-            }
-            let current_dist = f32::min(current_instruction_length, dist_left);
-            dist_left -= current_dist;
-
-            if let MillInstruction::MoveSlow(location) = instruction {
-                let target = location.move_toward(self.mill.position(), current_dist);
-                self.move_slow_to(&target)?;
-            } else {
-                self.execute_next_instruction()?;
-            }
+        if finished {
+            self.finish_instruction(idx);
+        }
+
+        Ok(dist / speed)
+    }
+
+    /// Executes instructions for `time` seconds of machine time
+    pub fn execute_for(&mut self, mut time: f32) -> MillingResult {
+        while time > 0.0 && !self.done() {
+            time -= self.execute_instruction_for(time)?;
         }
 
         Ok(())
     }
 
+    /// Machine time needed by the current instruction to move the mill by a single block sample,
+    /// `None` if it is not a move
+    pub fn sample_time(&self) -> Option<f32> {
+        if self.done() {
+            return None;
+        }
+
+        self.instruction_speed(self.current_instruction())
+            .map(|speed| self.block.sample_size().min() / speed)
+    }
+
+    /// Time in seconds the executed part of the program would take on the machine
+    pub fn machine_time(&self) -> f32 {
+        self.machine_time
+    }
+
+    /// Volume of the material removed so far in mm^3
+    pub fn removed_volume(&self) -> f32 {
+        self.removed_volume
+    }
+
+    /// Whether any cut has removed material so far
+    pub fn material_removed(&self) -> bool {
+        self.removed_volume > 0.0
+    }
+
     pub fn done(&self) -> bool {
         self.current_instruction == self.program.instructions().len()
     }
 
-    pub fn retake_all(self) -> (Mill, Program, Block) {
+    pub fn retake_all(self) -> (Mill, Program, S) {
         (self.mill, self.program, self.block)
     }
 
-    pub fn block(&self) -> &Block {
+    pub fn block(&self) -> &S {
         &self.block
     }
 
-    pub fn block_mut(&mut self) -> &mut Block {
+    pub fn block_mut(&mut self) -> &mut S {
         &mut self.block
     }
 
@@ -179,4 +426,91 @@ impl MillingProcess {
     pub fn mill_mut(&mut self) -> &mut Mill {
         &mut self.mill
     }
+
+    pub fn fixtures(&self) -> &[Fixture] {
+        &self.fixtures
+    }
+
+    pub fn fixtures_mut(&mut self) -> &mut Vec<Fixture> {
+        &mut self.fixtures
+    }
+}
+
+impl<S: CheckpointStock> MillingProcess<S> {
+    pub fn checkpoint(&self) -> Checkpoint {
+        let position = self.mill.position();
+
+        Checkpoint {
+            sampling_x: self.block.sampling().x,
+            sampling_y: self.block.sampling().y,
+            size_x: self.block.size().x,
+            size_y: self.block.size().y,
+            size_z: self.block.block_height(),
+            base_height: self.block.base_height(),
+            stock: self.block.state(),
+            program: self.program.clone(),
+            current_instruction: self.current_instruction,
+            executed_instruction: Some(self.executed_instruction),
+            started_instruction: self.started_instruction,
+            cutter: self.mill.cutter,
+            holder: self.mill.holder,
+            position: [position.x, position.y, position.z],
+            movement_speed: self.mill.movement_speed(),
+            rotation_speed: self.mill.rotation_speed(),
+            speed_limits: self.mill.limits,
+            rapid_speed: self.rapid_speed,
+            default_feed_rate: self.default_feed_rate,
+            machine_time: self.machine_time,
+            removed_volume: self.removed_volume,
+            lenient: self.lenient,
+            fixtures: self.fixtures.iter().map(FixtureState::from).collect(),
+            report: self.report.iter().map(ProblemState::from).collect(),
+        }
+    }
+
+    /// Resumes the simulation saved in the checkpoint, listeners have to be attached again
+    pub fn from_checkpoint(checkpoint: Checkpoint) -> Result<Self, CheckpointError> {
+        if checkpoint.current_instruction > checkpoint.program.instructions().len() {
+            return Err(CheckpointError::InstructionOutOfRange(
+                checkpoint.current_instruction,
+            ));
+        }
+
+        let block = S::from_state(&checkpoint)?;
+        let mut mill = Mill::new(checkpoint.cutter);
+        mill.holder = checkpoint.holder;
+        mill.limits = checkpoint.speed_limits;
+        if let Some(speed) = checkpoint.movement_speed {
+            mill.set_movement_speed(speed)
+                .map_err(CheckpointError::Mill)?;
+        }
+
+        if let Some(speed) = checkpoint.rotation_speed {
+            mill.set_rotation_speed(speed)
+                .map_err(CheckpointError::Mill)?;
+        }
+
+        mill.move_to(Vector3::from(checkpoint.position))
+            .map_err(CheckpointError::Mill)?;
+
+        let mut process = Self::new(mill, checkpoint.program, block);
+        process.rapid_speed = checkpoint.rapid_speed;
+        process.default_feed_rate = checkpoint.default_feed_rate;
+        process.machine_time = checkpoint.machine_time;
+        process.removed_volume = checkpoint.removed_volume;
+        process.current_instruction = checkpoint.current_instruction;
+        process.executed_instruction = checkpoint
+            .executed_instruction
+            .unwrap_or(checkpoint.current_instruction);
+        process.started_instruction = checkpoint.started_instruction;
+        process.lenient = checkpoint.lenient;
+        process.fixtures = checkpoint.fixtures.into_iter().map(Fixture::from).collect();
+        process.report = checkpoint
+            .report
+            .into_iter()
+            .map(MillingError::from)
+            .collect();
+
+        Ok(process)
+    }
 }
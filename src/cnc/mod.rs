@@ -1,8 +1,18 @@
 pub mod block;
+pub mod checkpoint;
+pub mod clock;
+pub mod dexel;
+pub mod fixture;
+pub mod heightmap;
+pub mod job_queue;
+pub mod lint;
+pub mod listener;
 pub mod location;
+pub mod machine_profile;
 pub mod mill;
 pub mod milling_player;
 pub mod milling_process;
 pub mod number;
 pub mod parser;
 pub mod program;
+pub mod stock;
@@ -5,6 +5,7 @@ use super::{
     parser::{self, LineParseError},
 };
 use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
 use std::io::Write;
 use thiserror::Error;
 
@@ -48,9 +49,11 @@ pub enum ProgramLine {
     },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Program {
     instructions: Vec<MillInstruction>,
+    // 1-based line of the source file each instruction comes from
+    source_lines: Vec<Option<usize>>,
     cutter: Cutter,
 }
 
@@ -103,8 +106,11 @@ impl Program {
             Self::validate_lines(&lines)?;
         }
 
+        let (instructions, source_lines) = Self::lines_to_mill_instructions(&lines);
+
         Ok(Self {
-            instructions: Self::lines_to_mill_instructions(&lines),
+            instructions,
+            source_lines,
             cutter: mill_shape,
         })
     }
@@ -112,6 +118,7 @@ impl Program {
     pub fn empty(cutter: Cutter) -> Self {
         Self {
             instructions: Vec::new(),
+            source_lines: Vec::new(),
             cutter,
         }
     }
@@ -127,16 +134,34 @@ impl Program {
 
     pub fn add_move(&mut self, location: &Vector3<f32>) {
         self.instructions
-            .push(MillInstruction::MoveSlow(Location::from_f32(location)))
+            .push(MillInstruction::MoveSlow(Location::from_f32(location)));
+        self.source_lines.push(None);
     }
 
-    pub fn save_to_file(&self, path: &std::path::Path) {
-        let mut file = std::fs::File::create(path).expect("File creation error");
+    pub fn save_to_file(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
 
         for (idx, instruction) in self.instructions.iter().enumerate() {
-            file.write_all(format!("N{}{}\n", idx + 3, instruction.to_str()).as_bytes())
-                .expect("File IO error");
+            file.write_all(format!("N{}{}\n", idx + 3, instruction.to_str()).as_bytes())?;
         }
+
+        Ok(())
+    }
+
+    /// Extension of the program files naming the cutter, if the cutter shape and diameter can be
+    /// read back from it
+    pub fn file_extension(&self) -> Option<String> {
+        let kind = match self.cutter.shape {
+            CutterShape::Ball => 'k',
+            CutterShape::Cylinder => 'f',
+            CutterShape::BullNose { .. } => return None,
+        };
+
+        let extension = format!("{kind}{:02}", self.cutter.diameter.round() as u32);
+        Self::parse_program_extension(&extension)
+            .ok()
+            .filter(|cutter| cutter.diameter == self.cutter.diameter)
+            .map(|_| extension)
     }
 
     fn parse_program_extension(extension: &str) -> Result<Cutter, ProgramLoadError> {
@@ -157,11 +182,19 @@ impl Program {
         })
     }
 
-    fn lines_to_mill_instructions(lines: &[ProgramLine]) -> Vec<MillInstruction> {
+    fn lines_to_mill_instructions(
+        lines: &[ProgramLine],
+    ) -> (Vec<MillInstruction>, Vec<Option<usize>>) {
+        // `parser::parse_source` produces exactly one `ProgramLine` per source line
         lines
             .iter()
-            .flat_map(Self::line_to_mill_instruction)
-            .collect()
+            .enumerate()
+            .flat_map(|(idx, line)| {
+                Self::line_to_mill_instruction(line)
+                    .into_iter()
+                    .map(move |instruction| (instruction, Some(idx + 1)))
+            })
+            .unzip()
     }
 
     fn line_to_mill_instruction(line: &ProgramLine) -> Vec<MillInstruction> {
@@ -294,6 +327,10 @@ impl Program {
         &self.instructions
     }
 
+    pub fn source_line(&self, instruction_idx: usize) -> Option<usize> {
+        self.source_lines.get(instruction_idx).copied().flatten()
+    }
+
     pub fn shape(&self) -> Cutter {
         self.cutter
     }
@@ -311,3 +348,43 @@ impl Program {
         points
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(shape: CutterShape, diameter: f32) -> Program {
+        Program::from_locations(
+            Vec::new(),
+            Cutter {
+                height: 4.0 * diameter,
+                diameter,
+                shape,
+            },
+        )
+    }
+
+    #[test]
+    fn whole_diameters_round_trip_through_the_extension() {
+        assert_eq!(
+            program(CutterShape::Ball, 8.0).file_extension().as_deref(),
+            Some("k08")
+        );
+        assert_eq!(
+            program(CutterShape::Cylinder, 16.0)
+                .file_extension()
+                .as_deref(),
+            Some("f16")
+        );
+    }
+
+    #[test]
+    fn fractional_diameters_have_no_extension() {
+        assert_eq!(program(CutterShape::Ball, 2.5).file_extension(), None);
+        assert_eq!(program(CutterShape::Ball, 0.8).file_extension(), None);
+        assert_eq!(
+            program(CutterShape::BullNose { corner_radius: 1.0 }, 8.0).file_extension(),
+            None
+        );
+    }
+}
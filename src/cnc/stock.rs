@@ -0,0 +1,159 @@
+use super::{block::Block, dexel::DexelBlock};
+use crate::render::generic_mesh::{CNCBlockVertex, Mesh};
+use nalgebra::{vector, Vector2};
+use std::borrow::Cow;
+
+/// Material representation which can be cut by a `Mill`.
+pub trait Stock {
+    fn sampling(&self) -> &Vector2<usize>;
+    fn sample_size(&self) -> &Vector2<f32>;
+    fn size(&self) -> &Vector2<f32>;
+    fn block_height(&self) -> f32;
+    fn base_height(&self) -> f32;
+    fn base_height_mut(&mut self) -> &mut f32;
+
+    /// Whether there is any material in the column above `height`
+    fn material_above(&self, x: usize, y: usize, height: f32) -> bool;
+
+    /// Removes material between `bottom` and `top` in the column, returns the total height of the
+    /// removed material
+    fn remove(&mut self, x: usize, y: usize, bottom: f32, top: f32) -> f32;
+
+    fn generate_mesh(&self) -> Mesh<CNCBlockVertex>;
+
+    fn mill_to_block(&self, position: &Vector2<f32>) -> Vector2<i32> {
+        vector![
+            ((position.x + 0.5 * self.size().x) / self.sample_size().x).floor() as i32,
+            ((position.y + 0.5 * self.size().y) / self.sample_size().y).floor() as i32
+        ]
+    }
+}
+
+impl Stock for Block {
+    fn sampling(&self) -> &Vector2<usize> {
+        Block::sampling(self)
+    }
+
+    fn sample_size(&self) -> &Vector2<f32> {
+        Block::sample_size(self)
+    }
+
+    fn size(&self) -> &Vector2<f32> {
+        Block::size(self)
+    }
+
+    fn block_height(&self) -> f32 {
+        Block::block_height(self)
+    }
+
+    fn base_height(&self) -> f32 {
+        self.base_height
+    }
+
+    fn base_height_mut(&mut self) -> &mut f32 {
+        &mut self.base_height
+    }
+
+    fn material_above(&self, x: usize, y: usize, height: f32) -> bool {
+        self.height(x, y) > height
+    }
+
+    fn remove(&mut self, x: usize, y: usize, bottom: f32, _top: f32) -> f32 {
+        let height = self.height(x, y);
+        if self.cut(x, y, bottom) {
+            height - bottom
+        } else {
+            0.0
+        }
+    }
+
+    fn generate_mesh(&self) -> Mesh<CNCBlockVertex> {
+        Block::generate_mesh(self)
+    }
+}
+
+#[derive(Clone)]
+pub enum StockModel {
+    Heightmap(Block),
+    MultiDexel(DexelBlock),
+}
+
+impl StockModel {
+    pub fn heightmap(&self) -> Cow<'_, Block> {
+        match self {
+            StockModel::Heightmap(block) => Cow::Borrowed(block),
+            StockModel::MultiDexel(dexel) => Cow::Owned(dexel.to_block()),
+        }
+    }
+
+    pub fn into_multi_dexel(self) -> DexelBlock {
+        match self {
+            StockModel::Heightmap(block) => DexelBlock::from_block(&block),
+            StockModel::MultiDexel(dexel) => dexel,
+        }
+    }
+
+    fn stock(&self) -> &dyn Stock {
+        match self {
+            StockModel::Heightmap(block) => block,
+            StockModel::MultiDexel(dexel) => dexel,
+        }
+    }
+
+    fn stock_mut(&mut self) -> &mut dyn Stock {
+        match self {
+            StockModel::Heightmap(block) => block,
+            StockModel::MultiDexel(dexel) => dexel,
+        }
+    }
+}
+
+impl Stock for StockModel {
+    fn sampling(&self) -> &Vector2<usize> {
+        self.stock().sampling()
+    }
+
+    fn sample_size(&self) -> &Vector2<f32> {
+        self.stock().sample_size()
+    }
+
+    fn size(&self) -> &Vector2<f32> {
+        self.stock().size()
+    }
+
+    fn block_height(&self) -> f32 {
+        self.stock().block_height()
+    }
+
+    fn base_height(&self) -> f32 {
+        self.stock().base_height()
+    }
+
+    fn base_height_mut(&mut self) -> &mut f32 {
+        self.stock_mut().base_height_mut()
+    }
+
+    fn material_above(&self, x: usize, y: usize, height: f32) -> bool {
+        self.stock().material_above(x, y, height)
+    }
+
+    fn remove(&mut self, x: usize, y: usize, bottom: f32, top: f32) -> f32 {
+        self.stock_mut().remove(x, y, bottom, top)
+    }
+
+    fn generate_mesh(&self) -> Mesh<CNCBlockVertex> {
+        self.stock().generate_mesh()
+    }
+}
+
+impl From<Block> for StockModel {
+    fn from(block: Block) -> Self {
+        StockModel::Heightmap(block)
+    }
+}
+
+impl From<DexelBlock> for StockModel {
+    fn from(dexel: DexelBlock) -> Self {
+        StockModel::MultiDexel(dexel)
+    }
+}
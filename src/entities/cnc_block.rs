@@ -7,15 +7,24 @@ use crate::{
     camera::Camera,
     cnc::program as cncp,
     cnc::{
-        block::Block,
+        block::{Block, DirtyRect},
+        checkpoint,
+        dexel::{Dexel, DexelBlock},
+        fixture::{Fixture, FixtureShape},
+        heightmap,
+        job_queue::{JobQueue, JobStatus},
+        lint::{self, Violation},
+        listener::MillingStatistics,
+        machine_profile::MachineProfile,
         mill::{Cutter, CutterShape, Mill},
-        milling_player::MillingPlayer,
+        milling_player::{Breakpoint, MillingPlayer},
         milling_process::MillingProcess,
-        milling_process::MillingResult,
+        milling_process::{MillingError, MillingResult},
+        stock::{Stock, StockModel},
     },
     math::{
         affine::transforms,
-        geometry::{cylinder::Cylinder, gridable::Gridable, sphere::Sphere},
+        geometry::{cylinder::Cylinder, gridable::Gridable, sphere::Sphere, torus::Torus},
     },
     primitives::color::Color,
     render::{
@@ -27,12 +36,42 @@ use crate::{
     },
     repositories::NameRepository,
 };
-use nalgebra::{vector, Matrix4, Vector2, Vector3};
+use nalgebra::{point, vector, Matrix4, Vector2, Vector3};
 use std::{cell::RefCell, rc::Rc, sync::mpsc};
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StockShape {
+    Box,
+    /// Round bar lying along the X axis
+    Bar,
+    /// Box with a rectangular pocket already cut from the top, given in mill coordinates
+    Pocket {
+        center: Vector2<f32>,
+        size: Vector2<f32>,
+        depth: f32,
+    },
+    /// Heightmap saved with `heightmap::save`
+    Heightmap(String),
+    /// Surface of the selected model raised by a machining allowance
+    Model {
+        allowance: f32,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum StockError {
+    #[error("heightmap error: {0}")]
+    Heightmap(heightmap::HeightmapError),
+    #[error("model-shaped stock requires a selected model")]
+    NoModel,
+}
 
+#[derive(Clone)]
 pub struct CNCBlockArgs {
     pub size: Vector3<f32>,
     pub sampling: Vector2<i32>,
+    pub shape: StockShape,
 }
 
 impl Default for CNCBlockArgs {
@@ -47,11 +86,13 @@ impl CNCBlockArgs {
     const MAX_SIZE: f32 = 400.0;
     const MIN_SAMPLING: i32 = 50;
     const MAX_SAMPLING: i32 = 4000;
+    const MAX_ALLOWANCE: f32 = 20.0;
 
     pub fn new() -> Self {
         Self {
             size: vector!(160.0, 160.0, 50.0),
             sampling: vector!(1000, 1000),
+            shape: StockShape::Box,
         }
     }
 
@@ -68,36 +109,110 @@ impl CNCBlockArgs {
             .sampling
             .y
             .clamp(Self::MIN_SAMPLING, Self::MAX_SAMPLING);
+
+        match &mut self.shape {
+            StockShape::Pocket {
+                center,
+                size,
+                depth,
+            } => {
+                size.x = size.x.clamp(0.0, self.size.x);
+                size.y = size.y.clamp(0.0, self.size.y);
+                center.x = center.x.clamp(-0.5 * self.size.x, 0.5 * self.size.x);
+                center.y = center.y.clamp(-0.5 * self.size.y, 0.5 * self.size.y);
+                *depth = depth.clamp(0.0, self.size.z);
+            }
+            StockShape::Model { allowance } => {
+                *allowance = allowance.clamp(0.0, Self::MAX_ALLOWANCE);
+            }
+            StockShape::Box | StockShape::Bar | StockShape::Heightmap(_) => {}
+        }
+    }
+
+    /// Creates the initial stock. `model` is the heightmap of the model surface used by the
+    /// `StockShape::Model` shape, the stock is at its base height outside of it.
+    pub fn stock(&self, model: Option<&Block>) -> Result<StockModel, StockError> {
+        let sampling = vector![self.sampling.x as usize, self.sampling.y as usize];
+
+        Ok(match &self.shape {
+            StockShape::Box => Block::new(sampling, self.size).into(),
+            StockShape::Bar => DexelBlock::bar(sampling, self.size).into(),
+            StockShape::Pocket {
+                center,
+                size,
+                depth,
+            } => {
+                let mut block = Block::new(sampling, self.size);
+                block.cut_pocket(center, size, *depth);
+                block.into()
+            }
+            StockShape::Heightmap(path) => heightmap::load(std::path::Path::new(path))
+                .map_err(StockError::Heightmap)?
+                .into(),
+            StockShape::Model { allowance } => {
+                let model = model.ok_or(StockError::NoModel)?;
+                let mut block = Block::new(sampling, self.size);
+                for x in 0..sampling.x {
+                    for y in 0..sampling.y {
+                        let height = model
+                            .height_at(&block.block_to_mill(x, y))
+                            .unwrap_or(model.base_height);
+                        block.cut(x, y, height + allowance);
+                    }
+                }
+
+                block.into()
+            }
+        })
     }
 }
 
 use std::time::Instant;
 
 enum MeshMessage {
-    #[allow(dead_code)]
-    CreateNewMesh(Block),
+    /// Replaces the copy of the stock kept by the mesh thread
+    SetBlock(DexelBlock),
+    /// Updates the columns of the copy changed by milling
+    UpdateRegion(DirtyRect, Vec<Vec<Dexel>>),
     Exit,
 }
 
 pub struct CNCBlock<'gl> {
     gl: &'gl glow::Context,
-    block: Option<Block>,
+    block: Option<StockModel>,
     mesh: GlMesh<'gl>,
     cutter_mesh: LinesMesh<'gl>,
     additional_mesh_translation: Matrix4<f32>,
     paths_mesh: LinesMesh<'gl>,
     draw_paths: bool,
+    fixtures: Vec<Fixture>,
+    fixture_meshes: Vec<LinesMesh<'gl>>,
+    breakpoints: Vec<Breakpoint>,
+    breakpoint_hit: Option<Breakpoint>,
+    job_queue: JobQueue,
+    job_path: String,
+    profile_path: String,
+    /// Machine whose limits are checked by the lint and the simulated mill
+    machine_profile: MachineProfile,
+    lint_report: Option<Vec<Violation>>,
+    statistics: Rc<RefCell<MillingStatistics>>,
     name: ChangeableName,
     shader_manager: Rc<ShaderManager<'gl>>,
     linear_transform: LinearTransformEntity,
     script_path: String,
     script_error: Option<String>,
-    milling_player: Option<MillingPlayer>,
+    heightmap_path: String,
+    checkpoint_path: String,
+    milling_player: Option<MillingPlayer<StockModel>>,
+    lenient_milling: bool,
     playback_paused: bool,
     last_mesh_regen: Instant,
     mesh_regen_interval: f32,
     mesh_notifier: mpsc::Sender<MeshMessage>,
     mesh_receiver: mpsc::Receiver<Mesh<CNCBlockVertex>>,
+    mesh_pending: bool,
+    /// Whether the mesh thread has a copy of the current multi-dexel stock
+    mesh_block_sent: bool,
     height_texture: GlTexture<'gl>,
 }
 
@@ -106,8 +221,10 @@ impl<'gl> CNCBlock<'gl> {
         gl: &'gl glow::Context,
         name_repo: Rc<RefCell<dyn NameRepository>>,
         shader_manager: Rc<ShaderManager<'gl>>,
-        block: Block,
+        block: impl Into<StockModel>,
     ) -> Self {
+        let block = block.into();
+
         let mut linear_transform = LinearTransformEntity::new();
         linear_transform.scale.scale = vector![0.05, 0.05, 0.05];
         linear_transform.orientation.axis = vector![1.0, 0.0, 0.0];
@@ -118,11 +235,22 @@ impl<'gl> CNCBlock<'gl> {
         let (mesh_notifier, mesh_getter) = std::sync::mpsc::channel::<MeshMessage>();
 
         std::thread::spawn(move || {
+            let mut copy: Option<DexelBlock> = None;
+
             while let Ok(msg) = mesh_getter.recv() {
-                let _ = match msg {
-                    MeshMessage::CreateNewMesh(block) => mesh_sender.send(block.generate_mesh()),
+                match msg {
+                    MeshMessage::SetBlock(block) => copy = Some(block),
+                    MeshMessage::UpdateRegion(rect, columns) => {
+                        if let Some(copy) = &mut copy {
+                            copy.set_region(&rect, columns);
+                        }
+                    }
                     MeshMessage::Exit => break,
-                };
+                }
+
+                if let Some(copy) = &copy {
+                    let _ = mesh_sender.send(copy.generate_mesh());
+                }
             }
         });
 
@@ -130,7 +258,7 @@ impl<'gl> CNCBlock<'gl> {
             mesh: GlMesh::new(gl, &block.generate_mesh()),
             height_texture: GlTexture::new_float(
                 gl,
-                block.raw_heights(),
+                block.heightmap().raw_heights(),
                 block.sampling().x,
                 block.sampling().y,
             ),
@@ -142,6 +270,16 @@ impl<'gl> CNCBlock<'gl> {
             ]),
             draw_paths: true,
             paths_mesh: LinesMesh::empty(gl),
+            fixtures: Vec::new(),
+            fixture_meshes: Vec::new(),
+            breakpoints: Vec::new(),
+            breakpoint_hit: None,
+            job_queue: JobQueue::new(),
+            job_path: String::from("gen-paths/1.k16"),
+            profile_path: String::from("machine.json"),
+            machine_profile: MachineProfile::default(),
+            lint_report: None,
+            statistics: Rc::new(RefCell::new(MillingStatistics::default())),
             gl,
             block: Some(block),
             shader_manager,
@@ -149,12 +287,17 @@ impl<'gl> CNCBlock<'gl> {
             name: ChangeableName::new("CNC block", name_repo),
             script_path: String::from("gen-paths/1.k16"),
             script_error: None,
+            heightmap_path: String::from("gen-paths/stock.png"),
+            checkpoint_path: String::from("gen-paths/checkpoint.json"),
             milling_player: None,
+            lenient_milling: false,
             playback_paused: true,
             mesh_regen_interval: 0.0,
             last_mesh_regen: Instant::now(),
             mesh_notifier,
             mesh_receiver,
+            mesh_pending: false,
+            mesh_block_sent: false,
         }
     }
 
@@ -163,40 +306,70 @@ impl<'gl> CNCBlock<'gl> {
         name_repo: Rc<RefCell<dyn NameRepository>>,
         shader_manager: Rc<ShaderManager<'gl>>,
         args: CNCBlockArgs,
-    ) -> Self {
-        let block = Block::new(
-            vector!(args.sampling.x as usize, args.sampling.y as usize),
-            args.size,
-        );
-
-        Self::with_block(gl, name_repo, shader_manager, block)
+        model: Option<&Block>,
+    ) -> Result<Self, StockError> {
+        let block = args.stock(model)?;
+        Ok(Self::with_block(gl, name_repo, shader_manager, block))
     }
 
     pub fn request_new_mesh(&mut self) {
-        let block = self
-            .block
-            .as_ref()
-            .or(self
-                .milling_player
-                .as_ref()
-                .map(|p| p.milling_process().block()))
-            .unwrap();
+        let block = match (&mut self.block, &mut self.milling_player) {
+            (Some(block), _) => block,
+            (None, Some(player)) => player.milling_process_mut().block_mut(),
+            (None, None) => unreachable!("CNC block without stock"),
+        };
 
-        self.height_texture
-            .load_float(block.raw_heights(), block.sampling().x, block.sampling().y);
+        match block {
+            StockModel::Heightmap(block) => {
+                // Only the samples cut since the previous request are uploaded
+                if let Some(rect) = block.take_dirty_rect() {
+                    let size = rect.size();
+                    self.height_texture.load_float_rect(
+                        block.raw_heights(),
+                        block.sampling().x,
+                        rect.min.x,
+                        rect.min.y,
+                        size.x,
+                        size.y,
+                    );
+                }
+            }
+            StockModel::MultiDexel(dexel) => {
+                if self.mesh_pending {
+                    return;
+                }
 
-        // if self.mesh_regen_interval == 0.0 {
-        //     let mesh = block.generate_mesh();
-        //     self.set_new_mesh(mesh);
-        // } else {
-        //     let _ = self
-        //         .mesh_notifier
-        //         .send(MeshMessage::CreateNewMesh(block.clone()));
-        // }
+                // The whole stock is copied only once, later only the cut columns are sent
+                let rect = dexel.take_dirty_rect();
+                let message = if !self.mesh_block_sent {
+                    self.mesh_block_sent = true;
+                    MeshMessage::SetBlock(dexel.clone())
+                } else if let Some(rect) = rect {
+                    MeshMessage::UpdateRegion(rect, dexel.region(&rect))
+                } else {
+                    return;
+                };
+
+                let _ = self.mesh_notifier.send(message);
+                self.mesh_pending = true;
+            }
+        }
+    }
+
+    /// Uploads the whole height texture of a heightmap stock
+    fn reload_height_texture(&mut self) {
+        if let StockModel::Heightmap(block) = self.current_block() {
+            self.height_texture.load_float(
+                block.raw_heights(),
+                block.sampling().x,
+                block.sampling().y,
+            );
+        }
     }
 
     pub fn try_receive_new_mesh(&mut self) {
         if let Ok(mesh) = self.mesh_receiver.try_recv() {
+            self.mesh_pending = false;
             self.set_new_mesh(mesh)
         }
     }
@@ -205,17 +378,82 @@ impl<'gl> CNCBlock<'gl> {
         self.mesh = GlMesh::new(self.gl, &mesh);
     }
 
-    pub fn block_mut(&mut self) -> Option<&mut Block> {
+    pub fn set_block(&mut self, block: impl Into<StockModel>) {
+        let block = block.into();
+        self.playback_paused = true;
+        self.milling_player = None;
+        self.paths_mesh = LinesMesh::empty(self.gl);
+        self.mesh = GlMesh::new(self.gl, &block.generate_mesh());
+        self.additional_mesh_translation =
+            transforms::translate(vector![block.size().x * 0.5, block.size().y * 0.5, 0.0]);
+        self.block = Some(block);
+        self.mesh_block_sent = false;
+        self.reload_height_texture();
+    }
+
+    fn current_block(&self) -> &StockModel {
+        self.block
+            .as_ref()
+            .or(self
+                .milling_player
+                .as_ref()
+                .map(|p| p.milling_process().block()))
+            .unwrap()
+    }
+
+    pub fn block_mut(&mut self) -> Option<&mut StockModel> {
         self.block.as_mut()
     }
 
-    pub fn block(&self) -> Option<&Block> {
+    pub fn block(&self) -> Option<&StockModel> {
         self.block.as_ref()
     }
 
+    /// Heightmap of the stock in its current state, also while a program is being milled
+    pub fn stock_heightmap(&self) -> Block {
+        self.current_block().heightmap().into_owned()
+    }
+
+    /// Turns the stock upside down for the next setup, switching to the multi-dexel model so
+    /// that material under overhangs is preserved
+    pub fn flip_stock(&mut self) {
+        if let Some(player) = self.milling_player.take() {
+            self.block = Some(player.take().retake_all().2);
+        }
+
+        let mut dexel = self.block.take().unwrap().into_multi_dexel();
+        dexel.flip();
+        self.set_block(dexel);
+    }
+
     fn milling_control(&mut self, ui: &imgui::Ui) -> MillingResult {
         ui.text("Milling control");
         self.load_script_ui(ui);
+        self.heightmap_ui(ui);
+        self.checkpoint_ui(ui);
+        self.jobs_ui(ui);
+        self.lint_ui(ui);
+
+        if ui.button("Flip stock") {
+            self.flip_stock();
+        }
+
+        if self.fixtures_ui(ui) {
+            self.create_fixture_meshes();
+
+            if let Some(player) = &mut self.milling_player {
+                player
+                    .milling_process_mut()
+                    .fixtures_mut()
+                    .clone_from(&self.fixtures);
+            }
+        }
+
+        if self.breakpoints_ui(ui) {
+            if let Some(player) = &mut self.milling_player {
+                player.breakpoints_mut().clone_from(&self.breakpoints);
+            }
+        }
 
         if let Some(player) = &mut self.milling_player {
             ui.text("Milling player");
@@ -230,19 +468,41 @@ impl<'gl> CNCBlock<'gl> {
                 position.x, position.y, position.z,
             ));
 
+            let statistics = self.statistics.borrow().clone();
+            ui.text(format!(
+                "Cuts: {}, removed volume: {:.0} mm^3",
+                statistics.cuts, statistics.removed_volume
+            ));
+
             ui.checkbox("Draw paths", &mut self.draw_paths);
+            if ui.checkbox("Collect all problems", &mut self.lenient_milling) {
+                player
+                    .milling_process_mut()
+                    .set_lenient(self.lenient_milling);
+            }
+
+            Self::milling_report_ui(ui, player.milling_process().report());
+
             let mut regen_mesh = false;
 
             if ui.button("Step") {
+                self.breakpoint_hit = None;
                 player.full_step()?;
                 regen_mesh = true;
             }
 
             if ui.button("Complete") {
-                player.complete()?;
+                self.breakpoint_hit = player.complete()?;
                 regen_mesh = true;
             }
 
+            if let Some(breakpoint) = &self.breakpoint_hit {
+                ui.text_colored(
+                    [1.0, 0.8, 0.3, 1.0],
+                    format!("Paused at breakpoint: {}", breakpoint),
+                );
+            }
+
             if regen_mesh {
                 self.request_new_mesh();
             }
@@ -250,9 +510,246 @@ impl<'gl> CNCBlock<'gl> {
             self.player_control(ui)?;
         }
 
+        self.advance_job_queue();
+
         Ok(())
     }
 
+    fn checkpoint_ui(&mut self, ui: &imgui::Ui) {
+        if ui.button("Checkpoint") {
+            ui.open_popup("checkpoint_path_popup");
+        }
+
+        ui.popup("checkpoint_path_popup", || {
+            ui.input_text("File path", &mut self.checkpoint_path)
+                .build();
+            let path = std::path::Path::new(&self.checkpoint_path);
+
+            if let Some(player) = &self.milling_player {
+                if ui.button("Save") {
+                    let checkpoint = player.milling_process().checkpoint();
+                    if let Err(err) = checkpoint::save(&checkpoint, path) {
+                        self.script_error = Some(err.to_string());
+                    }
+
+                    ui.close_current_popup();
+                }
+
+                ui.same_line();
+            }
+
+            if ui.button("Load") {
+                match checkpoint::load(path).and_then(MillingProcess::from_checkpoint) {
+                    Err(err) => self.script_error = Some(err.to_string()),
+                    Ok(process) => self.resume_process(process),
+                }
+
+                ui.close_current_popup();
+            }
+        });
+    }
+
+    fn resume_process(&mut self, process: MillingProcess<StockModel>) {
+        self.job_queue.stop();
+        self.playback_paused = true;
+        self.block = None;
+        self.milling_player = None;
+        self.mesh_block_sent = false;
+        self.lenient_milling = process.lenient();
+
+        let block = process.block();
+        self.mesh = GlMesh::new(self.gl, &block.generate_mesh());
+        self.additional_mesh_translation =
+            transforms::translate(vector![block.size().x * 0.5, block.size().y * 0.5, 0.0]);
+        self.paths_mesh = LinesMesh::strip(self.gl, process.program().positions_sequence());
+        self.create_new_cutter_mesh(&process.mill().cutter);
+        self.fixtures = process.fixtures().to_vec();
+        self.create_fixture_meshes();
+
+        self.use_process(process);
+        self.reload_height_texture();
+    }
+
+    fn jobs_ui(&mut self, ui: &imgui::Ui) {
+        let Some(_token) = ui.tree_node("Jobs") else {
+            return;
+        };
+
+        ui.input_text("File path", &mut self.job_path).build();
+        ui.same_line();
+        if ui.button("Add") {
+            if let Err(err) = self.job_queue.add(std::path::Path::new(&self.job_path)) {
+                self.script_error = Some(err.to_string());
+            }
+        }
+
+        let mut removed = None;
+        for (idx, job) in self.job_queue.jobs().iter().enumerate() {
+            let _token = ui.push_id(format!("job_{}", idx));
+            ui.separator();
+            ui.text(format!("{}. {}", idx + 1, job.path.display()));
+
+            match &job.status {
+                JobStatus::Queued => ui.text("Queued"),
+                JobStatus::Running => ui.text("Running"),
+                JobStatus::Finished {
+                    machine_time,
+                    problems,
+                } => {
+                    ui.text(format!(
+                        "Finished in {:.1} s, {} problems",
+                        machine_time,
+                        problems.len()
+                    ));
+                    Self::milling_report_ui(ui, problems);
+                }
+                JobStatus::Failed(err) => {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Failed: {}", err))
+                }
+            }
+
+            if !self.job_queue.running() {
+                ui.same_line();
+                if ui.button("Remove") {
+                    removed = Some(idx);
+                }
+            }
+        }
+
+        if let Some(idx) = removed {
+            self.job_queue.remove(idx);
+        }
+
+        ui.separator();
+        if self.job_queue.running() {
+            if ui.button("Stop") {
+                self.job_queue.stop();
+            }
+        } else {
+            if ui.button("Run") {
+                self.start_next_job();
+            }
+
+            ui.same_line();
+            if ui.button("Reset") {
+                self.job_queue.reset();
+            }
+        }
+    }
+
+    fn lint_ui(&mut self, ui: &imgui::Ui) {
+        const MAX_SHOWN_VIOLATIONS: usize = 100;
+
+        let Some(_token) = ui.tree_node("Machine profile") else {
+            return;
+        };
+
+        ui.input_text("Profile path", &mut self.profile_path)
+            .build();
+        if ui.button("Load profile") {
+            match MachineProfile::load(std::path::Path::new(&self.profile_path)) {
+                Err(err) => self.script_error = Some(err.to_string()),
+                Ok(profile) => {
+                    if let Some(player) = &mut self.milling_player {
+                        player.milling_process_mut().mill_mut().limits = profile.speed_limits();
+                    }
+
+                    self.machine_profile = profile;
+                    self.lint_report = None;
+                }
+            }
+        }
+
+        ui.same_line();
+        if ui.button("Save default profile") {
+            if let Err(err) =
+                MachineProfile::default().save(std::path::Path::new(&self.profile_path))
+            {
+                self.script_error = Some(err.to_string());
+            }
+        }
+
+        ui.text(format!("Machine: {}", self.machine_profile.name));
+
+        let Some(player) = &self.milling_player else {
+            return;
+        };
+
+        if ui.button("Lint program") {
+            self.lint_report = Some(lint::lint(
+                player.milling_process().program(),
+                &self.machine_profile,
+            ));
+        }
+
+        let Some(report) = &self.lint_report else {
+            return;
+        };
+
+        if report.is_empty() {
+            ui.text("No violations");
+        }
+
+        for violation in report.iter().take(MAX_SHOWN_VIOLATIONS) {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], violation.to_string());
+        }
+
+        if report.len() > MAX_SHOWN_VIOLATIONS {
+            ui.text(format!(
+                "... and {} more",
+                report.len() - MAX_SHOWN_VIOLATIONS
+            ));
+        }
+    }
+
+    fn start_next_job(&mut self) {
+        let paused = self.playback_paused;
+        if let Some(program) = self.job_queue.start_next() {
+            self.use_program(program);
+            self.playback_paused = paused;
+            self.request_new_mesh();
+        }
+    }
+
+    /// Starts the next job once the running one is milled
+    fn advance_job_queue(&mut self) {
+        let Some(player) = &mut self.milling_player else {
+            return;
+        };
+
+        if !self.job_queue.running() || !player.milling_process().done() {
+            return;
+        }
+
+        let process = player.milling_process_mut();
+        let machine_time = process.machine_time();
+        let problems = process.take_report();
+        self.job_queue.finish_current(machine_time, problems);
+        self.start_next_job();
+    }
+
+    fn milling_report_ui(ui: &imgui::Ui, report: &[MillingError]) {
+        const MAX_SHOWN_PROBLEMS: usize = 100;
+
+        if report.is_empty() {
+            return;
+        }
+
+        if let Some(_token) = ui.tree_node(format!("Problems ({})###milling_report", report.len()))
+        {
+            for error in report.iter().take(MAX_SHOWN_PROBLEMS) {
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], error.to_string());
+            }
+
+            if report.len() > MAX_SHOWN_PROBLEMS {
+                ui.text(format!(
+                    "... and {} more",
+                    report.len() - MAX_SHOWN_PROBLEMS
+                ));
+            }
+        }
+    }
+
     fn load_script_ui(&mut self, ui: &imgui::Ui) {
         if ui.button("Load script") {
             ui.open_popup("mill_path_popup");
@@ -268,6 +765,7 @@ impl<'gl> CNCBlock<'gl> {
                         self.script_error = Some(err.to_string());
                     }
                     Ok(prog) => {
+                        self.job_queue.stop();
                         self.use_program(prog);
                     }
                 }
@@ -277,6 +775,202 @@ impl<'gl> CNCBlock<'gl> {
         });
     }
 
+    fn heightmap_ui(&mut self, ui: &imgui::Ui) {
+        if ui.button("Stock heightmap") {
+            ui.open_popup("heightmap_path_popup");
+        }
+
+        ui.popup("heightmap_path_popup", || {
+            ui.input_text("File path (.png/.raw)", &mut self.heightmap_path)
+                .build();
+            let path = std::path::Path::new(&self.heightmap_path);
+
+            if ui.button("Save") {
+                if let Err(err) = heightmap::save(&self.current_block().heightmap(), path) {
+                    self.script_error = Some(err.to_string());
+                }
+
+                ui.close_current_popup();
+            }
+
+            ui.same_line();
+            if ui.button("Load") {
+                match heightmap::load(path) {
+                    Err(err) => self.script_error = Some(err.to_string()),
+                    Ok(block) => self.set_block(block),
+                }
+
+                ui.close_current_popup();
+            }
+        });
+    }
+
+    fn fixtures_ui(&mut self, ui: &imgui::Ui) -> bool {
+        let mut changed = false;
+
+        ui.text("Fixtures");
+        if ui.button("Add box fixture") {
+            self.fixtures.push(Fixture::new_box(
+                &format!("Fixture {}", self.fixtures.len()),
+                vector![0.0, 0.0, 0.0],
+                vector![20.0, 20.0, 20.0],
+            ));
+            changed = true;
+        }
+
+        ui.same_line();
+        if ui.button("Add cylinder fixture") {
+            self.fixtures.push(Fixture::new_cylinder(
+                &format!("Fixture {}", self.fixtures.len()),
+                vector![0.0, 0.0, 0.0],
+                10.0,
+                20.0,
+            ));
+            changed = true;
+        }
+
+        let mut removed = None;
+        for (idx, fixture) in self.fixtures.iter_mut().enumerate() {
+            let _token = ui.push_id(format!("fixture_{}", idx));
+            ui.separator();
+            changed |= ui.input_text("Name", &mut fixture.name).build();
+            changed |= ui
+                .input_float("Position X", &mut fixture.position.x)
+                .build();
+            changed |= ui
+                .input_float("Position Y", &mut fixture.position.y)
+                .build();
+            changed |= ui
+                .input_float("Position Z", &mut fixture.position.z)
+                .build();
+
+            match &mut fixture.shape {
+                FixtureShape::Box { size } => {
+                    changed |= ui.input_float("Size X", &mut size.x).build();
+                    changed |= ui.input_float("Size Y", &mut size.y).build();
+                    changed |= ui.input_float("Size Z", &mut size.z).build();
+                    size.apply(|c| *c = c.max(0.0));
+                }
+                FixtureShape::Cylinder { radius, height } => {
+                    changed |= ui.input_float("Radius", radius).build();
+                    changed |= ui.input_float("Height", height).build();
+                    *radius = radius.max(0.0);
+                    *height = height.max(0.0);
+                }
+            }
+
+            if ui.button("Remove") {
+                removed = Some(idx);
+            }
+        }
+
+        if let Some(idx) = removed {
+            self.fixtures.remove(idx);
+            changed = true;
+        }
+
+        changed
+    }
+
+    fn breakpoints_ui(&mut self, ui: &imgui::Ui) -> bool {
+        let mut changed = false;
+
+        ui.text("Breakpoints");
+        let kinds = [
+            ("Instruction", Breakpoint::Instruction(0)),
+            ("Line", Breakpoint::SourceLine(1)),
+            ("Below Z", Breakpoint::BelowZ(0.0)),
+            ("First cut", Breakpoint::FirstCut),
+            ("First error", Breakpoint::FirstError),
+        ];
+
+        for (idx, (label, breakpoint)) in kinds.into_iter().enumerate() {
+            if idx > 0 {
+                ui.same_line();
+            }
+
+            if ui.button(label) {
+                self.breakpoints.push(breakpoint);
+                changed = true;
+            }
+        }
+
+        let mut removed = None;
+        for (idx, breakpoint) in self.breakpoints.iter_mut().enumerate() {
+            let _token = ui.push_id(format!("breakpoint_{}", idx));
+
+            match breakpoint {
+                Breakpoint::Instruction(instruction) => {
+                    let mut value = *instruction as i32;
+                    changed |= ui.input_int("Instruction", &mut value).build();
+                    *instruction = value.max(0) as usize;
+                }
+                Breakpoint::SourceLine(line) => {
+                    let mut value = *line as i32;
+                    changed |= ui.input_int("Line", &mut value).build();
+                    *line = value.max(1) as usize;
+                }
+                Breakpoint::BelowZ(z) => {
+                    changed |= ui.input_float("Z", z).build();
+                }
+                Breakpoint::FirstCut | Breakpoint::FirstError => {
+                    ui.text(breakpoint.to_string());
+                }
+            }
+
+            ui.same_line();
+            if ui.button("Remove") {
+                removed = Some(idx);
+            }
+        }
+
+        if let Some(idx) = removed {
+            self.breakpoints.remove(idx);
+            changed = true;
+        }
+
+        changed
+    }
+
+    fn create_fixture_meshes(&mut self) {
+        self.fixture_meshes = self
+            .fixtures
+            .iter()
+            .map(|fixture| match fixture.shape {
+                FixtureShape::Box { size } => {
+                    let min = fixture.position - vector![0.5 * size.x, 0.5 * size.y, 0.0];
+                    let vertices = (0..8)
+                        .map(|i| {
+                            (min + vector![
+                                if i & 1 == 0 { 0.0 } else { size.x },
+                                if i & 2 == 0 { 0.0 } else { size.y },
+                                if i & 4 == 0 { 0.0 } else { size.z }
+                            ])
+                            .into()
+                        })
+                        .collect();
+                    let indices = vec![
+                        0, 1, 1, 3, 3, 2, 2, 0, 4, 5, 5, 7, 7, 6, 6, 4, 0, 4, 1, 5, 2, 6, 3, 7,
+                    ];
+
+                    LinesMesh::new(self.gl, vertices, indices)
+                }
+                FixtureShape::Cylinder { radius, height } => {
+                    let (vertices, indices) =
+                        Cylinder::new(radius as f64, height as f64).grid(30, 30);
+                    LinesMesh::new(
+                        self.gl,
+                        vertices
+                            .iter()
+                            .map(|v| v.point + fixture.position)
+                            .collect(),
+                        indices,
+                    )
+                }
+            })
+            .collect();
+    }
+
     fn merge_mesh(
         mesh_0: (Vec<SurfaceVertex>, Vec<u32>),
         mesh_1: (Vec<SurfaceVertex>, Vec<u32>),
@@ -318,6 +1012,34 @@ impl<'gl> CNCBlock<'gl> {
                     i,
                 )
             }
+            Cutter {
+                shape: CutterShape::BullNose { .. },
+                diameter,
+                height,
+            } => {
+                let corner_radius = cutter.corner_radius() as f64;
+                let torus =
+                    Torus::with_radii(0.5 * *diameter as f64 - corner_radius, corner_radius)
+                        .grid(30, 30);
+                let cylinder =
+                    Cylinder::new(0.5 * *diameter as f64, *height as f64 - corner_radius)
+                        .grid(30, 30);
+
+                // The torus is built around the Y axis
+                let torus = (
+                    torus
+                        .0
+                        .into_iter()
+                        .map(|v| SurfaceVertex {
+                            point: point![v.point.x, v.point.z, v.point.y + corner_radius as f32],
+                            uv: v.uv,
+                        })
+                        .collect(),
+                    torus.1,
+                );
+
+                Self::merge_mesh(torus, cylinder)
+            }
         };
 
         self.cutter_mesh = LinesMesh::new(
@@ -337,6 +1059,7 @@ impl<'gl> CNCBlock<'gl> {
         }
 
         let mut mill = Mill::new(program.shape());
+        mill.limits = self.machine_profile.speed_limits();
         mill.move_to(vector![
             0.0,
             0.0,
@@ -345,7 +1068,18 @@ impl<'gl> CNCBlock<'gl> {
         .unwrap();
 
         let process = MillingProcess::new(mill, program, self.block.take().unwrap());
-        self.milling_player = Some(MillingPlayer::new(process));
+        self.use_process(process);
+    }
+
+    fn use_process(&mut self, process: MillingProcess<StockModel>) {
+        let mut process = process.with_fixtures(self.fixtures.clone());
+        process.set_lenient(self.lenient_milling);
+        self.statistics = Rc::new(RefCell::new(MillingStatistics::default()));
+        process.add_listener(Box::new(Rc::clone(&self.statistics)));
+        self.breakpoint_hit = None;
+        self.lint_report = None;
+        self.milling_player =
+            Some(MillingPlayer::new(process).with_breakpoints(self.breakpoints.clone()));
     }
 
     fn player_control(&mut self, ui: &imgui::Ui) -> MillingResult {
@@ -358,11 +1092,12 @@ impl<'gl> CNCBlock<'gl> {
         if self.playback_paused {
             if ui.button("Play") {
                 self.playback_paused = false;
+                self.breakpoint_hit = None;
                 player.reset_timer();
             }
         } else {
-            player.step()?;
-            if ui.button("Pause") {
+            self.breakpoint_hit = player.step()?;
+            if ui.button("Pause") || self.breakpoint_hit.is_some() {
                 self.playback_paused = true;
                 regen_mesh = true;
             }
@@ -407,18 +1142,33 @@ impl<'gl> CNCBlock<'gl> {
             player.milling_process().block().block_height(),
         )
         .flags(imgui::SliderFlags::NO_INPUT)
-        .build(&mut player.milling_process_mut().block_mut().base_height);
+        .build(player.milling_process_mut().block_mut().base_height_mut());
 
-        ui.slider_config("Simulation speed", 1.0, 1000.0)
+        ui.slider_config("Simulation speed", 0.1, 1000.0)
             .flags(imgui::SliderFlags::LOGARITHMIC | imgui::SliderFlags::NO_INPUT)
-            .build(&mut player.slow_speed);
+            .build(&mut player.speed_multiplier);
 
-        ui.slider_config("Mesh regeneration interval", 0.0, 1.0)
+        let process = player.milling_process_mut();
+        ui.slider_config("Rapid speed [mm/s]", 1.0, 500.0)
             .flags(imgui::SliderFlags::NO_INPUT)
-            .build(&mut self.mesh_regen_interval);
+            .build(&mut process.rapid_speed);
+        ui.slider_config("Default feed rate [mm/s]", 1.0, 500.0)
+            .flags(imgui::SliderFlags::NO_INPUT)
+            .build(&mut process.default_feed_rate);
+        ui.text(format!("Machine time: {:.1} s", process.machine_time()));
+
+        // Heightmap updates are cheap, as only the cut part of the texture is uploaded
+        let heightmap = matches!(player.milling_process().block(), StockModel::Heightmap(_));
+        if !heightmap {
+            ui.slider_config("Mesh regeneration interval", 0.0, 1.0)
+                .flags(imgui::SliderFlags::NO_INPUT)
+                .build(&mut self.mesh_regen_interval);
+        }
 
         if !self.playback_paused
-            && (Instant::now() - self.last_mesh_regen).as_secs_f32() >= self.mesh_regen_interval
+            && (heightmap
+                || (Instant::now() - self.last_mesh_regen).as_secs_f32()
+                    >= self.mesh_regen_interval)
         {
             regen_mesh = true;
             self.last_mesh_regen = Instant::now();
@@ -426,6 +1176,7 @@ impl<'gl> CNCBlock<'gl> {
 
         if regen_cutter {
             let cutter = player.milling_process().mill().cutter;
+            player.milling_process_mut().change_tool(cutter);
             self.create_new_cutter_mesh(&cutter);
         }
 
@@ -443,6 +1194,7 @@ impl<'gl> Entity for CNCBlock<'gl> {
 
         if self.script_error.is_none() {
             if let Err(err) = self.milling_control(ui) {
+                self.job_queue.fail_current(err.clone());
                 self.playback_paused = true;
                 self.request_new_mesh();
                 self.script_error = Some(err.to_string());
@@ -492,6 +1244,26 @@ impl<'gl> Drawable for CNCBlock<'gl> {
 
         self.mesh.draw();
 
+        if !self.fixture_meshes.is_empty() {
+            let program = self.shader_manager.program("spline");
+            program.enable();
+            program.uniform_matrix_4_f32_slice(
+                "model_transform",
+                (premul * model_transform * self.additional_mesh_translation).as_slice(),
+            );
+            program
+                .uniform_matrix_4_f32_slice("view_transform", camera.view_transform().as_slice());
+            program.uniform_matrix_4_f32_slice(
+                "projection_transform",
+                camera.projection_transform().as_slice(),
+            );
+            program.uniform_color("vertex_color", &Color::blue());
+
+            for mesh in &self.fixture_meshes {
+                mesh.draw();
+            }
+        }
+
         if let Some(player) = &self.milling_player {
             let program = self.shader_manager.program("spline");
             program.enable();
@@ -537,6 +1309,10 @@ impl<'gl> SceneObject for CNCBlock<'gl> {
     fn set_model_transform(&mut self, linear_transform: LinearTransformEntity) {
         self.linear_transform = linear_transform;
     }
+
+    fn as_cnc_block(&self) -> Option<&CNCBlock<'_>> {
+        Some(self)
+    }
 }
 
 impl<'gl> NamedEntity for CNCBlock<'gl> {
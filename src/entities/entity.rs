@@ -1,6 +1,7 @@
 use super::{
     basic::{IntersectionTexture, LinearTransformEntity},
     bezier_surface_c0::BezierSurfaceC0,
+    cnc_block::CNCBlock,
     intersection_curve::IntersectionCurve,
     point::Point,
 };
@@ -177,6 +178,10 @@ pub trait SceneObject {
         None
     }
 
+    fn as_cnc_block(&self) -> Option<&CNCBlock<'_>> {
+        None
+    }
+
     fn set_intersection_texture(&mut self, _texture: Texture) {}
 
     fn intersection_texture(&self) -> Option<&IntersectionTexture> {
@@ -244,6 +249,10 @@ pub trait ReferentialSceneObject<'gl> {
         None
     }
 
+    fn as_cnc_block(&self) -> Option<&CNCBlock<'_>> {
+        None
+    }
+
     fn set_intersection_texture(&mut self, _texture: Texture) {}
 
     fn intersection_texture(&self) -> Option<&IntersectionTexture> {
@@ -317,6 +326,10 @@ impl<'gl, T: SceneObject> ReferentialSceneObject<'gl> for T {
         self.as_intersection()
     }
 
+    fn as_cnc_block(&self) -> Option<&CNCBlock<'_>> {
+        self.as_cnc_block()
+    }
+
     fn set_intersection_texture(&mut self, texture: Texture) {
         self.set_intersection_texture(texture);
     }
@@ -1,4 +1,8 @@
-use crate::{json, state::State, path_gen_ui::path_gen_ui};
+use crate::{
+    json,
+    path_gen_ui::{path_gen_ui, PathGeneration},
+    state::State,
+};
 use kalimorfia::{
     camera::Stereo,
     entities::{
@@ -6,7 +10,7 @@ use kalimorfia::{
         bezier_surface_args::BezierSurfaceArgs,
         bezier_surface_c0::BezierSurfaceC0,
         bezier_surface_c2::BezierSurfaceC2,
-        cnc_block::{CNCBlock, CNCBlockArgs},
+        cnc_block::{CNCBlock, CNCBlockArgs, StockShape},
         cubic_spline_c0::CubicSplineC0,
         cubic_spline_c2::CubicSplineC2,
         entity::{Entity, EntityCollection, ReferentialSceneEntity, SceneObject},
@@ -25,11 +29,16 @@ use kalimorfia::{
         },
         utils::{point_32_to_64, point_64_to_32},
     },
+    path_gen::{
+        job::{JobDescription, JobError},
+        model::Model,
+        progress::Progress,
+    },
     render::{shader_manager::ShaderManager, texture::Texture},
     repositories::NameRepository,
     ui::selector::Selector,
 };
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Point3, Vector2, Vector3};
 use std::{cell::RefCell, io::Write, rc::Rc, str::FromStr};
 
 enum BezierSurfaceType {
@@ -43,8 +52,13 @@ pub struct MainControl<'gl, 'a> {
     bezier_surface_args: Option<BezierSurfaceArgs>,
     added_surface_type: Option<BezierSurfaceType>,
     cnc_block_args: Option<CNCBlockArgs>,
+    cnc_block_error: Option<String>,
     intersection_parameters: Option<IntersetionParameters>,
     file_path: String,
+    job_path: String,
+    job: Option<JobDescription>,
+    job_error: Option<String>,
+    pub path_generation: PathGeneration,
     pub gl: &'gl glow::Context,
 }
 
@@ -78,6 +92,10 @@ impl<'gl, 'a> MainControl<'gl, 'a> {
                 .map(|p| String::from(p.to_str().unwrap_or("/")))
                 .unwrap_or(String::from("/"))
                 + "/file.json",
+            job_path: String::from("model-job.json"),
+            job: JobDescription::load(std::path::Path::new("model-job.json")).ok(),
+            job_error: None,
+            path_generation: PathGeneration::new(),
             added_surface_type: None,
             entity_manager,
             gl,
@@ -85,6 +103,7 @@ impl<'gl, 'a> MainControl<'gl, 'a> {
             shader_manager,
             bezier_surface_args: None,
             cnc_block_args: None,
+            cnc_block_error: None,
         }
     }
 
@@ -92,7 +111,7 @@ impl<'gl, 'a> MainControl<'gl, 'a> {
         self.main_control_window(ui, state);
         self.entities_window(ui, state);
         self.selection_window(ui, state);
-        // path_gen_ui(ui, state, self);
+        path_gen_ui(ui, state, self);
 
         if self.bezier_surface_args.is_some() {
             match self.added_surface_type {
@@ -123,6 +142,8 @@ impl<'gl, 'a> MainControl<'gl, 'a> {
                 ui.separator();
                 self.file_control(ui, state);
                 ui.separator();
+                self.job_control(ui);
+                ui.separator();
                 self.additional_control(ui, state);
                 ui.separator();
                 self.object_creation(ui, state);
@@ -239,6 +260,28 @@ impl<'gl, 'a> MainControl<'gl, 'a> {
         ui.columns(1, "file_reset_columns", false);
     }
 
+    fn job_control(&mut self, ui: &imgui::Ui) {
+        ui.input_text("Job path", &mut self.job_path).build();
+
+        if ui.button("Load job") {
+            match JobDescription::load(std::path::Path::new(&self.job_path)) {
+                Ok(job) => {
+                    self.job = Some(job);
+                    self.job_error = None;
+                }
+                Err(err) => self.job_error = Some(err.to_string()),
+            }
+        }
+
+        if let Some(err) = &self.job_error {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Error: {}", err));
+        } else if let Some(job) = &self.job {
+            ui.text(format!("Job with {} parts loaded", job.parts.len()));
+        } else {
+            ui.text("No job loaded");
+        }
+    }
+
     fn additional_control(&mut self, ui: &imgui::Ui, state: &mut State) {
         ui.columns(3, "additional columns", false);
         self.select_deselect_all(ui, state);
@@ -605,20 +648,111 @@ impl<'gl, 'a> MainControl<'gl, 'a> {
                 ui.input_float("Size Y", &mut args.size.y).build();
                 ui.input_float("Size Z", &mut args.size.z).build();
 
+                Self::stock_shape_control(ui, &mut args.shape);
+
                 args.clamp();
 
+                if let Some(err) = &self.cnc_block_error {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Error: {}", err));
+                }
+
                 if ui.button("Create") {
-                    let args = self.cnc_block_args.take().unwrap();
-                    self.add_cnc_block(state, args);
+                    let args = self.cnc_block_args.clone().unwrap();
+                    match self.add_cnc_block(state, args) {
+                        Ok(()) => {
+                            self.cnc_block_args = None;
+                            self.cnc_block_error = None;
+                        }
+                        Err(err) => self.cnc_block_error = Some(err.to_string()),
+                    }
+
                     return;
                 }
 
                 if ui.button("Cancel") {
                     self.cnc_block_args = None;
+                    self.cnc_block_error = None;
                 }
             });
     }
 
+    fn stock_shape_control(ui: &imgui::Ui, shape: &mut StockShape) {
+        ui.text("Stock shape");
+
+        if ui.radio_button_bool("Box", matches!(shape, StockShape::Box)) {
+            *shape = StockShape::Box;
+        }
+
+        if ui.radio_button_bool("Bar", matches!(shape, StockShape::Bar)) {
+            *shape = StockShape::Bar;
+        }
+
+        if ui.radio_button_bool("Pocketed box", matches!(shape, StockShape::Pocket { .. })) {
+            *shape = StockShape::Pocket {
+                center: Vector2::zeros(),
+                size: Vector2::new(50.0, 50.0),
+                depth: 10.0,
+            };
+        }
+
+        if ui.radio_button_bool("Heightmap", matches!(shape, StockShape::Heightmap(_))) {
+            *shape = StockShape::Heightmap(String::from("gen-paths/stock.png"));
+        }
+
+        if ui.radio_button_bool("Selected model", matches!(shape, StockShape::Model { .. })) {
+            *shape = StockShape::Model { allowance: 2.0 };
+        }
+
+        match shape {
+            StockShape::Box | StockShape::Bar => {}
+            StockShape::Pocket {
+                center,
+                size,
+                depth,
+            } => {
+                ui.input_float("Pocket center X", &mut center.x).build();
+                ui.input_float("Pocket center Y", &mut center.y).build();
+                ui.input_float("Pocket size X", &mut size.x).build();
+                ui.input_float("Pocket size Y", &mut size.y).build();
+                ui.input_float("Pocket depth", depth).build();
+            }
+            StockShape::Heightmap(path) => {
+                ui.input_text("Heightmap path", path).build();
+            }
+            StockShape::Model { allowance } => {
+                ui.input_float("Allowance", allowance).build();
+            }
+        }
+    }
+
+    /// Model made of the selected surfaces named in the loaded job description
+    pub fn selected_model(&self, state: &State) -> Result<Model, JobError> {
+        let job = self.job.clone().ok_or(JobError::NotLoaded)?;
+        let manager = self.entity_manager.borrow();
+        let selected = state.selector.selected();
+
+        let surfaces = job
+            .parts
+            .iter()
+            .map(|part| {
+                selected
+                    .iter()
+                    .copied()
+                    .find_map(|id| {
+                        let entity = manager.get_entity(id);
+                        if entity.name() == part.surface {
+                            entity.as_parametric_2_to_3()
+                        } else {
+                            None
+                        }
+                    })
+                    .ok_or_else(|| JobError::MissingSurface(part.surface.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Model::new(job, surfaces)
+    }
+
     fn add_point_at(&self, state: &mut State, position: Point3<f32>) -> usize {
         let point = Box::new(Point::with_position(
             self.gl,
@@ -1015,16 +1149,31 @@ impl<'gl, 'a> MainControl<'gl, 'a> {
         }
     }
 
-    pub fn add_cnc_block(&self, state: &mut State, args: CNCBlockArgs) {
+    pub fn add_cnc_block(
+        &self,
+        state: &mut State,
+        args: CNCBlockArgs,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let model = if matches!(args.shape, StockShape::Model { .. }) {
+            Some(
+                self.selected_model(state)?
+                    .surface_block(&Progress::new())?,
+            )
+        } else {
+            None
+        };
+
         let block = Box::new(CNCBlock::new(
             self.gl,
             Rc::clone(&state.name_repo),
             Rc::clone(&self.shader_manager),
             args,
-        ));
+            model.as_ref(),
+        )?);
 
         let id = self.entity_manager.borrow_mut().add_entity(block);
         state.selector.add_selectable(id);
+        Ok(())
     }
 
     pub fn add_intersection_curve(&self, state: &mut State, intersection: Intersection) {
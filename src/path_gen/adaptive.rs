@@ -0,0 +1,267 @@
+use super::job::MillingDirection;
+use crate::cnc::{block::Block, mill::Cutter};
+use nalgebra::{vector, Vector2, Vector3};
+use std::f32::consts::PI;
+
+/// Directions around the cutter in which the engagement is checked
+const ENGAGEMENT_RAYS: usize = 36;
+const LOOP_SEGMENTS: usize = 24;
+/// Halvings of the loop advance while looking for the largest allowed one
+const ADVANCE_ITERATIONS: usize = 6;
+/// Iterations of the bisection finding the radius at which the edge is checked
+const RING_ITERATIONS: usize = 24;
+
+/// Clearing that follows the roughing lines and switches to trochoidal loops wherever going
+/// straight would engage the cutter by more than the allowed angle. The engagement is found
+/// from a heightmap of the stock cut so far, checking the cutter edge at the middle of the
+/// layer being removed.
+pub struct AdaptiveClearing<'a> {
+    stock: Block,
+    heightmap: &'a Block,
+    cutter: Cutter,
+    max_engagement: f32,
+    loop_radius: f32,
+    /// 1 for counterclockwise loops, -1 for clockwise
+    loop_turn: f32,
+    sampling: f32,
+    /// Height of the previous level
+    top: f32,
+    /// Distance from the axis at which the edge is checked for material
+    ring: f32,
+}
+
+impl<'a> AdaptiveClearing<'a> {
+    /// Starts from the full stock of the size of the `heightmap` of the lowest cutter tip heights
+    pub fn new(
+        heightmap: &'a Block,
+        cutter: Cutter,
+        max_engagement: f32,
+        direction: MillingDirection,
+        sampling: f32,
+    ) -> Self {
+        let size = heightmap.size();
+        let stock = Block::new(
+            *heightmap.sampling(),
+            vector![size.x, size.y, heightmap.block_height()],
+        );
+
+        // The uncut material is outside of the loops, which is on the right of the cutter going
+        // around them counterclockwise
+        let loop_turn = if direction.material_on_left() {
+            -1.0
+        } else {
+            1.0
+        };
+
+        Self {
+            stock,
+            heightmap,
+            cutter,
+            max_engagement: max_engagement.to_radians(),
+            loop_radius: 0.25 * cutter.diameter,
+            loop_turn,
+            sampling,
+            top: heightmap.block_height(),
+            ring: 0.5 * cutter.diameter,
+        }
+    }
+
+    /// Cuts along the `path` at the `level`, returns the locations of the cutter
+    pub fn follow(&mut self, path: &[Vector3<f32>], level: f32) -> Vec<Vector3<f32>> {
+        self.ring = self.edge_radius(0.5 * (self.top - level));
+        self.top = level;
+
+        let points = self.resample(path);
+        let Some(&first) = points.first() else {
+            return Vec::new();
+        };
+
+        let mut locs = Vec::new();
+        let mut position = first;
+        self.cut(&position, level, &mut locs);
+
+        let mut i = 1;
+        while i < points.len() {
+            let next = points[i];
+            if (next - position).norm() < 1e-6 {
+                i += 1;
+                continue;
+            }
+
+            if self.engagement(&next, self.tip(&next, level), &[]) <= self.max_engagement {
+                self.cut(&next, level, &mut locs);
+                position = next;
+                i += 1;
+                continue;
+            }
+
+            let direction = (next - position).normalize();
+            let center = position - direction * self.loop_radius;
+            let advance = self.advance(&center, &direction, level);
+
+            for point in self.trochoid(&center, &direction, advance) {
+                self.cut(&point, level, &mut locs);
+                position = point;
+            }
+
+            while i < points.len() && (points[i] - position).dot(&direction) <= 0.0 {
+                i += 1;
+            }
+        }
+
+        locs
+    }
+
+    fn resample(&self, path: &[Vector3<f32>]) -> Vec<Vector2<f32>> {
+        let mut points: Vec<Vector2<f32>> = path.first().map(|p| p.xy()).into_iter().collect();
+
+        for pair in path.windows(2) {
+            let (start, end) = (pair[0].xy(), pair[1].xy());
+            let samples = ((end - start).norm() / self.sampling).ceil() as usize;
+            points.extend((1..=samples).map(|s| start.lerp(&end, s as f32 / samples as f32)));
+        }
+
+        points
+    }
+
+    /// Height of the cutter tip at `point` not going below the `level`
+    fn tip(&self, point: &Vector2<f32>, level: f32) -> f32 {
+        self.heightmap
+            .height_at(point)
+            .map_or(level, |h| h.max(level))
+    }
+
+    /// Lowers the stock under the cutter at `point` and adds its location to `locs`
+    fn cut(&mut self, point: &Vector2<f32>, level: f32, locs: &mut Vec<Vector3<f32>>) {
+        let tip = self.tip(point, level);
+        let radius = 0.5 * self.cutter.diameter;
+        let sample_size = *self.stock.sample_size();
+        let from = self.stock.mill_to_block(&point.add_scalar(-radius));
+        let to = self.stock.mill_to_block(&point.add_scalar(radius));
+
+        for x in from.x..=to.x {
+            for y in from.y..=to.y {
+                if !self.stock.contains(&vector![x, y]) {
+                    continue;
+                }
+
+                let (x, y) = (x as usize, y as usize);
+                let distance = (self.stock.block_to_mill(x, y) - point).norm();
+                if distance <= radius + 0.5 * sample_size.min() {
+                    let depth = tip + self.cutter.profile_height(distance.min(radius));
+                    self.stock.cut(x, y, depth);
+                }
+            }
+        }
+
+        let len = locs.len();
+        let new = vector![point.x, point.y, tip];
+
+        // Merge the straight moves at a constant height
+        if len >= 2 && locs[len - 1].z == tip && locs[len - 2].z == tip {
+            let (a, b) = (locs[len - 2].xy(), locs[len - 1].xy());
+            let (ab, bn) = (b - a, point - b);
+            if (ab.x * bn.y - ab.y * bn.x).abs() < 1e-4 && ab.dot(&bn) > 0.0 {
+                locs[len - 1] = new;
+                return;
+            }
+        }
+
+        locs.push(new);
+    }
+
+    /// Angle of the cutter edge at `point` cutting the stock, which is also cut by the cutter at
+    /// the `earlier` positions given with their tip heights
+    fn engagement(&self, point: &Vector2<f32>, tip: f32, earlier: &[(Vector2<f32>, f32)]) -> f32 {
+        let radius = 0.5 * self.cutter.diameter;
+        let engaged = (0..ENGAGEMENT_RAYS)
+            .filter(|&ray| {
+                let angle = 2.0 * PI * (ray as f32 + 0.5) / ENGAGEMENT_RAYS as f32;
+                let (sin, cos) = angle.sin_cos();
+
+                let at = point + vector![cos, sin] * self.ring;
+                let Some(mut stock) = self.stock.height_at(&at) else {
+                    return false;
+                };
+
+                for (earlier, earlier_tip) in earlier {
+                    let distance = (at - earlier).norm();
+                    if distance <= radius {
+                        stock = stock.min(earlier_tip + self.cutter.profile_height(distance));
+                    }
+                }
+
+                stock > tip + self.cutter.profile_height(self.ring)
+            })
+            .count();
+
+        2.0 * PI * engaged as f32 / ENGAGEMENT_RAYS as f32
+    }
+
+    /// Largest distance from the axis at which the edge is no higher than `height` above the tip
+    fn edge_radius(&self, height: f32) -> f32 {
+        let (mut low, mut high) = (0.0, 0.5 * self.cutter.diameter);
+        if self.cutter.profile_height(high) <= height {
+            return high;
+        }
+
+        for _ in 0..RING_ITERATIONS {
+            let middle = 0.5 * (low + high);
+            if self.cutter.profile_height(middle) <= height {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        low
+    }
+
+    /// Largest advance of the loops not going over the allowed engagement
+    fn advance(&self, center: &Vector2<f32>, direction: &Vector2<f32>, level: f32) -> f32 {
+        let mut advance = self.loop_radius;
+
+        for _ in 0..ADVANCE_ITERATIONS {
+            let mut earlier = Vec::with_capacity(LOOP_SEGMENTS);
+
+            let allowed = self
+                .trochoid(center, direction, advance)
+                .iter()
+                .all(|point| {
+                    let tip = self.tip(point, level);
+                    let engagement = self.engagement(point, tip, &earlier);
+                    earlier.push((*point, tip));
+                    engagement <= self.max_engagement
+                });
+
+            if allowed {
+                break;
+            }
+
+            advance *= 0.5;
+        }
+
+        advance
+    }
+
+    /// Loop around the `center` moving forward by the `advance`, starting from the front of the
+    /// circle
+    fn trochoid(
+        &self,
+        center: &Vector2<f32>,
+        direction: &Vector2<f32>,
+        advance: f32,
+    ) -> Vec<Vector2<f32>> {
+        let normal = vector![-direction.y, direction.x] * self.loop_turn;
+
+        (1..=LOOP_SEGMENTS)
+            .map(|s| {
+                let t = s as f32 / LOOP_SEGMENTS as f32;
+                let (sin, cos) = (2.0 * PI * t).sin_cos();
+                center
+                    + direction * (advance * t)
+                    + (direction * cos + normal * sin) * self.loop_radius
+            })
+            .collect()
+    }
+}
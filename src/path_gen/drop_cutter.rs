@@ -0,0 +1,250 @@
+use super::{gen::GenerationError, progress::Progress};
+use crate::cnc::{block::Block, mill::Cutter};
+use nalgebra::{vector, Vector2, Vector3};
+use rayon::prelude::*;
+
+/// Side of the square buckets the triangles are sorted into
+const BUCKET_SIZE: f32 = 5.0;
+/// Iterations of the golden section search along the edges
+const EDGE_ITERATIONS: usize = 32;
+
+/// Triangle in mill coordinates
+pub type Triangle = [Vector3<f32>; 3];
+
+/// Triangles touching the buckets of a square grid, the highest first
+struct Buckets {
+    origin: Vector2<f32>,
+    count: Vector2<usize>,
+    triangles: Vec<Vec<(f32, usize)>>,
+}
+
+impl Buckets {
+    fn new(triangles: &[Triangle], radius: f32, size: &Vector2<f32>) -> Self {
+        let origin = -0.5 * size;
+        let count = vector![
+            (size.x / BUCKET_SIZE).ceil() as usize,
+            (size.y / BUCKET_SIZE).ceil() as usize
+        ];
+        let mut buckets = vec![Vec::new(); count.x * count.y];
+
+        for (idx, triangle) in triangles.iter().enumerate() {
+            let (min, max) = triangle.iter().fold(
+                (
+                    vector![f32::INFINITY, f32::INFINITY],
+                    vector![-f32::INFINITY, -f32::INFINITY],
+                ),
+                |(min, max), p| (min.inf(&p.xy()), max.sup(&p.xy())),
+            );
+            let top = triangle.iter().map(|p| p.z).fold(-f32::INFINITY, f32::max);
+
+            let from = ((min.add_scalar(-radius) - origin) / BUCKET_SIZE).map(|c| c.floor());
+            let to = ((max.add_scalar(radius) - origin) / BUCKET_SIZE).map(|c| c.floor());
+            if to.x < 0.0 || to.y < 0.0 || from.x >= count.x as f32 || from.y >= count.y as f32 {
+                continue;
+            }
+
+            for y in from.y.max(0.0) as usize..=(to.y as usize).min(count.y - 1) {
+                for x in from.x.max(0.0) as usize..=(to.x as usize).min(count.x - 1) {
+                    buckets[x + y * count.x].push((top, idx));
+                }
+            }
+        }
+
+        buckets
+            .par_iter_mut()
+            .for_each(|bucket| bucket.sort_by(|a, b| b.0.total_cmp(&a.0)));
+
+        Self {
+            origin,
+            count,
+            triangles: buckets,
+        }
+    }
+
+    fn at(&self, point: &Vector2<f32>) -> &[(f32, usize)] {
+        let local = (point - self.origin) / BUCKET_SIZE;
+        let x = (local.x.floor().max(0.0) as usize).min(self.count.x - 1);
+        let y = (local.y.floor().max(0.0) as usize).min(self.count.y - 1);
+        &self.triangles[x + y * self.count.x]
+    }
+}
+
+/// Heightmap of the lowest tip heights at which the cutter does not cut into any of the
+/// `triangles`, sampled in the middles of the block cells and never lower than `base`. Fails if
+/// the generation is cancelled.
+pub fn drop_cutter(
+    triangles: &[Triangle],
+    cutter: &Cutter,
+    sampling: Vector2<usize>,
+    size: Vector3<f32>,
+    base: f32,
+    progress: &Progress,
+) -> Result<Block, GenerationError> {
+    let radius = 0.5 * cutter.diameter;
+    let buckets = Buckets::new(triangles, radius, &size.xy());
+    let sample_size = vector![size.x / sampling.x as f32, size.y / sampling.y as f32];
+
+    let rows: Vec<Vec<f32>> = (0..sampling.y)
+        .into_par_iter()
+        .map(|y| {
+            progress.check()?;
+
+            Ok((0..sampling.x)
+                .map(|x| {
+                    let point = vector![
+                        (x as f32 + 0.5) * sample_size.x - 0.5 * size.x,
+                        (y as f32 + 0.5) * sample_size.y - 0.5 * size.y
+                    ];
+
+                    let mut height = base;
+                    for &(top, idx) in buckets.at(&point) {
+                        // The tip never goes above the highest vertex
+                        if top <= height {
+                            break;
+                        }
+
+                        if let Some(z) = drop_triangle(&triangles[idx], cutter, &point) {
+                            height = height.max(z);
+                        }
+                    }
+
+                    height
+                })
+                .collect())
+        })
+        .collect::<Result<_, GenerationError>>()?;
+
+    Ok(Block::from_heights(sampling, size, rows.concat(), base))
+}
+
+/// Lowest height of the tip of the cutter above `point` touching the triangle
+fn drop_triangle(triangle: &Triangle, cutter: &Cutter, point: &Vector2<f32>) -> Option<f32> {
+    let edges = (0..3)
+        .filter_map(|i| drop_edge(&triangle[i], &triangle[(i + 1) % 3], cutter, point))
+        .fold(None, |max: Option<f32>, z| {
+            Some(max.map_or(z, |m| m.max(z)))
+        });
+
+    match (edges, drop_facet(triangle, cutter, point)) {
+        (Some(edge), Some(facet)) => Some(edge.max(facet)),
+        (edge, facet) => edge.or(facet),
+    }
+}
+
+/// Contact of the cutting edge with the inside of the triangle, where the normal of the plane
+/// points at the center of the rounded corner
+fn drop_facet(triangle: &Triangle, cutter: &Cutter, point: &Vector2<f32>) -> Option<f32> {
+    let mut normal = (triangle[1] - triangle[0]).cross(&(triangle[2] - triangle[0]));
+    if normal.z < 0.0 {
+        normal = -normal;
+    }
+
+    let length = normal.norm();
+    if length == 0.0 || normal.z < 1e-6 * length {
+        return None;
+    }
+    normal /= length;
+
+    let corner_radius = cutter.corner_radius();
+    let flat_radius = 0.5 * cutter.diameter - corner_radius;
+
+    let horizontal = normal.xy();
+    let ring = if horizontal.norm() > 0.0 {
+        -flat_radius * horizontal.normalize()
+    } else {
+        Vector2::zeros()
+    };
+
+    let contact = vector![
+        ring.x - corner_radius * normal.x,
+        ring.y - corner_radius * normal.y,
+        corner_radius - corner_radius * normal.z
+    ];
+    let contact_xy = point + contact.xy();
+
+    if !triangle_contains(triangle, &contact_xy) {
+        return None;
+    }
+
+    let plane_z = triangle[0].z
+        - (normal.x * (contact_xy.x - triangle[0].x) + normal.y * (contact_xy.y - triangle[0].y))
+            / normal.z;
+
+    Some(plane_z - contact.z)
+}
+
+/// Contact of the cutting edge with the edge or its ends. The tip height is concave along the
+/// edge, as the cutter profile is convex, so the golden section search finds the exact maximum.
+fn drop_edge(
+    a: &Vector3<f32>,
+    b: &Vector3<f32>,
+    cutter: &Cutter,
+    point: &Vector2<f32>,
+) -> Option<f32> {
+    let radius = 0.5 * cutter.diameter;
+    let direction = b.xy() - a.xy();
+    let offset = a.xy() - point;
+
+    let tip = |t: f32| {
+        let distance = (offset + direction * t).norm().min(radius);
+        a.z + (b.z - a.z) * t - cutter.profile_height(distance)
+    };
+
+    // Part of the edge under the cutter
+    let qa = direction.norm_squared();
+    let qb = offset.dot(&direction);
+    let qc = offset.norm_squared() - radius * radius;
+
+    let (mut from, mut to) = if qa == 0.0 {
+        if qc > 0.0 {
+            return None;
+        }
+        (0.0, 1.0)
+    } else {
+        let discriminant = qb * qb - qa * qc;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        ((-qb - root) / qa, (-qb + root) / qa)
+    };
+
+    from = from.max(0.0);
+    to = to.min(1.0);
+    if from > to {
+        return None;
+    }
+
+    let ends = tip(from).max(tip(to));
+
+    let ratio = 0.5 * (5.0_f32.sqrt() - 1.0);
+    for _ in 0..EDGE_ITERATIONS {
+        let left = to - ratio * (to - from);
+        let right = from + ratio * (to - from);
+
+        if tip(left) < tip(right) {
+            from = left;
+        } else {
+            to = right;
+        }
+    }
+
+    Some(ends.max(tip(0.5 * (from + to))))
+}
+
+fn triangle_contains(triangle: &Triangle, point: &Vector2<f32>) -> bool {
+    let side = |a: &Vector3<f32>, b: &Vector3<f32>| {
+        let edge = b.xy() - a.xy();
+        let to_point = point - a.xy();
+        edge.x * to_point.y - edge.y * to_point.x
+    };
+
+    let sides = [
+        side(&triangle[0], &triangle[1]),
+        side(&triangle[1], &triangle[2]),
+        side(&triangle[2], &triangle[0]),
+    ];
+
+    sides.iter().all(|&s| s >= 0.0) || sides.iter().all(|&s| s <= 0.0)
+}
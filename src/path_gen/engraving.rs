@@ -0,0 +1,437 @@
+use nalgebra::{vector, Vector2, Vector3};
+use std::{collections::HashMap, path::Path};
+use thiserror::Error;
+
+/// Height above the engraved surface to which the cutter is raised between the strokes
+const CLEARANCE: f32 = 1.0;
+/// Stroke ends closer than this many font units are cut without raising the cutter
+const LINK_TOLERANCE: f32 = 1e-3;
+
+/// Width of a character cell of the segment font
+const SEGMENT_ADVANCE: f32 = 12.5;
+const SEGMENT_HEIGHT: f32 = 10.0;
+const SEGMENT_LINE_HEIGHT: f32 = 14.0;
+
+// 0 1 2
+// 7 8 3
+// 6 5 4
+const SEGMENT_POINTS: [Vector2<f32>; 9] = [
+    vector![0.0, 10.0],
+    vector![5.0, 10.0],
+    vector![10.0, 10.0],
+    vector![10.0, 5.0],
+    vector![10.0, 0.0],
+    vector![5.0, 0.0],
+    vector![0.0, 0.0],
+    vector![0.0, 5.0],
+    vector![5.0, 5.0],
+];
+
+const SEGMENTS: [[usize; 2]; 16] = [
+    // Outer 0-7
+    [0, 1],
+    [1, 2],
+    [2, 3],
+    [3, 4],
+    [4, 5],
+    [5, 6],
+    [6, 7],
+    [7, 0],
+    // Inside 8-15
+    [0, 8],
+    [1, 8],
+    [2, 8],
+    [3, 8],
+    [4, 8],
+    [5, 8],
+    [6, 8],
+    [7, 8],
+];
+
+/// Segments of the characters, the most significant bit is the first segment
+const SEGMENT_CHARACTERS: &[(char, u16)] = &[
+    ('a', 0b1111001100010001),
+    ('b', 0b1111110001010100),
+    ('c', 0b1100111100000000),
+    ('d', 0b1111110001000100),
+    ('e', 0b1100111100000001),
+    ('f', 0b1100001100000010),
+    ('g', 0b1101111100010000),
+    ('h', 0b0011001100010001),
+    ('i', 0b1100110001000100),
+    ('j', 0b0011111000000000),
+    ('k', 0b0000001100101001),
+    ('l', 0b0000111100000000),
+    ('ł', 0b0000111100000010),
+    ('m', 0b0011001110100000),
+    ('n', 0b0011001110001000),
+    ('o', 0b1111111100000000),
+    ('p', 0b1110001100010001),
+    ('q', 0b1111111100001000),
+    ('r', 0b1110001100011001),
+    ('s', 0b1101110100010001),
+    ('t', 0b1100000001000100),
+    ('u', 0b0011111100000000),
+    ('v', 0b0000001100100010),
+    ('w', 0b0011001100001010),
+    ('x', 0b0000000010101010),
+    ('y', 0b0000000010100100),
+    ('z', 0b1100110000100010),
+    ('0', 0b1111111100100010),
+    ('1', 0b0011000000000000),
+    ('2', 0b1110111000010001),
+    ('3', 0b1111110000010000),
+    ('4', 0b0011000100010001),
+    ('5', 0b1101110100010001),
+    ('6', 0b1101111100010001),
+    ('7', 0b1111000000000000),
+    ('8', 0b1111111100010001),
+    ('9', 0b1111110100010001),
+    ('-', 0b0000000000010001),
+    ('+', 0b0000000001010101),
+    ('=', 0b0000110000010001),
+    ('_', 0b0000110000000000),
+    ('*', 0b0000000011101110),
+    ('/', 0b0000000000100010),
+    ('\\', 0b0000000010001000),
+    ('|', 0b0000000001000100),
+    ('(', 0b0000000000101000),
+    (')', 0b0000000010000010),
+    ('<', 0b0000000000101000),
+    ('>', 0b0000000010000010),
+    ('[', 0b1000011100000000),
+    (']', 0b0111100000000000),
+    ('\'', 0b0000000001000000),
+    ('^', 0b0000000010100000),
+    (' ', 0b0000000000000000),
+];
+
+/// Characters of the segment font which do not fit on the segments
+const SEGMENT_EXTRA_CHARACTERS: &[(char, &[&[[f32; 2]]])] = &[
+    ('.', &[&[[5.0, 0.0], [5.0, 1.0]]]),
+    (',', &[&[[5.0, 1.0], [4.0, -1.0]]]),
+    (':', &[&[[5.0, 2.0], [5.0, 3.0]], &[[5.0, 7.0], [5.0, 8.0]]]),
+    (';', &[&[[5.0, 7.0], [5.0, 8.0]], &[[5.0, 2.0], [4.0, 0.0]]]),
+    (
+        '!',
+        &[&[[5.0, 10.0], [5.0, 3.0]], &[[5.0, 1.0], [5.0, 0.0]]],
+    ),
+    (
+        '?',
+        &[
+            &[
+                [0.0, 10.0],
+                [10.0, 10.0],
+                [10.0, 5.0],
+                [5.0, 5.0],
+                [5.0, 3.0],
+            ],
+            &[[5.0, 1.0], [5.0, 0.0]],
+        ],
+    ),
+    (
+        '#',
+        &[
+            &[[3.0, 0.0], [3.0, 10.0]],
+            &[[7.0, 0.0], [7.0, 10.0]],
+            &[[0.0, 3.0], [10.0, 3.0]],
+            &[[0.0, 7.0], [10.0, 7.0]],
+        ],
+    ),
+    (
+        '"',
+        &[&[[3.0, 10.0], [3.0, 7.0]], &[[7.0, 10.0], [7.0, 7.0]]],
+    ),
+    (
+        '%',
+        &[
+            &[[0.0, 0.0], [10.0, 10.0]],
+            &[[1.0, 8.0], [2.0, 8.0]],
+            &[[8.0, 2.0], [9.0, 2.0]],
+        ],
+    ),
+];
+
+/// Coordinate characters of the Hershey fonts are offsets from this one
+const HERSHEY_ORIGIN: u8 = b'R';
+/// Hershey capitals go from -12 to 9 with the Y axis pointing down
+const HERSHEY_BASELINE: f32 = 9.0;
+const HERSHEY_CAP_HEIGHT: f32 = 21.0;
+const HERSHEY_LINE_HEIGHT: f32 = 32.0;
+
+#[derive(Error, Debug)]
+pub enum EngravingError {
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+    #[error("invalid Hershey glyph {0}")]
+    InvalidGlyph(usize),
+    #[error("no glyph for {0:?} in the font")]
+    MissingGlyph(char),
+}
+
+/// Single-stroke character in font units, with the origin on the left end of its baseline
+#[derive(Clone, Debug, Default)]
+pub struct Glyph {
+    pub strokes: Vec<Vec<Vector2<f32>>>,
+    pub advance: f32,
+}
+
+/// Font engraved with the cutter following the strokes
+#[derive(Clone, Debug)]
+pub struct StrokeFont {
+    pub glyphs: HashMap<char, Glyph>,
+    /// Height of the capitals in font units
+    pub cap_height: f32,
+    /// Distance between the baselines of consecutive lines in font units
+    pub line_height: f32,
+}
+
+impl StrokeFont {
+    /// Font made of the 16 segments of the character cell, lowercase and uppercase letters look
+    /// the same
+    pub fn segment() -> Self {
+        let mut glyphs = HashMap::new();
+
+        for &(c, segments) in SEGMENT_CHARACTERS {
+            let strokes = SEGMENTS
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| (segments << i) & 0x8000 == 0x8000)
+                .map(|(_, &[p_0, p_1])| vec![SEGMENT_POINTS[p_0], SEGMENT_POINTS[p_1]])
+                .collect();
+
+            glyphs.insert(c, Self::segment_glyph(strokes));
+        }
+
+        for &(c, strokes) in SEGMENT_EXTRA_CHARACTERS {
+            let strokes = strokes
+                .iter()
+                .map(|s| s.iter().map(|&[x, y]| vector![x, y]).collect())
+                .collect();
+
+            glyphs.insert(c, Self::segment_glyph(strokes));
+        }
+
+        let uppercase: Vec<_> = glyphs
+            .iter()
+            .filter(|(c, _)| c.is_lowercase())
+            .flat_map(|(c, glyph)| c.to_uppercase().map(move |u| (u, glyph.clone())))
+            .collect();
+        glyphs.extend(uppercase);
+
+        Self {
+            glyphs,
+            cap_height: SEGMENT_HEIGHT,
+            line_height: SEGMENT_LINE_HEIGHT,
+        }
+    }
+
+    fn segment_glyph(strokes: Vec<Vec<Vector2<f32>>>) -> Glyph {
+        Glyph {
+            strokes: link_strokes(strokes),
+            advance: SEGMENT_ADVANCE,
+        }
+    }
+
+    /// Parses a font in the Hershey `.jhf` format with the glyphs of the printable ASCII
+    /// characters in order, starting from the space
+    pub fn from_hershey(data: &str) -> Result<Self, EngravingError> {
+        let mut glyphs = HashMap::new();
+        let mut lines = data.lines().filter(|l| !l.trim().is_empty());
+        let mut idx = 0;
+
+        while let Some(line) = lines.next() {
+            let count: usize = line
+                .get(5..8)
+                .and_then(|c| c.trim().parse().ok())
+                .filter(|&count| count > 0)
+                .ok_or(EngravingError::InvalidGlyph(idx))?;
+
+            // Long glyphs continue on the following lines
+            let mut coordinates = line.get(8..).unwrap_or_default().as_bytes().to_vec();
+            while coordinates.len() < 2 * count {
+                let Some(next) = lines.next() else {
+                    return Err(EngravingError::InvalidGlyph(idx));
+                };
+                coordinates.extend_from_slice(next.as_bytes());
+            }
+
+            let c =
+                char::from_u32(' ' as u32 + idx as u32).ok_or(EngravingError::InvalidGlyph(idx))?;
+            glyphs.insert(c, Self::hershey_glyph(&coordinates[..2 * count]));
+            idx += 1;
+        }
+
+        Ok(Self {
+            glyphs,
+            cap_height: HERSHEY_CAP_HEIGHT,
+            line_height: HERSHEY_LINE_HEIGHT,
+        })
+    }
+
+    fn hershey_glyph(coordinates: &[u8]) -> Glyph {
+        let value = |c: u8| c as f32 - HERSHEY_ORIGIN as f32;
+        let left = value(coordinates[0]);
+        let right = value(coordinates[1]);
+
+        let mut strokes = vec![Vec::new()];
+        for pair in coordinates[2..].chunks_exact(2) {
+            if pair == b" R" {
+                strokes.push(Vec::new());
+            } else {
+                strokes.last_mut().unwrap().push(vector![
+                    value(pair[0]) - left,
+                    HERSHEY_BASELINE - value(pair[1])
+                ]);
+            }
+        }
+
+        Glyph {
+            strokes: strokes.into_iter().filter(|s| s.len() > 1).collect(),
+            advance: right - left,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, EngravingError> {
+        let data = std::fs::read_to_string(path).map_err(EngravingError::Io)?;
+        Self::from_hershey(&data)
+    }
+
+    pub fn glyph(&self, c: char) -> Result<&Glyph, EngravingError> {
+        self.glyphs.get(&c).ok_or(EngravingError::MissingGlyph(c))
+    }
+}
+
+/// Text cut into a flat surface
+#[derive(Clone, Debug)]
+pub struct Engraving {
+    pub text: String,
+    /// Height of the capitals in mm
+    pub size: f32,
+    /// Left end of the first baseline on the engraved surface, in mill coordinates
+    pub start: Vector3<f32>,
+    /// Direction of the baseline
+    pub baseline: Vector2<f32>,
+    pub depth: f32,
+}
+
+impl Engraving {
+    /// Strokes of the text cut at the depth below the surface. Strokes meeting at their ends are
+    /// cut without raising the cutter, otherwise it goes up above the surface between them.
+    pub fn paths(&self, font: &StrokeFont) -> Result<Vec<Vector3<f32>>, EngravingError> {
+        let scale = self.size / font.cap_height;
+        let along = self.baseline.normalize() * scale;
+        let up = vector![-along.y, along.x];
+
+        let bottom = self.start.z - self.depth;
+        let safe = self.start.z + CLEARANCE;
+
+        let mut locs: Vec<Vector3<f32>> = Vec::new();
+        for (line_idx, line) in self.text.lines().enumerate() {
+            let mut cursor = vector![0.0, -(line_idx as f32) * font.line_height];
+
+            for c in line.chars() {
+                let glyph = font.glyph(c)?;
+
+                for stroke in &glyph.strokes {
+                    let mill: Vec<_> = stroke
+                        .iter()
+                        .map(|p| {
+                            let p = cursor + p;
+                            let xy = self.start.xy() + along * p.x + up * p.y;
+                            vector![xy.x, xy.y, bottom]
+                        })
+                        .collect();
+
+                    let linked = locs.last().is_some_and(|last| {
+                        last.z == bottom && (last - mill[0]).norm() < LINK_TOLERANCE * scale
+                    });
+
+                    if !linked {
+                        if let Some(&last) = locs.last() {
+                            locs.push(vector![last.x, last.y, safe]);
+                        }
+                        locs.push(vector![mill[0].x, mill[0].y, safe]);
+                    }
+
+                    locs.extend(mill);
+                }
+
+                cursor.x += glyph.advance;
+            }
+        }
+
+        if let Some(&last) = locs.last() {
+            locs.push(vector![last.x, last.y, safe]);
+        }
+
+        Ok(locs)
+    }
+}
+
+/// Orders and reverses the strokes so that as many as possible start where the previous one
+/// ends, and joins such strokes
+fn link_strokes(mut strokes: Vec<Vec<Vector2<f32>>>) -> Vec<Vec<Vector2<f32>>> {
+    let mut linked: Vec<Vec<Vector2<f32>>> = Vec::new();
+
+    while !strokes.is_empty() {
+        let current = linked.last().and_then(|s| s.last()).copied();
+
+        let (idx, reverse, distance) = strokes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, s)| {
+                let start = current.map_or(0.0, |c| (s[0] - c).norm());
+                let end = current.map_or(f32::INFINITY, |c| (s[s.len() - 1] - c).norm());
+                [(i, false, start), (i, true, end)]
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .unwrap();
+
+        let mut stroke = strokes.remove(idx);
+        if reverse {
+            stroke.reverse();
+        }
+
+        match linked.last_mut() {
+            Some(last) if distance < LINK_TOLERANCE => last.extend(stroke.into_iter().skip(1)),
+            _ => linked.push(stroke),
+        }
+    }
+
+    linked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hershey_glyphs_continue_on_the_following_lines() {
+        let data = "12345  1JZ\n12345  9MWRFRT RRYQZ\nR[SZRY\n";
+        let font = StrokeFont::from_hershey(data).unwrap();
+
+        let space = font.glyph(' ').unwrap();
+        assert_eq!(space.advance, 16.0);
+        assert!(space.strokes.is_empty());
+
+        let exclamation = font.glyph('!').unwrap();
+        assert_eq!(exclamation.advance, 10.0);
+        assert_eq!(exclamation.strokes.len(), 2);
+        assert_eq!(
+            exclamation.strokes[0],
+            vec![vector![5.0, 21.0], vector![5.0, 7.0]]
+        );
+        assert_eq!(exclamation.strokes[1].len(), 5);
+        assert_eq!(exclamation.strokes[1][2], vector![5.0, 0.0]);
+    }
+
+    #[test]
+    fn truncated_hershey_glyph_is_rejected() {
+        let data = "12345  1JZ\n12345  9MWRFRT RRYQZ\n";
+        assert!(matches!(
+            StrokeFont::from_hershey(data),
+            Err(EngravingError::InvalidGlyph(1))
+        ));
+    }
+}
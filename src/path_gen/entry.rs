@@ -0,0 +1,263 @@
+use super::job::{EntryParameters, LeadKind, MillingDirection, RampKind};
+use crate::cnc::mill::{Cutter, CutterShape};
+use nalgebra::{vector, Vector2, Vector3};
+use std::f32::consts::{FRAC_PI_2, PI};
+
+/// Locations closer than this in XY are treated as vertical moves
+const VERTICAL_TOLERANCE: f32 = 1e-4;
+const ARC_SEGMENTS: usize = 8;
+const HELIX_SEGMENTS: usize = 24;
+/// Longest move of the leads and ramps checked against the model between its ends
+const CLAMP_STEP: f32 = 0.5;
+
+/// Adds the lead-ins and ramps to the passes entered with a vertical plunge and the lead-outs to
+/// the passes left with a vertical retract. Flat cutters always ramp into the passes, starting
+/// from the `stock` top above the pass at the given height. The arc leads come from the side of
+/// the cut material for the milling `direction`. The added moves are raised above the `floor`
+/// heights of the cutter tip not gouging the model.
+pub fn add_entries(
+    locs: Vec<Vector3<f32>>,
+    params: &EntryParameters,
+    cutter: &Cutter,
+    direction: Option<MillingDirection>,
+    stock: impl Fn(&Vector2<f32>, f32) -> f32,
+    floor: impl Fn(&Vector2<f32>) -> f32,
+) -> Vec<Vector3<f32>> {
+    let ramp = match (params.ramp, cutter.shape) {
+        (RampKind::None, CutterShape::Cylinder | CutterShape::BullNose { .. }) => RampKind::ZigZag,
+        (ramp, _) => ramp,
+    };
+
+    let mut result = Vec::with_capacity(locs.len());
+    let mut i = 0;
+    while i < locs.len() {
+        let current = locs[i];
+
+        let plunge =
+            plunge(&locs, i).filter(|_| ramp != RampKind::None || params.lead_in != LeadKind::None);
+        if let Some(pass_direction) = plunge {
+            let pass = locs[i + 1];
+            let lead = lead_in(&pass, &pass_direction, direction, params);
+            let entry = lead.first().copied().unwrap_or(pass);
+            let lead_direction = match lead.get(1) {
+                Some(next) => (next.xy() - entry.xy()).normalize(),
+                None if lead.is_empty() => pass_direction,
+                None => (pass.xy() - entry.xy()).normalize(),
+            };
+
+            let material = stock(&entry.xy(), pass.z);
+            let mut moves = ramp_down(&entry, &lead_direction, current.z, material, ramp, params);
+            if !lead.is_empty() {
+                moves.extend(lead.into_iter().skip(1));
+                moves.push(pass);
+            }
+
+            let moves = clamped(&moves, &floor);
+            result.push(current);
+            result.push(vector![moves[0].x, moves[0].y, current.z]);
+            result.extend(moves);
+
+            i += 2;
+            continue;
+        }
+
+        let retract = retract(&locs, i).filter(|_| params.lead_out != LeadKind::None);
+        if let Some(pass_direction) = retract {
+            let mut moves = vec![current];
+            moves.extend(lead_out(&current, &pass_direction, direction, params));
+            let moves = clamped(&moves, &floor);
+            let top = locs[i + 1];
+            let last = *moves.last().unwrap();
+
+            result.extend(moves);
+            result.push(vector![last.x, last.y, top.z]);
+            i += 2;
+            continue;
+        }
+
+        result.push(current);
+        i += 1;
+    }
+
+    result
+}
+
+fn vertical(a: &Vector3<f32>, b: &Vector3<f32>) -> bool {
+    (a.xy() - b.xy()).norm() < VERTICAL_TOLERANCE
+}
+
+/// Direction of the pass entered with a vertical move down from `locs[i]`, when the cutter got
+/// there travelling in the air
+fn plunge(locs: &[Vector3<f32>], i: usize) -> Option<Vector2<f32>> {
+    let (current, next) = (locs[i], *locs.get(i + 1)?);
+    if !vertical(&current, &next) || next.z >= current.z {
+        return None;
+    }
+
+    let in_air = i == 0 || {
+        let previous = locs[i - 1];
+        previous.z == current.z || (vertical(&previous, &current) && previous.z < current.z)
+    };
+
+    if !in_air {
+        return None;
+    }
+
+    locs[i + 1..]
+        .iter()
+        .find(|p| !vertical(p, &next))
+        .map(|p| (p.xy() - next.xy()).normalize())
+}
+
+/// Direction of the pass left with a vertical move up from `locs[i]`, when the cutter then
+/// travels in the air
+fn retract(locs: &[Vector3<f32>], i: usize) -> Option<Vector2<f32>> {
+    let (current, next) = (locs[i], *locs.get(i + 1)?);
+    if i == 0 || !vertical(&current, &next) || next.z <= current.z {
+        return None;
+    }
+
+    let in_air = locs
+        .get(i + 2)
+        .is_none_or(|after| after.z == next.z || (vertical(&next, after) && after.z < next.z));
+
+    if !in_air {
+        return None;
+    }
+
+    locs[..i]
+        .iter()
+        .rev()
+        .find(|p| !vertical(p, &current))
+        .map(|p| (current.xy() - p.xy()).normalize())
+}
+
+/// Side of the pass going in `direction` on which the material is already cut away, the right
+/// one for the paths without the material on either side
+fn waste_side(direction: &Vector2<f32>, milling: Option<MillingDirection>) -> Vector2<f32> {
+    let right = vector![direction.y, -direction.x];
+    match milling {
+        Some(milling) if !milling.material_on_left() => -right,
+        _ => right,
+    }
+}
+
+/// Lead-in before the start of the pass, the first location is where the cutter goes down
+fn lead_in(
+    pass: &Vector3<f32>,
+    direction: &Vector2<f32>,
+    milling: Option<MillingDirection>,
+    params: &EntryParameters,
+) -> Vec<Vector3<f32>> {
+    let length = params.lead_length;
+    let waste = waste_side(direction, milling);
+    let at = |p: Vector2<f32>| vector![p.x, p.y, pass.z];
+
+    match params.lead_in {
+        LeadKind::None => Vec::new(),
+        LeadKind::Line => vec![at(pass.xy() - direction * length)],
+        // Quarter of a circle coming from the waste side of the pass
+        LeadKind::Arc => {
+            let center = pass.xy() + waste * length;
+            (0..ARC_SEGMENTS)
+                .map(|s| {
+                    let angle = -FRAC_PI_2 * (1.0 - s as f32 / ARC_SEGMENTS as f32);
+                    at(center + (direction * angle.sin() - waste * angle.cos()) * length)
+                })
+                .collect()
+        }
+    }
+}
+
+/// Lead-out starting after the end of the pass
+fn lead_out(
+    end: &Vector3<f32>,
+    direction: &Vector2<f32>,
+    milling: Option<MillingDirection>,
+    params: &EntryParameters,
+) -> Vec<Vector3<f32>> {
+    let length = params.lead_length;
+    let waste = waste_side(direction, milling);
+    let at = |p: Vector2<f32>| vector![p.x, p.y, end.z];
+
+    match params.lead_out {
+        LeadKind::None => Vec::new(),
+        LeadKind::Line => vec![at(end.xy() + direction * length)],
+        LeadKind::Arc => {
+            let center = end.xy() + waste * length;
+            (1..=ARC_SEGMENTS)
+                .map(|s| {
+                    let angle = FRAC_PI_2 * s as f32 / ARC_SEGMENTS as f32;
+                    at(center + (direction * angle.sin() - waste * angle.cos()) * length)
+                })
+                .collect()
+        }
+    }
+}
+
+/// Moves split into steps not longer than `CLAMP_STEP` and raised above the `floor`
+fn clamped(moves: &[Vector3<f32>], floor: impl Fn(&Vector2<f32>) -> f32) -> Vec<Vector3<f32>> {
+    let lift = |p: Vector3<f32>| vector![p.x, p.y, p.z.max(floor(&p.xy()))];
+    let mut result = vec![lift(moves[0])];
+
+    for (from, to) in moves.iter().zip(moves.iter().skip(1)) {
+        let steps = ((to - from).norm() / CLAMP_STEP).ceil().max(1.0) as usize;
+        result.extend((1..=steps).map(|s| lift(from.lerp(to, s as f32 / steps as f32))));
+    }
+
+    result
+}
+
+/// Moves going down from above `entry` at `top` to it, the last one is `entry`. The ramp starts
+/// at the `material` top, or the ramp height above the entry if that is higher.
+fn ramp_down(
+    entry: &Vector3<f32>,
+    direction: &Vector2<f32>,
+    top: f32,
+    material: f32,
+    ramp: RampKind,
+    params: &EntryParameters,
+) -> Vec<Vector3<f32>> {
+    let start = top.min(material.max(entry.z + params.ramp_height));
+    let drop = start - entry.z;
+    let slope = params.ramp_angle.to_radians().tan();
+    let length = params.ramp_length;
+
+    let mut locs = vec![vector![entry.x, entry.y, start]];
+    match ramp {
+        RampKind::None => return vec![*entry],
+        // Back and forth along the line the cutter enters the pass on
+        RampKind::ZigZag => {
+            let legs = (drop / (2.0 * length * slope)).ceil().max(1.0) as usize;
+            let leg_drop = drop / (2 * legs) as f32;
+            let back = entry.xy() - direction * length;
+
+            for leg in 0..legs {
+                let z = start - 2.0 * leg as f32 * leg_drop;
+                locs.push(vector![back.x, back.y, z - leg_drop]);
+
+                if leg + 1 < legs {
+                    locs.push(vector![entry.x, entry.y, z - 2.0 * leg_drop]);
+                }
+            }
+        }
+        // Counterclockwise turns around the center on the left of the entry
+        RampKind::Helix => {
+            let turns = (drop / (2.0 * PI * length * slope)).ceil().max(1.0) as usize;
+            let center = entry.xy() + vector![-direction.y, direction.x] * length;
+            let from = entry.xy() - center;
+            let segments = turns * HELIX_SEGMENTS;
+
+            for s in 1..segments {
+                let t = s as f32 / segments as f32;
+                let angle = 2.0 * PI * turns as f32 * t;
+                let (sin, cos) = angle.sin_cos();
+                let p = center + vector![from.x * cos - from.y * sin, from.x * sin + from.y * cos];
+                locs.push(vector![p.x, p.y, start - drop * t]);
+            }
+        }
+    }
+
+    locs.push(*entry);
+    locs
+}
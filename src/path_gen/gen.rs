@@ -1,4 +1,18 @@
-use super::{model::*, svg};
+use super::{
+    adaptive::AdaptiveClearing,
+    engraving::{Engraving, EngravingError, StrokeFont},
+    entry,
+    job::{
+        EntryParameters, MillingDirection, PartDescription, RoughingParameters, RoughingPattern,
+        SvgOperation,
+    },
+    linking,
+    model::*,
+    pocket::Pocket,
+    progress::Progress,
+    rest,
+    svg::{self, SvgError, SvgPlacement},
+};
 use crate::{
     cnc::{
         block::Block,
@@ -18,113 +32,299 @@ use itertools::Itertools;
 use nalgebra::{vector, Point2, Vector2, Vector3};
 use ordered_float::NotNan;
 use rayon::prelude::*;
-use std::{
-    collections::{BTreeMap, HashMap},
-    mem::MaybeUninit,
-};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thiserror::Error;
 
 const SAFE_CONTOUR_ADD: usize = 3;
 const INTERSECTION_IN_BLOCK: f32 = INTERSECTION_STEP as f32 * MODEL_SCALE;
 
 pub const SAFE_HEIGHT: f32 = 66.0;
-const CUTTER_DIAMETER_ROUGH: f32 = 16.0;
-pub const CUTTER_RADIUS_ROUGH: f32 = CUTTER_DIAMETER_ROUGH * 0.5;
-const CUTTER_RADIUS_ROUGH_SQRT_2: f32 = CUTTER_RADIUS_ROUGH * std::f32::consts::SQRT_2 * 0.5;
 const BASE_HEIGHT: f32 = 16.0;
 
-const CUTTER_DIAMETER_FLAT: f32 = 10.0;
-const CUTTER_RADIUS_FLAT: f32 = 0.5 * CUTTER_DIAMETER_FLAT;
-const CUTTER_HEIGHT_FLAT: f32 = 4.0 * CUTTER_DIAMETER_FLAT;
-const FLAT_EPS: f32 = 0.1 * CUTTER_RADIUS_FLAT;
-
-const CUTTER_DIAMETER_DETAIL: f32 = 8.0;
-pub const CUTTER_RADIUS_DETAIL: f32 = 0.5 * CUTTER_DIAMETER_DETAIL;
+#[derive(Error, Debug)]
+pub enum GenerationError {
+    #[error("path generation cancelled")]
+    Cancelled,
+    #[error("failed to find the model silhouette")]
+    NoSilhouette,
+    #[error("failed to find the intersection of \"{0}\" and \"{1}\", check its guide point")]
+    NoIntersection(String, String),
+    #[error("failed to find the hole of \"{0}\" around \"{1}\", check its guide point")]
+    NoHole(String, String),
+    #[error("engraving error: {0}")]
+    Engraving(EngravingError),
+    #[error("SVG error: {0}")]
+    Svg(SvgError),
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+    #[error("no program file extension names the {0:?} cutter of {1} mm")]
+    UnnamedCutter(CutterShape, f32),
+}
 
-pub fn rough(model: &Model) -> cncp::Program {
-    const UPPER_PLANE_HEIGHT: f32 = 35.0;
-    const LOWER_PLANE_HEIGHT: f32 = 20.0;
-    const CUTTER_HEIGHT: f32 = 4.0 * CUTTER_DIAMETER_ROUGH;
-    const SPACING: f32 = CUTTER_DIAMETER_ROUGH * 0.5;
+pub fn rough(model: &Model, progress: &Progress) -> Result<cncp::Program, GenerationError> {
     const SAMPLING: f32 = 1.0;
 
-    let heightmap = model.sampled_block();
-    let mut locs = initial_locations();
-    locs.push(vector![
-        BLOCK_SIZE * 0.5 + SPACING,
-        BLOCK_SIZE * 0.5 + SPACING * 2.0,
-        SAFE_HEIGHT
-    ]);
+    let params = &model.job.roughing;
+    let cutter = rough_cutter(model);
+    let heightmap = rough_heightmap(model, &cutter, progress)?;
+    let safe = heightmap.block_height() + SAFE_CLEARANCE;
+    let levels = rough_levels(params, heightmap.block_height());
+    progress.update(1, levels.len() + 1)?;
 
-    locs.extend(rough_plane(
-        UPPER_PLANE_HEIGHT,
+    let mut adaptive = AdaptiveClearing::new(
         &heightmap,
-        SPACING,
+        cutter,
+        params.max_engagement,
+        params.direction,
         SAMPLING,
-    ));
+    );
+
+    let mut locs = initial_locations(safe);
+
+    for (i, &level) in levels.iter().enumerate() {
+        let mut plane = rough_plane(level, &heightmap, params, SAMPLING, safe);
+
+        match params.pattern {
+            RoughingPattern::ZigZag | RoughingPattern::Adaptive => {
+                // Go back the same way so that the next level starts where the last one ended
+                if i % 2 == 1 {
+                    plane.reverse();
+                }
+
+                if params.pattern == RoughingPattern::Adaptive {
+                    plane = adaptive.follow(&plane, level);
+                }
+
+                if let (0, Some(first)) = (i, plane.first()) {
+                    locs.push(vector![first.x, first.y, safe]);
+                }
+
+                locs.extend(plane);
+            }
+            // Every line is already entered and left at the safe height
+            RoughingPattern::OneWay => locs.extend(plane),
+            RoughingPattern::Spiral => extend_safe(&mut locs, plane, safe),
+        }
+
+        progress.update(i + 2, levels.len() + 1)?;
+    }
 
-    let mut lower_plane = rough_plane(LOWER_PLANE_HEIGHT, &heightmap, SPACING, SAMPLING);
-    lower_plane.reverse();
-    locs.extend(lower_plane);
+    add_ending_locs(&mut locs, safe);
 
-    add_ending_locs(&mut locs);
+    // The passes are entered where the level above cut the stock
+    let top = heightmap.block_height();
+    let uncut = uncut_stock(top, 0.5 * cutter.diameter);
+    let stock = |p: &Vector2<f32>, z: f32| {
+        let above = levels
+            .iter()
+            .copied()
+            .filter(|&l| l > z)
+            .fold(top, f32::min);
+        uncut(p, z).min(above)
+    };
 
-    cncp::Program::from_locations(
+    Ok(program(
         locs,
-        Cutter {
-            height: CUTTER_HEIGHT,
-            diameter: CUTTER_DIAMETER_ROUGH,
-            shape: CutterShape::Ball,
-        },
-    )
+        cutter,
+        &model.job.entries,
+        Some(params.direction),
+        stock,
+        block_heights(&heightmap),
+    ))
+}
+
+fn rough_cutter(model: &Model) -> Cutter {
+    let diameter = model.job.cutters.rough;
+    Cutter {
+        height: 4.0 * diameter,
+        diameter,
+        shape: CutterShape::Ball,
+    }
+}
+
+/// Lowest heights of the roughing cutter tip leaving the allowance above the model
+fn rough_heightmap(
+    model: &Model,
+    cutter: &Cutter,
+    progress: &Progress,
+) -> Result<Block, GenerationError> {
+    let mut heightmap = model.drop_cutter_block(cutter, progress)?;
+    let sampling = *heightmap.sampling();
+    for x in 0..sampling.x {
+        for y in 0..sampling.y {
+            *heightmap.height_mut(x, y) += model.job.roughing.allowance;
+        }
+    }
+
+    Ok(heightmap)
+}
+
+/// Stock left by the roughing, which does not go below its lowest level
+fn rough_stock(model: &Model, progress: &Progress) -> Result<Block, GenerationError> {
+    let mut stock = rough_heightmap(model, &rough_cutter(model), progress)?;
+    let sampling = *stock.sampling();
+    for x in 0..sampling.x {
+        for y in 0..sampling.y {
+            let height = stock.height_mut(x, y);
+            *height = height.max(model.job.roughing.min_height);
+        }
+    }
+
+    Ok(stock)
+}
+
+/// Program with the entry and exit moves added to the passes, see `entry::add_entries`
+fn program(
+    locs: Vec<Vector3<f32>>,
+    cutter: Cutter,
+    entries: &EntryParameters,
+    direction: Option<MillingDirection>,
+    stock: impl Fn(&Vector2<f32>, f32) -> f32,
+    floor: impl Fn(&Vector2<f32>) -> f32,
+) -> cncp::Program {
+    let locs = entry::add_entries(locs, entries, &cutter, direction, stock, floor);
+    cncp::Program::from_locations(locs, cutter)
+}
+
+/// Heights of the block at the mill positions, there is nothing outside of it
+fn block_heights(block: &Block) -> impl Fn(&Vector2<f32>) -> f32 + '_ {
+    |p| block.height_at(p).unwrap_or(f32::NEG_INFINITY)
+}
+
+/// Top of the stock not cut by the earlier programs, the cutter of the `radius` is in the air
+/// outside of the block
+fn uncut_stock(top: f32, radius: f32) -> impl Fn(&Vector2<f32>, f32) -> f32 {
+    move |p, _| {
+        if p.x.abs().max(p.y.abs()) < 0.5 * BLOCK_SIZE + radius {
+            top
+        } else {
+            f32::NEG_INFINITY
+        }
+    }
+}
+
+/// Floor of the programs cutting into a flat surface away from the model
+fn no_floor(_: &Vector2<f32>) -> f32 {
+    f32::NEG_INFINITY
+}
+
+/// Heights of the roughing levels from the `top` of the stock down to the lowest level
+fn rough_levels(params: &RoughingParameters, top: f32) -> Vec<f32> {
+    let mut levels = Vec::new();
+    let mut level = top - params.step_down;
+
+    while level > params.min_height {
+        levels.push(level);
+        level -= params.step_down;
+    }
+
+    levels.push(params.min_height);
+    levels
+}
+
+/// Extends `locs` with the path entered and left at the `safe` height
+fn extend_safe(locs: &mut Vec<Vector3<f32>>, path: Vec<Vector3<f32>>, safe: f32) {
+    let (Some(&first), Some(&last)) = (path.first(), path.last()) else {
+        return;
+    };
+
+    locs.push(vector![first.x, first.y, safe]);
+    locs.extend(path);
+    locs.push(vector![last.x, last.y, safe]);
 }
 
-fn rough_plane(height: f32, heightmap: &Block, spacing: f32, sampling: f32) -> Vec<Vector3<f32>> {
+fn rough_plane(
+    height: f32,
+    heightmap: &Block,
+    params: &RoughingParameters,
+    sampling: f32,
+    safe: f32,
+) -> Vec<Vector3<f32>> {
+    if params.pattern == RoughingPattern::Spiral {
+        return rough_spiral(height, heightmap, params, sampling);
+    }
+
+    let spacing = params.stepover;
+    let climb = params.direction == MillingDirection::Climb;
+
     (0..(BLOCK_SIZE / spacing + 4.0) as usize)
         .into_par_iter()
         .flat_map(|i| {
             let x = 0.5 * BLOCK_SIZE + spacing - spacing * i as f32;
             let mut line = rough_line(height, x, heightmap, spacing, sampling);
-            if i % 2 == 1 {
+
+            // Lines go towards -Y with the uncut material towards -X on their right, which is
+            // climb milling
+            let reverse = match params.pattern {
+                RoughingPattern::ZigZag | RoughingPattern::Adaptive => (i % 2 == 1) == climb,
+                _ => !climb,
+            };
+
+            if reverse {
                 line.reverse();
             }
 
-            line
+            if params.pattern == RoughingPattern::OneWay {
+                let mut retracted = Vec::with_capacity(line.len() + 2);
+                extend_safe(&mut retracted, line, safe);
+                retracted
+            } else {
+                line
+            }
         })
         .collect()
 }
 
-fn get_height(bx: f32, by: f32, hsamx: f32, hsamy: f32, block: &Block) -> Option<f32> {
-    (bx >= 0.0 && by >= 0.0 && bx < hsamx && by < hsamy)
-        .then(|| block.height(bx as usize, by as usize))
-}
-
-fn rough_max(bx: f32, by: f32, hsamx: f32, hsamy: f32, block: &Block) -> f32 {
-    [
-        (bx, by),
-        (bx + CUTTER_RADIUS_ROUGH, by),
-        (bx, by + CUTTER_RADIUS_ROUGH),
-        (bx - CUTTER_RADIUS_ROUGH, by),
-        (bx, by - CUTTER_RADIUS_ROUGH),
-        (
-            bx + CUTTER_RADIUS_ROUGH_SQRT_2,
-            by + CUTTER_RADIUS_ROUGH_SQRT_2,
-        ),
-        (
-            bx + CUTTER_RADIUS_ROUGH_SQRT_2,
-            by - CUTTER_RADIUS_ROUGH_SQRT_2,
-        ),
-        (
-            bx - CUTTER_RADIUS_ROUGH_SQRT_2,
-            by + CUTTER_RADIUS_ROUGH_SQRT_2,
-        ),
-        (
-            bx - CUTTER_RADIUS_ROUGH_SQRT_2,
-            by - CUTTER_RADIUS_ROUGH_SQRT_2,
-        ),
-    ]
-    .into_iter()
-    .filter_map(|(x, y)| get_height(x, y, hsamx, hsamy, block))
-    .fold(0.0, f32::max)
+/// Rectangles around the middle of the block, clockwise for climb milling so that the uncut
+/// material in the middle is on the right of the cutter
+fn rough_spiral(
+    height: f32,
+    heightmap: &Block,
+    params: &RoughingParameters,
+    sampling: f32,
+) -> Vec<Vector3<f32>> {
+    let spacing = params.stepover;
+    // The corners go around counterclockwise, mirroring them reverses the direction
+    let mirror = match params.direction {
+        MillingDirection::Climb => -1.0,
+        MillingDirection::Conventional => 1.0,
+    };
+
+    let mut corners = Vec::new();
+    let mut half = 0.5 * BLOCK_SIZE + spacing;
+    while half > 0.0 {
+        corners.extend_from_slice(&[
+            vector![-half, -half],
+            vector![half, -half],
+            vector![half, half],
+            vector![-half, half],
+            vector![-half, -half + spacing],
+        ]);
+
+        half -= spacing;
+    }
+    corners.push(vector![0.0, 0.0]);
+
+    let mut locs: Vec<Vector3<f32>> = Vec::new();
+    for (start, end) in corners.into_iter().tuple_windows() {
+        let samples = ((end - start).norm() / sampling).ceil().max(1.0) as usize;
+
+        for i in 0..=samples {
+            let point = start.lerp(&end, i as f32 / samples as f32);
+            let z = rough_z(point.x * mirror, point.y, height, heightmap);
+            push_merged(&mut locs, vector![point.x * mirror, point.y, z]);
+        }
+    }
+
+    locs
+}
+
+/// Height of the cutter at `x`, `y` not going below the level `height`
+fn rough_z(x: f32, y: f32, height: f32, heightmap: &Block) -> f32 {
+    heightmap
+        .height_at(&vector![x, y])
+        .map_or(height, |h| h.max(height))
 }
 
 fn rough_line(
@@ -138,69 +338,131 @@ fn rough_line(
     let width = BLOCK_SIZE + 4.0 * spacing;
     let samples = (width / sampling) as usize + 1;
     let mut locs: Vec<Vector3<f32>> = Vec::new();
-    let ss = heightmap.sample_size();
-    let hsam = heightmap.sampling();
-    let hsamx = hsam.x as f32;
-    let hsamy = hsam.y as f32;
 
     for _ in 0..samples {
-        let bx = ((x + BLOCK_SIZE * 0.5) / ss.x).floor();
-        let by = ((y + BLOCK_SIZE * 0.5) / ss.y).floor();
+        let z = rough_z(x, y, height, heightmap);
+        push_merged(&mut locs, vector![x, y, z]);
+        y -= sampling;
+    }
 
-        let z = if bx >= 0.0 && by >= 0.0 && bx < hsamx && by < hsamy {
-            f32::max(rough_max(bx, by, hsamx, hsamy, heightmap), height)
-        } else {
-            height
-        };
+    locs
+}
 
-        let new = vector![x, y, z];
-        let len = locs.len();
+/// Adds the location to `locs`, replacing the last one if the move to it continues the previous
+/// straight move at a constant height
+fn push_merged(locs: &mut Vec<Vector3<f32>>, new: Vector3<f32>) {
+    let len = locs.len();
 
-        if len >= 2 && locs[len - 1].z == z && locs[len - 2].z == z {
+    if len >= 2 && locs[len - 1].z == new.z && locs[len - 2].z == new.z {
+        let (a, b) = (locs[len - 2].xy(), locs[len - 1].xy());
+        let (ab, bn) = (b - a, new.xy() - b);
+        if (ab.x * bn.y - ab.y * bn.x).abs() < 1e-4 && ab.dot(&bn) > 0.0 {
             locs[len - 1] = new;
-        } else {
-            locs.push(new);
+            return;
         }
-
-        y -= sampling;
     }
 
-    locs
+    locs.push(new);
 }
 
-pub fn flat(model: &Model) -> Option<cncp::Program> {
-    let mut locs = initial_locations();
+pub fn flat(model: &Model, progress: &Progress) -> Result<cncp::Program, GenerationError> {
+    let safe = model.safe_height();
+    let diameter = model.job.cutters.flat;
+
+    let mut locs = initial_locations(safe);
     locs.extend_from_slice(&[
         vector![
-            -BLOCK_SIZE * 0.5 - CUTTER_DIAMETER_FLAT,
-            BLOCK_SIZE * 0.5 + CUTTER_DIAMETER_FLAT,
-            SAFE_HEIGHT
+            -BLOCK_SIZE * 0.5 - diameter,
+            BLOCK_SIZE * 0.5 + diameter,
+            safe
         ],
         vector![
-            -BLOCK_SIZE * 0.5 - CUTTER_DIAMETER_FLAT,
-            BLOCK_SIZE * 0.5 + CUTTER_DIAMETER_FLAT,
+            -BLOCK_SIZE * 0.5 - diameter,
+            BLOCK_SIZE * 0.5 + diameter,
             BASE_HEIGHT
         ],
     ]);
 
-    let silhouette = model.silhouette()?;
+    let silhouette = model.silhouette().ok_or(GenerationError::NoSilhouette)?;
+    progress.update(1, 2)?;
 
-    locs.extend(flat_mow(&silhouette));
-    locs.extend(flat_silhouette(&silhouette)?);
+    locs.extend(flat_mow(&silhouette, diameter));
+    locs.extend(flat_silhouette(&silhouette, diameter).ok_or(GenerationError::NoSilhouette)?);
 
-    add_ending_locs(&mut locs);
+    add_ending_locs(&mut locs, safe);
+
+    let cutter = Cutter {
+        height: 4.0 * diameter,
+        diameter,
+        shape: CutterShape::Cylinder,
+    };
+    let floor = model.drop_cutter_block(&cutter, progress)?;
+    progress.update(2, 2)?;
 
-    Some(cncp::Program::from_locations(
+    Ok(program(
         locs,
-        Cutter {
-            height: CUTTER_HEIGHT_FLAT,
-            diameter: CUTTER_DIAMETER_FLAT,
-            shape: CutterShape::Cylinder,
-        },
+        cutter,
+        &model.job.entries,
+        Some(model.job.pocketing.direction),
+        uncut_stock(model.stock_height(), 0.5 * diameter),
+        block_heights(&floor),
+    ))
+}
+
+/// Clears the base around the silhouette with offsets of the block border and the silhouette
+pub fn flat_pocket(model: &Model, progress: &Progress) -> Result<cncp::Program, GenerationError> {
+    let safe = model.safe_height();
+    let diameter = model.job.cutters.flat;
+    let radius = 0.5 * diameter;
+
+    let silhouette = model.silhouette().ok_or(GenerationError::NoSilhouette)?;
+    progress.update(1, 2)?;
+    let island = silhouette
+        .points
+        .iter()
+        .map(|p| {
+            vector![
+                (p.point.x - PLANE_CENTER[0]) as f32 * MODEL_SCALE,
+                (p.point.z - PLANE_CENTER[2]) as f32 * MODEL_SCALE
+            ]
+        })
+        .collect();
+
+    // The cutter center can go up to the block border
+    let half = 0.5 * BLOCK_SIZE + radius;
+    let pocket = Pocket::new(vec![
+        vector![-half, -half],
+        vector![half, -half],
+        vector![half, half],
+        vector![-half, half],
+    ])
+    .with_island(island);
+
+    let paths = pocket.paths(radius, BASE_HEIGHT, safe, &model.job.pocketing);
+
+    let mut locs = initial_locations(safe);
+    extend_safe(&mut locs, paths, safe);
+    add_ending_locs(&mut locs, safe);
+
+    let cutter = Cutter {
+        height: 4.0 * diameter,
+        diameter,
+        shape: CutterShape::Cylinder,
+    };
+    let floor = model.drop_cutter_block(&cutter, progress)?;
+    progress.update(2, 2)?;
+
+    Ok(program(
+        locs,
+        cutter,
+        &model.job.entries,
+        Some(model.job.pocketing.direction),
+        uncut_stock(model.stock_height(), radius),
+        block_heights(&floor),
     ))
 }
 
-fn flat_mow(silhouette: &Intersection) -> Vec<Vector3<f32>> {
+fn flat_mow(silhouette: &Intersection, diameter: f32) -> Vec<Vector3<f32>> {
     let (bottom, top) = silhouette
         .points
         .iter()
@@ -214,28 +476,32 @@ fn flat_mow(silhouette: &Intersection) -> Vec<Vector3<f32>> {
             p.point.x - PLANE_CENTER[0] > 0.0
         });
 
-    let mut locs = flat_partition_paths(top, -1.0);
-    locs.extend(flat_partition_paths(bottom, 1.0).iter().rev());
+    let mut locs = flat_partition_paths(top, diameter, -1.0);
+    locs.extend(flat_partition_paths(bottom, diameter, 1.0).iter().rev());
     locs
 }
 
 fn flat_partition_paths(
     border: BTreeMap<NotNan<f64>, IntersectionPoint>,
+    diameter: f32,
     approach: f64,
 ) -> Vec<Vector3<f32>> {
+    let radius = 0.5 * diameter;
+    let eps = 0.1 * radius;
     let mut locs = Vec::new();
 
-    let mut y = (-BLOCK_SIZE * 0.5 - CUTTER_RADIUS_FLAT) as f64;
-    while y < (BLOCK_SIZE * 0.5 + CUTTER_RADIUS_FLAT) as f64 {
+    let mut y = (-BLOCK_SIZE * 0.5 - radius) as f64;
+    while y < (BLOCK_SIZE * 0.5 + radius) as f64 {
         flat_partition_path_pair(
             NotNan::new(y).unwrap(),
-            NotNan::new(y + (CUTTER_DIAMETER_FLAT - FLAT_EPS) as f64).unwrap(),
+            NotNan::new(y + (diameter - eps) as f64).unwrap(),
             &border,
             &mut locs,
+            diameter,
             NotNan::new(approach).unwrap(),
         );
 
-        y += (CUTTER_DIAMETER_FLAT - FLAT_EPS) as f64 * 2.0;
+        y += (diameter - eps) as f64 * 2.0;
     }
 
     locs
@@ -246,13 +512,15 @@ fn flat_partition_path_pair(
     y_limit: NotNan<f64>,
     border: &BTreeMap<NotNan<f64>, IntersectionPoint>,
     locs: &mut Vec<Vector3<f32>>,
+    diameter: f32,
     approach: NotNan<f64>,
 ) {
     const LIMIT_ACCURACY: usize = 10;
     // Do not touch the model while mowing the grass
     const CUTTER_SAFE_DISTANCE_MULTIPLIER: f32 = 1.1;
 
-    let x_start = *approach as f32 * (0.5 * BLOCK_SIZE + CUTTER_DIAMETER_FLAT);
+    let radius = 0.5 * diameter;
+    let x_start = *approach as f32 * (0.5 * BLOCK_SIZE + diameter);
 
     locs.push(vector![x_start, *y as f32, BASE_HEIGHT]);
 
@@ -261,9 +529,7 @@ fn flat_partition_path_pair(
         let y_interpol = y * (1.0 - t) + (y_limit) * t;
 
         let x_limit = border
-            .range(
-                (y_interpol - CUTTER_RADIUS_FLAT as f64)..(y_interpol + CUTTER_RADIUS_FLAT as f64),
-            )
+            .range((y_interpol - radius as f64)..(y_interpol + radius as f64))
             .map(|(_, p)| {
                 approach.as_f32()
                     * NotNan::new((p.point.x - PLANE_CENTER[0]) as f32 * MODEL_SCALE).unwrap()
@@ -271,7 +537,7 @@ fn flat_partition_path_pair(
             .max()
             .map(|p| approach.as_f32() * p)
             .unwrap_or(-NotNan::new(5.0).unwrap() * approach.as_f32())
-            + *approach as f32 * CUTTER_RADIUS_FLAT * CUTTER_SAFE_DISTANCE_MULTIPLIER;
+            + *approach as f32 * radius * CUTTER_SAFE_DISTANCE_MULTIPLIER;
 
         locs.push(vector![*x_limit, *y_interpol as f32, BASE_HEIGHT]);
     }
@@ -279,7 +545,7 @@ fn flat_partition_path_pair(
     locs.push(vector![x_start, *y_limit as f32, BASE_HEIGHT]);
 }
 
-fn flat_silhouette(silhouette: &Intersection) -> Option<Vec<Vector3<f32>>> {
+fn flat_silhouette(silhouette: &Intersection, diameter: f32) -> Option<Vec<Vector3<f32>>> {
     let len = silhouette.points.len();
     let mut locs = silhouette
         .points
@@ -289,85 +555,243 @@ fn flat_silhouette(silhouette: &Intersection) -> Option<Vec<Vector3<f32>>> {
         .skip(len / 2) // Model-specific things -- start from the other side
         .take(len + SAFE_CONTOUR_ADD) // make sure that the whole silhouette is cut with cutter moving
         .tuple_windows()
-        .filter_map(|(a, b)| cutter_at_inter_base::<false>(CUTTER_RADIUS_FLAT, a, b))
+        .filter_map(|(a, b)| cutter_at_inter_base::<false>(0.5 * diameter, a, b))
         .collect();
     clean_cutter_at_inter_base(&mut locs);
 
     Some(locs)
 }
 
-pub fn detail(model: &Model) -> cncp::Program {
-    const CUTTER_DIAMETER: f32 = 8.0;
-    const CUTTER_HEIGHT: f32 = 4.0 * CUTTER_DIAMETER;
+pub fn detail(model: &Model, progress: &Progress) -> Result<cncp::Program, GenerationError> {
+    const STEPS: usize = 4;
+
+    let safe = model.safe_height();
+    let diameter = model.job.cutters.detail;
+    let radius = model.detail_radius();
+
+    let cutter = Cutter {
+        height: 4.0 * diameter,
+        diameter,
+        shape: CutterShape::Ball,
+    };
 
     let start = std::time::Instant::now();
 
-    let mut locs = initial_locations();
-    std::thread::scope(|scope| {
-        let grill_thread = scope.spawn(|| grill(model));
-        let intersections = model.find_model_intersections();
-        let elevated_silhouette = model.elevated_silhouette().unwrap();
+    let mut segments = Vec::new();
+    let (stock, floor) = std::thread::scope(|scope| {
+        let grill_thread = scope.spawn(|| grill(model, progress));
+        let stock_thread = scope.spawn(|| rough_stock(model, progress));
+        let floor_thread = scope.spawn(|| model.drop_cutter_block(&cutter, progress));
+        let intersections = model.find_model_intersections()?;
+        let elevated_silhouette = model
+            .elevated_silhouette()
+            .ok_or(GenerationError::NoSilhouette)?;
+        progress.update(1, STEPS)?;
 
         std::thread::scope(|scope| {
-            let sand_thread = scope.spawn(|| sand(&intersections, model));
-            let inters_thread = scope.spawn(|| inters(&intersections, &elevated_silhouette));
+            let sand_thread = scope.spawn(|| sand(&intersections, model, progress));
+            let inters_thread =
+                scope.spawn(|| inters(&intersections, &elevated_silhouette, radius, progress));
+
+            let sand = sand_thread.join().unwrap()?;
+            segments.extend(sand);
+
+            let inters = inters_thread.join().unwrap()?;
+            segments.extend(inters);
+            Ok::<_, GenerationError>(())
+        })?;
+        progress.update(2, STEPS)?;
+
+        let grill = grill_thread.join().unwrap()?;
+        segments.extend(grill);
+
+        let stock = stock_thread.join().unwrap()?;
+        let floor = floor_thread.join().unwrap()?;
+        progress.update(3, STEPS)?;
+        Ok::<_, GenerationError>((stock, floor))
+    })?;
+
+    let mut locs = initial_locations(safe);
+    let linked = linking::link(segments, &locs[0], &stock, radius, safe, &model.job.linking);
+    locs.extend(linked);
+    progress.update(STEPS, STEPS)?;
+    let end = std::time::Instant::now();
 
-            let sand = sand_thread.join().unwrap();
-            locs.extend(sand);
+    add_ending_locs(&mut locs, safe);
 
-            let inters = inters_thread.join().unwrap();
-            locs.extend(inters);
-        });
+    println!("Time: {}", (end - start).as_secs_f32());
 
-        let grill = grill_thread.join().unwrap();
-        locs.extend(grill);
-    });
-    let end = std::time::Instant::now();
+    // The sanded surfaces have no material side
+    let stock_heights = block_heights(&stock);
+    Ok(program(
+        locs,
+        cutter,
+        &model.job.entries,
+        None,
+        |p, _| stock_heights(p),
+        block_heights(&floor),
+    ))
+}
 
-    add_ending_locs(&mut locs);
+/// Passes of the rest cutter over the material thicker than the threshold left in the `stock`
+/// simulated after the earlier programs
+pub fn rest(
+    model: &Model,
+    stock: &Block,
+    progress: &Progress,
+) -> Result<cncp::Program, GenerationError> {
+    let safe = model.safe_height();
+    let diameter = model.job.cutters.rest;
+    let cutter = Cutter {
+        height: 4.0 * diameter,
+        diameter,
+        shape: CutterShape::Ball,
+    };
 
-    println!("Time: {}", (end - start).as_secs_f32());
+    let target = model.drop_cutter_block(&cutter, progress)?;
+    let layers = rest::rest_layers(stock, &target, &cutter, &model.job.rest);
+    let count = layers.len();
+    progress.update(1, count + 1)?;
+
+    let mut locs = initial_locations(safe);
+    for (i, segments) in layers.into_iter().enumerate() {
+        let start = *locs.last().unwrap();
+        let linked = linking::link(
+            segments,
+            &start,
+            stock,
+            0.5 * diameter,
+            safe,
+            &model.job.linking,
+        );
+        locs.extend(linked);
+        progress.update(i + 2, count + 1)?;
+    }
+
+    add_ending_locs(&mut locs, safe);
 
-    cncp::Program::from_locations(
+    let stock_heights = block_heights(stock);
+    Ok(program(
         locs,
-        Cutter {
-            height: CUTTER_HEIGHT,
-            diameter: CUTTER_DIAMETER,
-            shape: CutterShape::Ball,
-        },
-    )
+        cutter,
+        &model.job.entries,
+        None,
+        |p, _| stock_heights(p),
+        block_heights(&target),
+    ))
 }
 
-fn grill(model: &Model) -> Vec<Vector3<f32>> {
-    let mut locs = Vec::new();
-    let holes = model.find_holes();
+/// Constant height loops around the parts for the steep walls, from the base upwards
+pub fn waterline(model: &Model, progress: &Progress) -> Result<cncp::Program, GenerationError> {
+    let top = model.stock_height();
+    let safe = top + SAFE_CLEARANCE;
+    let params = &model.job.waterline;
+    let diameter = model.job.cutters.detail;
+    let radius = model.detail_radius();
+
+    let mut levels = Vec::new();
+    let mut level = BASE_HEIGHT;
+    while level < top {
+        levels.push(level);
+        level += params.step_down;
+    }
 
-    for hole in holes.iter() {
-        let mut first_high = wrld_to_mod(&hole.points[0].point.xzy().coords);
-        first_high.z = SAFE_HEIGHT - 40.0;
-        locs.push(first_high);
+    let done = AtomicUsize::new(0);
+    let contours = levels
+        .par_iter()
+        .map(|&level| {
+            progress.check()?;
 
-        let contour = grill_contour(hole);
+            let height = ((level + radius - BASE_HEIGHT) / MODEL_SCALE) as f64 + PLANE_CENTER[1];
+            let contours = model.waterline(height);
 
-        locs.extend(grill_net(&contour));
-        locs.extend(
-            grill_net(&contour.iter().map(|p| p.yxz()).collect_vec())
-                .iter()
-                .map(|p| p.yxz()),
-        );
+            progress.update(done.fetch_add(1, Ordering::Relaxed) + 1, levels.len())?;
+            Ok(contours)
+        })
+        .collect::<Result<Vec<_>, GenerationError>>()?;
+
+    // Counterclockwise loops keep the model on the left of the cutter
+    let counterclockwise = params.direction.material_on_left();
+
+    let mut locs = initial_locations(safe);
+    let mut last: Option<Vector3<f32>> = None;
+    for contour in contours.into_iter().flatten() {
+        let mut path = contour
+            .iter()
+            .map(|p| wrld_to_mod(&p.coords) - vector![0.0, 0.0, radius])
+            .collect_vec();
+
+        let area: f32 = path
+            .iter()
+            .circular_tuple_windows()
+            .map(|(a, b)| a.x * b.y - b.x * a.y)
+            .sum();
+        if (area > 0.0) != counterclockwise {
+            path.reverse();
+        }
 
-        let mut first_high = *contour.first().unwrap();
-        first_high.z = SAFE_HEIGHT - 40.0;
-        locs.push(first_high);
+        let current = last.unwrap_or(vector![0.0, 0.0, safe]);
+        let start = path
+            .iter()
+            .position_min_by(|a, b| (*a - current).norm().total_cmp(&(*b - current).norm()))
+            .unwrap();
+        path.rotate_left(start);
+        path.push(path[0]);
+
+        // Neighbouring levels are linked directly along the wall
+        let linked = last.is_some_and(|last| (last.xy() - path[0].xy()).norm() <= diameter);
+        if !linked {
+            if let Some(last) = last {
+                locs.push(vector![last.x, last.y, safe]);
+            }
 
-        locs.extend(contour);
+            locs.push(vector![path[0].x, path[0].y, safe]);
+        }
 
-        let mut last_high = *locs.last().unwrap();
-        last_high.z = SAFE_HEIGHT - 40.0;
-        locs.push(last_high);
+        locs.extend(path);
+        last = locs.last().copied();
     }
 
-    locs
+    add_ending_locs(&mut locs, safe);
+
+    let cutter = Cutter {
+        height: 4.0 * diameter,
+        diameter,
+        shape: CutterShape::Ball,
+    };
+    let floor = model.drop_cutter_block(&cutter, progress)?;
+
+    Ok(program(
+        locs,
+        cutter,
+        &model.job.entries,
+        Some(params.direction),
+        uncut_stock(top, radius),
+        block_heights(&floor),
+    ))
+}
+
+/// Nets across the holes and their contours, separate segments for every hole
+fn grill(model: &Model, progress: &Progress) -> Result<Vec<Vec<Vector3<f32>>>, GenerationError> {
+    let mut segments = Vec::new();
+    let holes = model.find_holes()?;
+    let radius = model.detail_radius();
+
+    for hole in holes.iter() {
+        progress.check()?;
+        let contour = grill_contour(hole);
+
+        segments.push(grill_net(&contour, radius));
+        segments.push(
+            grill_net(&contour.iter().map(|p| p.yxz()).collect_vec(), radius)
+                .iter()
+                .map(|p| p.yxz())
+                .collect(),
+        );
+        segments.push(contour);
+    }
+
+    Ok(segments)
 }
 
 fn grill_contour(hole: &Intersection) -> Vec<Vector3<f32>> {
@@ -384,7 +808,7 @@ fn grill_contour(hole: &Intersection) -> Vec<Vector3<f32>> {
         .collect()
 }
 
-fn grill_net(contour: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
+fn grill_net(contour: &[Vector3<f32>], radius: f32) -> Vec<Vector3<f32>> {
     let mut locs = Vec::new();
 
     let x_map: BTreeMap<_, _> = contour
@@ -395,15 +819,11 @@ fn grill_net(contour: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
     let (&min_x, _) = x_map.first_key_value().unwrap();
     let (&max_x, _) = x_map.last_key_value().unwrap();
     let span = (min_x - max_x).abs();
-    let paths = (3.5 * span / CUTTER_RADIUS_DETAIL).ceil() as i32;
+    let paths = (3.5 * span / radius).ceil() as i32;
     let x_step = span / paths as f32;
 
     let mut x = min_x;
 
-    let [mut first_safe, _] = grill_point_pair(0, x, &x_map);
-    first_safe.z = SAFE_HEIGHT - 40.0;
-    locs.push(first_safe);
-
     for i in 0..paths {
         let points = grill_point_pair(i, x, &x_map);
         locs.extend(points);
@@ -411,10 +831,6 @@ fn grill_net(contour: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
         x += x_step;
     }
 
-    let mut last_safe = *locs.last().unwrap();
-    last_safe.z = SAFE_HEIGHT - 40.0;
-    locs.push(last_safe);
-
     locs
 }
 
@@ -450,256 +866,73 @@ fn grill_point_pair(
     }
 }
 
-fn sand(intersections: &[Intersection; INTERSECTIONS.len()], model: &Model) -> Vec<Vector3<f32>> {
-    let mut locs = Vec::new();
-
-    sand_shackle(Side::Left, intersections, model, &mut locs);
-    sand_shackle(Side::Right, intersections, model, &mut locs);
-    sand_shield(Side::Left, intersections, model, &mut locs);
-    sand_shield(Side::Right, intersections, model, &mut locs);
-    sand_screw(Side::Left, intersections, model, &mut locs);
-    sand_screw(Side::Right, intersections, model, &mut locs);
-    sand_body(intersections, model, &mut locs);
-
-    locs
-}
-
-fn extend_sand(locs: &mut Vec<Vector3<f32>>, extension: Vec<Vector3<f32>>) {
-    let Some(mut first_safe) = extension.first().copied() else {
-        return;
-    };
-    first_safe.z = SAFE_HEIGHT - 15.0;
-
-    let Some(mut last_safe) = extension.last().copied() else {
-        return;
-    };
-    last_safe.z = SAFE_HEIGHT - 15.0;
-
-    locs.push(first_safe);
-    locs.extend(extension);
-    locs.push(last_safe);
-}
-
-enum Side {
-    Left,
-    Right,
-}
-
-fn sand_shackle(
-    shackle: Side,
-    intersections: &[Intersection; INTERSECTIONS.len()],
-    model: &Model,
-    locs: &mut Vec<Vector3<f32>>,
-) {
-    const U_STEP: f64 = 0.012;
-    const V_STEP: f64 = 0.005;
-
-    let surface = match shackle {
-        Side::Left => model.surfaces[&LEFT_SHACKLE_ID].as_ref(),
-        Side::Right => model.surfaces[&RIGHT_SHACKLE_ID].as_ref(),
-    };
-
-    let inters = match shackle {
-        Side::Left => [
-            &intersections[LEFT_SHACKLE_INTERS[0]],
-            &intersections[LEFT_SHACKLE_INTERS[1]],
-        ],
-        Side::Right => [
-            &intersections[RIGHT_SHACKLE_INTERS[0]],
-            &intersections[RIGHT_SHACKLE_INTERS[1]],
-        ],
-    };
-
-    extend_sand(
-        locs,
-        sand_element(
-            &inters,
-            surface,
-            NotNan::new(U_STEP).unwrap(),
-            NotNan::new(V_STEP).unwrap(),
-            false,
-            (
-                -NotNan::new(f64::INFINITY).unwrap(),
-                NotNan::new(f64::INFINITY).unwrap(),
-            ),
-            (
-                -NotNan::new(f64::INFINITY).unwrap(),
-                NotNan::new(f64::INFINITY).unwrap(),
-            ),
-            BASE_HEIGHT + 15.0,
-            None,
-        ),
-    );
-}
-
-fn sand_shield(
-    shield: Side,
-    intersections: &[Intersection; INTERSECTIONS.len()],
+fn sand(
+    intersections: &[Intersection],
     model: &Model,
-    locs: &mut Vec<Vector3<f32>>,
-) {
-    const U_STEP: f64 = 0.017;
-    const V_STEP: f64 = 0.017;
-
-    let surface = match shield {
-        Side::Left => model.surfaces[&LEFT_SHIELD_ID].as_ref(),
-        Side::Right => model.surfaces[&RIGHT_SHIELD_ID].as_ref(),
-    };
+    progress: &Progress,
+) -> Result<Vec<Vec<Vector3<f32>>>, GenerationError> {
+    let mut segments = Vec::new();
 
-    let mut inverted = MaybeUninit::uninit();
-
-    let inters = match shield {
-        Side::Left => {
-            inverted.write(intersections[LEFT_SCREW_INTER].inverted());
-            [&intersections[LEFT_SHIELD_INTER], unsafe {
-                inverted.assume_init_ref()
-            }]
-        }
-        Side::Right => {
-            inverted.write(intersections[RIGHT_SCREW_INTER].inverted());
-            [&intersections[RIGHT_SHIELD_INTER], unsafe {
-                inverted.assume_init_ref()
-            }]
-        }
-    };
+    for (idx, part) in model.job.parts.iter().enumerate() {
+        sand_part(idx, part, intersections, model, progress, &mut segments)?;
+    }
 
-    extend_sand(
-        locs,
-        sand_element(
-            &inters,
-            surface,
-            NotNan::new(U_STEP).unwrap(),
-            NotNan::new(V_STEP).unwrap(),
-            false,
-            (
-                -NotNan::new(f64::INFINITY).unwrap(),
-                NotNan::new(f64::INFINITY).unwrap(),
-            ),
-            (
-                -NotNan::new(f64::INFINITY).unwrap(),
-                NotNan::new(0.51).unwrap(),
-            ),
-            SAFE_HEIGHT - 15.0,
-            None,
-        ),
-    );
-    extend_sand(
-        locs,
-        sand_element(
-            &inters,
-            surface,
-            NotNan::new(U_STEP).unwrap(),
-            NotNan::new(V_STEP).unwrap(),
-            false,
-            (
-                -NotNan::new(f64::INFINITY).unwrap(),
-                NotNan::new(f64::INFINITY).unwrap(),
-            ),
-            (
-                NotNan::new(0.49).unwrap(),
-                NotNan::new(f64::INFINITY).unwrap(),
-            ),
-            SAFE_HEIGHT - 15.0,
-            None,
-        ),
-    );
+    Ok(segments)
 }
 
-fn sand_screw(
-    screw: Side,
-    intersections: &[Intersection; INTERSECTIONS.len()],
+fn sand_part(
+    idx: usize,
+    part: &PartDescription,
+    intersections: &[Intersection],
     model: &Model,
-    locs: &mut Vec<Vector3<f32>>,
-) {
-    const U_STEP: f64 = 0.005;
-    const V_STEP: f64 = 0.005;
-
-    let surface = match screw {
-        Side::Left => model.surfaces[&LEFT_SCREW_ID].as_ref(),
-        Side::Right => model.surfaces[&RIGHT_SCREW_ID].as_ref(),
-    };
+    progress: &Progress,
+    segments: &mut Vec<Vec<Vector3<f32>>>,
+) -> Result<(), GenerationError> {
+    // Sanding works in the parameter space of `surface_1`, so the intersections where the part is
+    // the first surface have to be inverted
+    let inters = model
+        .job
+        .intersections
+        .iter()
+        .zip(intersections)
+        .filter_map(|(description, intersection)| {
+            if description.parts[1] == part.name {
+                Some(intersection.clone())
+            } else if description.parts[0] == part.name {
+                Some(intersection.inverted())
+            } else {
+                None
+            }
+        })
+        .collect_vec();
+    let inters = inters.iter().collect_vec();
 
-    let inters = match screw {
-        Side::Left => [&intersections[LEFT_SCREW_INTER]],
-        Side::Right => [&intersections[RIGHT_SCREW_INTER]],
-    };
+    let sanding = &part.sanding;
+    for region in &sanding.regions {
+        let (u_min, u_max) = region.u_bound();
+        let (v_min, v_max) = region.v_bound();
 
-    extend_sand(
-        locs,
-        sand_element(
+        segments.push(sand_element(
             &inters,
-            surface,
-            NotNan::new(U_STEP).unwrap(),
-            NotNan::new(V_STEP).unwrap(),
-            true,
-            (
-                -NotNan::new(f64::INFINITY).unwrap(),
-                NotNan::new(f64::INFINITY).unwrap(),
-            ),
-            (
-                -NotNan::new(f64::INFINITY).unwrap(),
-                NotNan::new(f64::INFINITY).unwrap(),
-            ),
-            SAFE_HEIGHT,
-            None,
-        ),
-    );
-}
-
-fn sand_body(
-    intersections: &[Intersection; INTERSECTIONS.len()],
-    model: &Model,
-    locs: &mut Vec<Vector3<f32>>,
-) {
-    const U_STEP: f64 = 0.005;
-    const V_STEP: f64 = 0.005;
-
-    let surface = model.surfaces[&BODY_ID].as_ref();
-
-    let i0 = intersections[LEFT_SHACKLE_INTERS[0]].inverted();
-    let i1 = intersections[LEFT_SHACKLE_INTERS[1]].inverted();
-    let i2 = intersections[RIGHT_SHACKLE_INTERS[0]].inverted();
-    let i3 = intersections[RIGHT_SHACKLE_INTERS[1]].inverted();
-    let i4 = intersections[LEFT_SHIELD_INTER].inverted();
-    let i5 = intersections[RIGHT_SHIELD_INTER].inverted();
-
-    let inters = [&i0, &i1, &i2, &i3, &i4, &i5];
-
-    let u_bounds = [0.0, 0.33, 0.66].map(|n| NotNan::new(n).unwrap());
-    let v_bounds = [
-        (0.0, 0.205),
-        (0.1975, 0.4025),
-        (0.3975, 0.6025),
-        (0.5975, 0.8025),
-        (0.7975, 1.0),
-    ]
-    .map(|(a, b)| (NotNan::new(a).unwrap(), NotNan::new(b).unwrap()));
-    let u_axes = [0.1, 0.255, 0.50, 0.745, 0.9];
-
-    for u_bound in u_bounds
-        .iter()
-        .copied()
-        .tuple_windows::<(NotNan<f64>, NotNan<f64>)>()
-    {
-        for (v_bound, u_axis) in v_bounds.iter().copied().zip(u_axes.iter()) {
-            extend_sand(
-                locs,
-                sand_element(
-                    &inters,
-                    surface,
-                    NotNan::new(U_STEP).unwrap(),
-                    NotNan::new(V_STEP).unwrap(),
-                    false,
-                    u_bound,
-                    v_bound,
-                    BASE_HEIGHT + 1.0,
-                    Some(*u_axis),
-                ),
-            );
-        }
+            model.surfaces[idx].as_ref(),
+            NotNan::new(sanding.u_step).unwrap(),
+            NotNan::new(sanding.v_step).unwrap(),
+            part.inverted,
+            (NotNan::new(u_min).unwrap(), NotNan::new(u_max).unwrap()),
+            (NotNan::new(v_min).unwrap(), NotNan::new(v_max).unwrap()),
+            sanding.safe_height,
+            region.u_axis,
+            model.detail_radius(),
+            sanding.scallop_height,
+            sanding.max_u_step.unwrap_or(f64::INFINITY),
+            progress,
+        )?);
     }
+
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn sand_element(
     inters: &[&Intersection],
     surface: &dyn DifferentialParametricForm<2, 3>,
@@ -710,14 +943,15 @@ fn sand_element(
     v_bound: (NotNan<f64>, NotNan<f64>),
     safe_break: f32,
     u_axis: Option<f64>,
-) -> Vec<Vector3<f32>> {
+    radius: f32,
+    scallop: Option<f32>,
+    max_u_step: f64,
+    progress: &Progress,
+) -> Result<Vec<Vector3<f32>>, GenerationError> {
     let mut locs = Vec::<Vector3<f32>>::new();
     let multiplier = if invert_surface { -1.0 } else { 1.0 };
 
-    let shifted_sufrace = ShiftedSurface::new(
-        surface,
-        multiplier * (CUTTER_RADIUS_DETAIL / MODEL_SCALE) as f64,
-    );
+    let shifted_sufrace = ShiftedSurface::new(surface, multiplier * (radius / MODEL_SCALE) as f64);
 
     let btree_u: BTreeMap<_, _> = inters
         .iter()
@@ -727,13 +961,22 @@ fn sand_element(
         .map(|p| (NotNan::new(p.surface_1.x).unwrap(), p))
         .collect();
 
+    // Bounds not given by the region nor by the intersections are the ones of the surface
+    let bounds = surface.bounds();
+    let surface_u = (
+        NotNan::new(bounds[0].0).unwrap(),
+        NotNan::new(bounds[0].1).unwrap(),
+    );
+    let v_low = (*v_bound.0).max(bounds[1].0);
+    let v_limits = (v_low, (*v_bound.1).min(bounds[1].1).max(v_low));
+
     let min_u = if u_bound.0.is_finite() {
         u_bound.0
     } else {
         *btree_u
             .first_key_value()
             .map(|p| p.0)
-            .unwrap_or(&u_bound.0)
+            .unwrap_or(&surface_u.0)
             .clamp(&u_bound.0, &u_bound.1)
     };
 
@@ -743,7 +986,7 @@ fn sand_element(
         *btree_u
             .last_key_value()
             .map(|p| p.0)
-            .unwrap_or(&u_bound.1)
+            .unwrap_or(&surface_u.1)
             .clamp(&u_bound.0, &u_bound.1)
     };
 
@@ -752,6 +995,8 @@ fn sand_element(
     let mut u = min_u + u_pillow;
     let mut reverse = false;
     while u <= max_u {
+        progress.check()?;
+
         let Some((min_v, max_v)) = min_max_v(u, u_step, &btree_u, v_bound, u_axis) else {
             u += u_step;
             continue;
@@ -759,13 +1004,15 @@ fn sand_element(
 
         let v_pillow = *v_step * 0.25;
 
-        let min_v = min_v.clamp(*v_bound.0, *v_bound.1) + v_pillow;
-        let max_v = max_v.clamp(*v_bound.0, *v_bound.1) - v_pillow;
+        let min_v = min_v.clamp(v_limits.0, v_limits.1) + v_pillow;
+        let max_v = max_v.clamp(v_limits.0, v_limits.1) - v_pillow;
 
+        let mut pass_v = Vec::new();
         let mut v = if !reverse { min_v } else { max_v };
         while min_v <= v && v <= max_v {
+            pass_v.push(v);
             let value = shifted_sufrace.value(&vector![*u, v]);
-            let mod_value = wrld_to_mod(&value.coords) - vector![0.0, 0.0, CUTTER_RADIUS_DETAIL];
+            let mod_value = wrld_to_mod(&value.coords) - vector![0.0, 0.0, radius];
 
             if mod_value.z < BASE_HEIGHT {
                 if !break_occured && locs.last().is_some() {
@@ -793,11 +1040,54 @@ fn sand_element(
             }
         }
 
-        u += u_step;
+        u += match scallop {
+            Some(scallop) => {
+                scallop_u_step(surface, *u, &pass_v, multiplier, radius, scallop).min(max_u_step)
+            }
+            None => *u_step,
+        };
         reverse = !reverse;
     }
 
-    locs
+    Ok(locs)
+}
+
+/// Largest `u` step from the pass at `u` which leaves scallops no higher than `scallop` between
+/// the passes of a ball cutter. `multiplier` points the surface normal towards the cutter.
+fn scallop_u_step(
+    surface: &dyn DifferentialParametricForm<2, 3>,
+    u: f64,
+    pass_v: &[f64],
+    multiplier: f64,
+    radius: f32,
+    scallop: f32,
+) -> f64 {
+    const DERIVATIVE_STEP: f64 = 1e-4;
+    const MIN_STEP: f64 = 1e-4;
+
+    let radius = (radius / MODEL_SCALE) as f64;
+    let scallop = (scallop / MODEL_SCALE) as f64;
+
+    pass_v
+        .iter()
+        .map(|&v| {
+            let jacobian = surface.jacobian(&vector![u, v]);
+            let su = jacobian.column(0).into_owned();
+            let normal = multiplier * su.cross(&jacobian.column(1)).normalize();
+            let suu = (surface.jacobian(&vector![u + DERIVATIVE_STEP, v]).column(0)
+                - surface.jacobian(&vector![u - DERIVATIVE_STEP, v]).column(0))
+                / (2.0 * DERIVATIVE_STEP);
+
+            // Normal curvature across the passes, positive where the surface bends away from the
+            // cutter and the scallops get higher
+            let curvature = -suu.dot(&normal) / su.norm_squared();
+            let effective = (1.0 / radius + curvature).max(f64::EPSILON);
+            let stepover = (8.0 * scallop / effective).sqrt();
+
+            stepover / su.norm()
+        })
+        .fold(f64::INFINITY, f64::min)
+        .max(MIN_STEP)
 }
 
 fn min_max_v(
@@ -867,16 +1157,22 @@ fn wrld_to_mod(vec: &Vector3<f64>) -> Vector3<f32> {
 }
 
 fn inters(
-    intersections: &[Intersection; INTERSECTIONS.len()],
+    intersections: &[Intersection],
     _elevated_silhouette: &Intersection,
-) -> Vec<Vector3<f32>> {
-    let mut locs = Vec::new();
+    radius: f32,
+    progress: &Progress,
+) -> Result<Vec<Vec<Vector3<f32>>>, GenerationError> {
+    let mut segments = Vec::new();
+
+    for intersection in intersections.iter()
+    /*.chain([elevated_silhouette])*/
+    {
+        progress.check()?;
 
-    for intersection in intersections.iter()/*.chain([elevated_silhouette])*/ {
         let mut initial_locs = intersection
             .points
             .iter()
-            .map(|p| wrld_to_mod(&p.point.coords) - vector![0.0, 0.0, CUTTER_RADIUS_DETAIL])
+            .map(|p| wrld_to_mod(&p.point.coords) - vector![0.0, 0.0, radius])
             .collect_vec();
 
         if let Some(first_under) = initial_locs.iter().position(|p| p.z < BASE_HEIGHT) {
@@ -898,18 +1194,10 @@ fn inters(
             }
         }
 
-        let mut first_safe = *initial_locs.first().unwrap();
-        first_safe.z = SAFE_HEIGHT;
-        locs.push(first_safe);
-
-        locs.extend(initial_locs);
-
-        let mut last_safe = *locs.last().unwrap();
-        last_safe.z = SAFE_HEIGHT;
-        locs.push(last_safe);
+        segments.push(initial_locs);
     }
 
-    locs
+    Ok(segments)
 }
 
 fn cutter_at_inter_base<const INV_NORM: bool>(
@@ -983,32 +1271,174 @@ fn round_vec(vec: &Vector3<f32>) -> Vector2<i32> {
 pub fn signa() -> cncp::Program {
     const CUTTER_DIAMETER: f32 = 1.0;
     const CUTTER_HEIGHT: f32 = 4.0 * CUTTER_DIAMETER;
-    const TEXT: &str = "szymon\n\rzygul\x08{a";
-    const POS: Vector3<f32> = vector![50.0, -60.0, 0.0];
+    const TEXT: &str = "szymon\nzyguła";
+
+    let engraving = Engraving {
+        text: String::from(TEXT),
+        size: 10.0,
+        start: vector![55.0, -65.0, BASE_HEIGHT],
+        baseline: vector![0.0, 1.0],
+        depth: 1.0,
+    };
 
-    let mut locs = initial_locations();
+    let mut locs = initial_locations(SAFE_HEIGHT);
 
-    locs.extend(svg::parse_signature(TEXT, &POS));
+    locs.extend(
+        engraving
+            .paths(&StrokeFont::segment())
+            .expect("The segment font has all letters of the signature"),
+    );
 
-    add_ending_locs(&mut locs);
+    add_ending_locs(&mut locs, SAFE_HEIGHT);
 
-    cncp::Program::from_locations(
+    program(
         locs,
         Cutter {
             height: CUTTER_HEIGHT,
             diameter: CUTTER_DIAMETER,
             shape: CutterShape::Ball,
         },
+        &EntryParameters::default(),
+        None,
+        |_, _| BASE_HEIGHT,
+        no_floor,
     )
 }
 
-fn initial_locations() -> Vec<Vector3<f32>> {
-    vec![vector![0.0, 0.0, 66.0]]
+/// Texts listed in the job engraved one after another
+pub fn engrave(model: &Model) -> Result<cncp::Program, EngravingError> {
+    let safe = model.safe_height();
+    let diameter = model.job.cutters.engraving;
+    let segment = StrokeFont::segment();
+
+    let mut locs = initial_locations(safe);
+
+    for description in &model.job.engravings {
+        let font = description
+            .font
+            .as_ref()
+            .map(|path| StrokeFont::load(std::path::Path::new(path)))
+            .transpose()?;
+
+        let [x, y, z] = description.start;
+        let angle = description.angle.to_radians();
+        let engraving = Engraving {
+            text: description.text.clone(),
+            size: description.size,
+            start: vector![x, y, z],
+            baseline: vector![angle.cos(), angle.sin()],
+            depth: description.depth,
+        };
+
+        extend_safe(
+            &mut locs,
+            engraving.paths(font.as_ref().unwrap_or(&segment))?,
+            safe,
+        );
+    }
+
+    add_ending_locs(&mut locs, safe);
+
+    // The texts are cut into the surfaces at their start heights
+    let top = model
+        .job
+        .engravings
+        .iter()
+        .map(|description| description.start[2])
+        .fold(f32::NEG_INFINITY, f32::max);
+
+    Ok(program(
+        locs,
+        Cutter {
+            height: 4.0 * diameter,
+            diameter,
+            shape: CutterShape::Ball,
+        },
+        &model.job.entries,
+        None,
+        |_, _| top,
+        no_floor,
+    ))
+}
+
+/// Program for every SVG file listed in the job
+pub fn svg_programs(
+    model: &Model,
+    progress: &Progress,
+) -> Result<Vec<cncp::Program>, GenerationError> {
+    let safe = model.safe_height();
+    model
+        .job
+        .svgs
+        .iter()
+        .map(|description| {
+            progress.check()?;
+
+            let [x, y] = description.offset;
+            let placement = SvgPlacement {
+                scale: description.scale,
+                rotation: description.rotation,
+                offset: vector![x, y],
+            };
+
+            let paths = svg::load(std::path::Path::new(&description.file), &placement)
+                .map_err(GenerationError::Svg)?;
+
+            let mut locs = initial_locations(safe);
+            extend_safe(
+                &mut locs,
+                svg::toolpaths(
+                    &paths,
+                    &placement,
+                    description.operation,
+                    0.5 * description.diameter,
+                    description.height,
+                    description.depth,
+                    description.step_down,
+                    safe,
+                    &model.job.pocketing,
+                ),
+                safe,
+            );
+            add_ending_locs(&mut locs, safe);
+
+            let shape = match description.operation {
+                SvgOperation::Engrave => CutterShape::Ball,
+                _ => CutterShape::Cylinder,
+            };
+
+            // The passes are entered where the level above cut the stock
+            let stock = |_: &Vector2<f32>, z: f32| {
+                description.step_down.map_or(description.height, |step| {
+                    (z + step).min(description.height)
+                })
+            };
+
+            Ok(program(
+                locs,
+                Cutter {
+                    height: 4.0 * description.diameter,
+                    diameter: description.diameter,
+                    shape,
+                },
+                &model.job.entries,
+                // The engraved paths have no material side
+                (description.operation != SvgOperation::Engrave)
+                    .then_some(model.job.pocketing.direction),
+                stock,
+                no_floor,
+            ))
+        })
+        .collect()
+}
+
+fn initial_locations(safe: f32) -> Vec<Vector3<f32>> {
+    vec![vector![0.0, 0.0, safe]]
 }
 
-fn add_ending_locs(locs: &mut Vec<Vector3<f32>>) {
-    let mut safe = *locs.last().unwrap();
-    safe.z = SAFE_HEIGHT;
-    locs.push(safe);
-    locs.push(vector![0.0, 0.0, SAFE_HEIGHT]);
+fn add_ending_locs(locs: &mut Vec<Vector3<f32>>, safe: f32) {
+    let mut last = *locs.last().unwrap();
+    last.z = safe;
+    locs.push(last);
+    locs.push(vector![0.0, 0.0, safe]);
 }
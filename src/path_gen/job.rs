@@ -0,0 +1,629 @@
+use nalgebra::{point, Point3};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+
+/// Part of the model milled by the detail paths
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PartDescription {
+    /// Name used to refer to the part in the job description
+    pub name: String,
+    /// Name of the surface entity in the scene
+    pub surface: String,
+    /// Whether the surface normals point into the material, so that the cutter has to be offset
+    /// the other way. Intersections may override the side.
+    #[serde(default)]
+    pub inverted: bool,
+    pub sanding: SandingDescription,
+}
+
+/// Parameter space zig-zag over the part surface bounded by its intersections with other parts
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SandingDescription {
+    pub u_step: f64,
+    pub v_step: f64,
+    /// Height to which the cutter is raised when the path goes under the base
+    pub safe_height: f32,
+    /// Largest height of the scallops left between the passes in mm. When given, the passes are
+    /// spaced by the surface curvature instead of `u_step`.
+    #[serde(default)]
+    pub scallop_height: Option<f32>,
+    /// Largest parameter distance between the passes spaced by the scallop height, unlimited if
+    /// not given
+    #[serde(default)]
+    pub max_u_step: Option<f64>,
+    /// Parameter space regions sanded separately, the whole surface by default
+    #[serde(default = "SandingDescription::default_regions")]
+    pub regions: Vec<SandingRegion>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SandingRegion {
+    #[serde(default)]
+    pub u_min: Option<f64>,
+    #[serde(default)]
+    pub u_max: Option<f64>,
+    #[serde(default)]
+    pub v_min: Option<f64>,
+    #[serde(default)]
+    pub v_max: Option<f64>,
+    /// `u` separating the intersection points bounding the region from below and above, the
+    /// average of the nearby points is used if not given
+    #[serde(default)]
+    pub u_axis: Option<f64>,
+}
+
+/// Side of a part surface, relative to its normals, on which the cutter center is offset
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Side {
+    /// Along the normals
+    Outside,
+    /// Against the normals
+    Inside,
+}
+
+impl Side {
+    /// Sign of the offset of the part surface
+    pub fn sign(self) -> f64 {
+        match self {
+            Side::Outside => 1.0,
+            Side::Inside => -1.0,
+        }
+    }
+}
+
+/// Intersection of two parts, found with the guide point in the scene coordinates
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IntersectionDescription {
+    pub parts: [String; 2],
+    pub guide: [f64; 3],
+    /// Sides of the parts intersected, given by whether the parts are inverted if not given
+    #[serde(default)]
+    pub sides: Option<[Side; 2]>,
+}
+
+/// Hole between `part` and `around` on the base plane, found with the guide point
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HoleDescription {
+    pub part: String,
+    pub around: String,
+    pub guide: [f64; 3],
+}
+
+/// Diameters of the cutters in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CutterChoice {
+    pub rough: f32,
+    pub flat: f32,
+    pub detail: f32,
+    #[serde(default = "CutterChoice::default_engraving")]
+    pub engraving: f32,
+    #[serde(default = "CutterChoice::default_rest")]
+    pub rest: f32,
+}
+
+impl Default for CutterChoice {
+    fn default() -> Self {
+        Self {
+            rough: 16.0,
+            flat: 10.0,
+            detail: 8.0,
+            engraving: Self::default_engraving(),
+            rest: Self::default_rest(),
+        }
+    }
+}
+
+impl CutterChoice {
+    fn default_engraving() -> f32 {
+        1.0
+    }
+
+    fn default_rest() -> f32 {
+        8.0
+    }
+}
+
+/// Side of the cutter on which the uncut material is left, assuming a clockwise turning spindle
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MillingDirection {
+    /// Material on the right of the cutter, so outside profiles go clockwise and pockets
+    /// counterclockwise
+    Climb,
+    /// Material on the left of the cutter
+    Conventional,
+}
+
+impl MillingDirection {
+    /// Whether the uncut material is on the left of the cutter moving along the path
+    pub fn material_on_left(self) -> bool {
+        match self {
+            MillingDirection::Climb => false,
+            MillingDirection::Conventional => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RoughingPattern {
+    /// Parallel lines joined at alternating ends
+    ZigZag,
+    /// Parallel lines cut in the same direction with a retract between them
+    OneWay,
+    /// Rectangles shrinking towards the middle of the block
+    Spiral,
+    /// Zig-zag lines followed with trochoidal loops wherever going straight would engage the
+    /// cutter by more than the allowed angle
+    Adaptive,
+}
+
+/// Z-level roughing, distances in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RoughingParameters {
+    /// Distance between consecutive levels
+    pub step_down: f32,
+    /// Distance between neighbouring passes of a level
+    pub stepover: f32,
+    /// Material left above the model
+    pub allowance: f32,
+    /// Height of the lowest level, the material below is left for the flat paths
+    pub min_height: f32,
+    pub direction: MillingDirection,
+    pub pattern: RoughingPattern,
+    /// Largest angle of the cutter edge in the material for the adaptive pattern, in degrees
+    #[serde(default = "RoughingParameters::default_max_engagement")]
+    pub max_engagement: f32,
+}
+
+impl Default for RoughingParameters {
+    fn default() -> Self {
+        Self {
+            step_down: 15.0,
+            stepover: 8.0,
+            allowance: 0.0,
+            min_height: 20.0,
+            direction: MillingDirection::Conventional,
+            pattern: RoughingPattern::Adaptive,
+            max_engagement: Self::default_max_engagement(),
+        }
+    }
+}
+
+impl RoughingParameters {
+    fn default_max_engagement() -> f32 {
+        90.0
+    }
+}
+
+/// Contour-parallel clearing, distances in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PocketingParameters {
+    /// Distance between neighbouring offsets
+    pub stepover: f32,
+    pub direction: MillingDirection,
+    /// Whether to start in the middle of the pocket instead of at its walls
+    pub inside_out: bool,
+}
+
+impl Default for PocketingParameters {
+    fn default() -> Self {
+        Self {
+            stepover: 8.0,
+            direction: MillingDirection::Climb,
+            inside_out: false,
+        }
+    }
+}
+
+/// Constant height finishing around the parts, distances in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WaterlineParameters {
+    /// Distance between consecutive levels
+    pub step_down: f32,
+    pub direction: MillingDirection,
+}
+
+impl Default for WaterlineParameters {
+    fn default() -> Self {
+        Self {
+            step_down: 1.0,
+            direction: MillingDirection::Climb,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LeadKind {
+    None,
+    /// Straight line continuing the pass
+    Line,
+    /// Quarter of a circle tangent to the pass
+    Arc,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RampKind {
+    /// Vertical plunge, only for the ball cutters
+    None,
+    /// Back and forth along the entry line
+    ZigZag,
+    /// Helix ending at the entry point
+    Helix,
+}
+
+/// Moves entering and leaving the passes, distances in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryParameters {
+    pub lead_in: LeadKind,
+    pub lead_out: LeadKind,
+    /// Length of the line leads and radius of the arc leads
+    pub lead_length: f32,
+    pub ramp: RampKind,
+    /// Angle of the ramp from the horizontal in degrees
+    pub ramp_angle: f32,
+    /// Least height above the pass from which the cutter ramps down, it ramps from the top of
+    /// the stock when that is higher and plunges down in the air
+    pub ramp_height: f32,
+    /// Length of the zig-zag legs and radius of the helix
+    pub ramp_length: f32,
+}
+
+impl Default for EntryParameters {
+    fn default() -> Self {
+        Self {
+            lead_in: LeadKind::None,
+            lead_out: LeadKind::None,
+            lead_length: 2.0,
+            ramp: RampKind::None,
+            ramp_angle: 5.0,
+            ramp_height: 2.0,
+            ramp_length: 5.0,
+        }
+    }
+}
+
+/// Machining of the material left by the earlier programs with the rest cutter, distances in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RestParameters {
+    /// Thinnest leftover material which is machined
+    pub threshold: f32,
+    /// Distance between neighbouring passes
+    pub stepover: f32,
+    /// Largest thickness of the material removed by a single pass
+    pub step_down: f32,
+}
+
+impl Default for RestParameters {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            stepover: 2.0,
+            step_down: 2.0,
+        }
+    }
+}
+
+/// Order of the detail segments and the air moves between them, distances in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkingParameters {
+    /// Whether to reorder the segments to shorten the air moves, they are cut in the order they
+    /// are generated in otherwise
+    pub reorder: bool,
+    /// Whether the segments may be cut backwards
+    pub reverse: bool,
+    /// Height of the air moves above the stock left by roughing
+    pub clearance: f32,
+}
+
+impl Default for LinkingParameters {
+    fn default() -> Self {
+        Self {
+            reorder: true,
+            reverse: true,
+            clearance: 2.0,
+        }
+    }
+}
+
+/// Text engraved with the engraving cutter, distances in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EngravingDescription {
+    pub text: String,
+    /// Height of the capitals
+    pub size: f32,
+    /// Left end of the first baseline on the engraved surface, in mill coordinates
+    pub start: [f32; 3],
+    /// Angle of the baseline from the X axis in degrees
+    #[serde(default)]
+    pub angle: f32,
+    pub depth: f32,
+    /// Hershey `.jhf` font file, the built-in segment font is used if not given
+    #[serde(default)]
+    pub font: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SvgOperation {
+    /// Cutter center following the paths
+    Engrave,
+    /// Cutter side going around the closed paths from the outside
+    ProfileOutside,
+    /// Cutter side going around the closed paths from the inside
+    ProfileInside,
+    /// Contour-parallel clearing of the closed paths with the nested ones as islands
+    Pocket,
+}
+
+/// Paths of an SVG file cut into a flat surface, distances in mm
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SvgDescription {
+    pub file: String,
+    /// Millimeters per SVG user unit
+    #[serde(default = "SvgDescription::default_scale")]
+    pub scale: f32,
+    /// Counterclockwise rotation in degrees
+    #[serde(default)]
+    pub rotation: f32,
+    /// Position of the SVG origin on the stock
+    #[serde(default)]
+    pub offset: [f32; 2],
+    /// Height of the surface cut into
+    pub height: f32,
+    pub depth: f32,
+    /// Largest depth of a single pass of the profiles and the pocket, the whole depth is cut at
+    /// once if not given
+    #[serde(default)]
+    pub step_down: Option<f32>,
+    pub operation: SvgOperation,
+    /// Diameter of the cutter, ball for engraving and flat otherwise
+    pub diameter: f32,
+}
+
+/// Description of a model from which the paths are generated
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JobDescription {
+    pub parts: Vec<PartDescription>,
+    #[serde(default)]
+    pub intersections: Vec<IntersectionDescription>,
+    #[serde(default)]
+    pub holes: Vec<HoleDescription>,
+    /// Parts touching the base plane which make up the model silhouette, the first one is
+    /// followed around by the flat paths
+    pub silhouette: Vec<String>,
+    #[serde(default = "JobDescription::default_silhouette_guide")]
+    pub silhouette_guide: [f64; 3],
+    #[serde(default)]
+    pub cutters: CutterChoice,
+    #[serde(default)]
+    pub roughing: RoughingParameters,
+    #[serde(default)]
+    pub pocketing: PocketingParameters,
+    #[serde(default)]
+    pub waterline: WaterlineParameters,
+    #[serde(default)]
+    pub entries: EntryParameters,
+    #[serde(default)]
+    pub linking: LinkingParameters,
+    #[serde(default)]
+    pub rest: RestParameters,
+    #[serde(default)]
+    pub engravings: Vec<EngravingDescription>,
+    #[serde(default)]
+    pub svgs: Vec<SvgDescription>,
+}
+
+#[derive(Error, Debug)]
+pub enum JobError {
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+    #[error("job description error: {0}")]
+    Json(serde_json::Error),
+    #[error("part \"{0}\" is not described")]
+    UnknownPart(String),
+    #[error("surface \"{0}\" not found in the scene")]
+    MissingSurface(String),
+    #[error("no job description loaded")]
+    NotLoaded,
+    #[error("sanding steps of part \"{0}\" have to be positive and its region bounds ordered")]
+    InvalidSanding(String),
+    #[error("cutter diameters have to be positive")]
+    InvalidCutters,
+    #[error(
+        "roughing step-down and stepover have to be positive, engagement between 0 and 180 degrees"
+    )]
+    InvalidRoughing,
+    #[error("pocketing stepover has to be positive")]
+    InvalidPocketing,
+    #[error("waterline step-down has to be positive")]
+    InvalidWaterline,
+    #[error("lead and ramp lengths have to be positive, ramp angle between 0 and 90 degrees")]
+    InvalidEntries,
+    #[error("linking clearance has to be positive")]
+    InvalidLinking,
+    #[error("rest machining threshold, stepover and step-down have to be positive")]
+    InvalidRest,
+    #[error("engraving size and depth have to be positive")]
+    InvalidEngraving,
+    #[error("SVG scale, depth, step-down and cutter diameter have to be positive")]
+    InvalidSvg,
+}
+
+impl SandingDescription {
+    fn default_regions() -> Vec<SandingRegion> {
+        vec![SandingRegion::default()]
+    }
+}
+
+impl SandingRegion {
+    pub fn u_bound(&self) -> (f64, f64) {
+        (
+            self.u_min.unwrap_or(-f64::INFINITY),
+            self.u_max.unwrap_or(f64::INFINITY),
+        )
+    }
+
+    pub fn v_bound(&self) -> (f64, f64) {
+        (
+            self.v_min.unwrap_or(-f64::INFINITY),
+            self.v_max.unwrap_or(f64::INFINITY),
+        )
+    }
+}
+
+impl SvgDescription {
+    fn default_scale() -> f32 {
+        1.0
+    }
+}
+
+impl JobDescription {
+    fn default_silhouette_guide() -> [f64; 3] {
+        [-2.0, 0.0, 2.5]
+    }
+
+    pub fn load(path: &Path) -> Result<Self, JobError> {
+        let json = std::fs::read_to_string(path).map_err(JobError::Io)?;
+        let job: Self = serde_json::from_str(&json).map_err(JobError::Json)?;
+        job.validate()?;
+        Ok(job)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), JobError> {
+        let json = serde_json::to_string_pretty(self).map_err(JobError::Json)?;
+        std::fs::write(path, json).map_err(JobError::Io)
+    }
+
+    /// Checks that all parts referred to are described and the parameters are usable
+    pub fn validate(&self) -> Result<(), JobError> {
+        let names = self
+            .intersections
+            .iter()
+            .flat_map(|i| i.parts.iter())
+            .chain(self.holes.iter().flat_map(|h| [&h.part, &h.around]))
+            .chain(self.silhouette.iter());
+
+        for name in names {
+            self.part_idx(name)?;
+        }
+
+        for part in &self.parts {
+            let sanding = &part.sanding;
+            if sanding.u_step <= 0.0
+                || sanding.v_step <= 0.0
+                || sanding.scallop_height.is_some_and(|h| h <= 0.0)
+                || sanding.max_u_step.is_some_and(|step| step <= 0.0)
+                || sanding.regions.iter().any(|region| {
+                    let (u_min, u_max) = region.u_bound();
+                    let (v_min, v_max) = region.v_bound();
+                    u_min > u_max || v_min > v_max
+                })
+            {
+                return Err(JobError::InvalidSanding(part.name.clone()));
+            }
+        }
+
+        let cutters = &self.cutters;
+        if [
+            cutters.rough,
+            cutters.flat,
+            cutters.detail,
+            cutters.engraving,
+            cutters.rest,
+        ]
+        .iter()
+        .any(|&diameter| diameter <= 0.0)
+        {
+            return Err(JobError::InvalidCutters);
+        }
+
+        let roughing = &self.roughing;
+        if roughing.step_down <= 0.0
+            || roughing.stepover <= 0.0
+            || !(roughing.max_engagement > 0.0 && roughing.max_engagement <= 180.0)
+        {
+            return Err(JobError::InvalidRoughing);
+        }
+
+        if self.pocketing.stepover <= 0.0 {
+            return Err(JobError::InvalidPocketing);
+        }
+
+        if self.waterline.step_down <= 0.0 {
+            return Err(JobError::InvalidWaterline);
+        }
+
+        let entries = &self.entries;
+        if entries.lead_length <= 0.0
+            || entries.ramp_height <= 0.0
+            || entries.ramp_length <= 0.0
+            || !(entries.ramp_angle > 0.0 && entries.ramp_angle < 90.0)
+        {
+            return Err(JobError::InvalidEntries);
+        }
+
+        if self.linking.clearance <= 0.0 {
+            return Err(JobError::InvalidLinking);
+        }
+
+        let rest = &self.rest;
+        if rest.threshold <= 0.0 || rest.stepover <= 0.0 || rest.step_down <= 0.0 {
+            return Err(JobError::InvalidRest);
+        }
+
+        if self
+            .engravings
+            .iter()
+            .any(|e| e.size <= 0.0 || e.depth <= 0.0)
+        {
+            return Err(JobError::InvalidEngraving);
+        }
+
+        if self.svgs.iter().any(|s| {
+            s.scale <= 0.0
+                || s.depth <= 0.0
+                || s.diameter <= 0.0
+                || s.step_down.is_some_and(|step| step <= 0.0)
+        }) {
+            return Err(JobError::InvalidSvg);
+        }
+
+        Ok(())
+    }
+
+    pub fn part_idx(&self, name: &str) -> Result<usize, JobError> {
+        self.parts
+            .iter()
+            .position(|p| p.name == name)
+            .ok_or_else(|| JobError::UnknownPart(String::from(name)))
+    }
+
+    pub fn silhouette_guide(&self) -> Point3<f64> {
+        let [x, y, z] = self.silhouette_guide;
+        point![x, y, z]
+    }
+}
+
+pub fn guide_point(guide: &[f64; 3]) -> Point3<f64> {
+    point![guide[0], guide[1], guide[2]]
+}
@@ -0,0 +1,174 @@
+use super::job::LinkingParameters;
+use crate::cnc::block::Block;
+use nalgebra::{vector, Vector2, Vector3};
+
+/// Passes of the 2-opt improvement done at most
+const MAX_PASSES: usize = 64;
+
+/// Joins the `segments`, each cut without leaving the material, with air moves starting from
+/// `start`. The moves go just above the `heightmap` of the stock where it is lower than `safe`.
+pub fn link(
+    segments: Vec<Vec<Vector3<f32>>>,
+    start: &Vector3<f32>,
+    heightmap: &Block,
+    radius: f32,
+    safe: f32,
+    params: &LinkingParameters,
+) -> Vec<Vector3<f32>> {
+    let mut segments: Vec<_> = segments.into_iter().filter(|s| !s.is_empty()).collect();
+
+    let order = if params.reorder {
+        let mut order = nearest_neighbour(&segments, start, params.reverse);
+        if params.reverse {
+            two_opt(&segments, start, &mut order);
+        }
+        order
+    } else {
+        (0..segments.len()).map(|i| (i, false)).collect()
+    };
+
+    let mut locs = Vec::new();
+    let mut current = *start;
+    for (idx, reversed) in order {
+        let mut segment = std::mem::take(&mut segments[idx]);
+        if reversed {
+            segment.reverse();
+        }
+
+        locs.extend(link_move(
+            &current,
+            &segment[0],
+            heightmap,
+            radius,
+            safe,
+            params,
+        ));
+        current = *segment.last().unwrap();
+        locs.extend(segment);
+    }
+
+    locs
+}
+
+fn ends(segment: &[Vector3<f32>], reversed: bool) -> (Vector3<f32>, Vector3<f32>) {
+    let (first, last) = (segment[0], segment[segment.len() - 1]);
+    if reversed {
+        (last, first)
+    } else {
+        (first, last)
+    }
+}
+
+/// Greedy order always going to the closest end of the segments left
+fn nearest_neighbour(
+    segments: &[Vec<Vector3<f32>>],
+    start: &Vector3<f32>,
+    reverse: bool,
+) -> Vec<(usize, bool)> {
+    let mut left: Vec<usize> = (0..segments.len()).collect();
+    let mut order = Vec::with_capacity(segments.len());
+    let mut current = *start;
+
+    while !left.is_empty() {
+        let (pos, reversed, _) = left
+            .iter()
+            .enumerate()
+            .flat_map(|(pos, &idx)| {
+                let directions: &[bool] = if reverse { &[false, true] } else { &[false] };
+                directions.iter().map(move |&reversed| {
+                    let (first, _) = ends(&segments[idx], reversed);
+                    (pos, reversed, (first - current).norm())
+                })
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .unwrap();
+
+        let idx = left.swap_remove(pos);
+        current = ends(&segments[idx], reversed).1;
+        order.push((idx, reversed));
+    }
+
+    order
+}
+
+/// Reverses the runs of the order, together with the directions of their segments, as long as
+/// it shortens the air moves
+fn two_opt(segments: &[Vec<Vector3<f32>>], start: &Vector3<f32>, order: &mut [(usize, bool)]) {
+    let first = |(idx, reversed): (usize, bool)| ends(&segments[idx], reversed).0;
+    let last = |(idx, reversed): (usize, bool)| ends(&segments[idx], reversed).1;
+
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+
+        for i in 0..order.len() {
+            for j in i..order.len() {
+                let before = if i == 0 { *start } else { last(order[i - 1]) };
+                let after = order.get(j + 1).map(|&next| first(next));
+
+                let old = (first(order[i]) - before).norm()
+                    + after.map_or(0.0, |after| (after - last(order[j])).norm());
+                // The reversed run starts at the old end of its last segment
+                let new = (last(order[j]) - before).norm()
+                    + after.map_or(0.0, |after| (after - first(order[i])).norm());
+
+                if new < old - 1e-4 {
+                    order[i..=j].reverse();
+                    order[i..=j].iter_mut().for_each(|(_, r)| *r = !*r);
+                    improved = true;
+                }
+            }
+        }
+
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// Air move from `from` to above `to`, as low as the stock under the cutter allows
+fn link_move(
+    from: &Vector3<f32>,
+    to: &Vector3<f32>,
+    heightmap: &Block,
+    radius: f32,
+    safe: f32,
+    params: &LinkingParameters,
+) -> Vec<Vector3<f32>> {
+    let height = (clear_height(&from.xy(), &to.xy(), heightmap, radius) + params.clearance)
+        .max(from.z.max(to.z) + params.clearance)
+        .min(safe.max(from.z));
+
+    let mut locs = Vec::with_capacity(2);
+    if height != from.z {
+        locs.push(vector![from.x, from.y, height]);
+    }
+    locs.push(vector![to.x, to.y, height]);
+    locs
+}
+
+/// Highest stock under the cutter moving along the line from `from` to `to`
+fn clear_height(from: &Vector2<f32>, to: &Vector2<f32>, heightmap: &Block, radius: f32) -> f32 {
+    let step = heightmap.sample_size().min();
+    let steps = ((to - from).norm() / step).ceil() as usize;
+    let reach = (radius / step).ceil() as i32;
+
+    let mut height = -f32::INFINITY;
+    for s in 0..=steps {
+        let center = from.lerp(to, s as f32 / steps.max(1) as f32);
+
+        for x in -reach..=reach {
+            for y in -reach..=reach {
+                let offset = vector![x as f32, y as f32] * step;
+                if offset.norm() > radius {
+                    continue;
+                }
+
+                if let Some(h) = heightmap.height_at(&(center + offset)) {
+                    height = height.max(h);
+                }
+            }
+        }
+    }
+
+    height
+}
@@ -1,4 +1,12 @@
-pub mod model;
+pub mod adaptive;
+pub mod drop_cutter;
+pub mod engraving;
+pub mod entry;
 pub mod gen;
-pub mod utils;
+pub mod job;
+pub mod linking;
+pub mod model;
+pub mod pocket;
+pub mod progress;
+pub mod rest;
 pub mod svg;
@@ -1,9 +1,14 @@
 use super::{
-    gen::{CUTTER_RADIUS_DETAIL, CUTTER_RADIUS_ROUGH},
-    utils::*,
+    drop_cutter::{self, Triangle},
+    gen::GenerationError,
+    job::{self, JobDescription, JobError, Side},
+    progress::Progress,
 };
 use crate::{
-    cnc::block::Block,
+    cnc::{
+        block::Block,
+        mill::{Cutter, CutterShape},
+    },
     math::{
         geometry::{
             intersection::{Intersection, IntersectionFinder},
@@ -15,8 +20,8 @@ use crate::{
 };
 use itertools::Itertools;
 use kiddo::KdTree;
-use nalgebra::{geometry::Rotation2, point, vector, Point3, Vector2, Vector3};
-use std::collections::HashMap;
+use nalgebra::{geometry::Rotation2, vector, Point3, Vector2, Vector3};
+use rayon::prelude::*;
 
 const PLANE_SIZE: f64 = 7.0;
 pub const PLANE_CENTER: Vector3<f64> = vector![0.0, 0.0, 2.5];
@@ -24,119 +29,65 @@ pub const PLANE_CENTER: Vector3<f64> = vector![0.0, 0.0, 2.5];
 const NUMERICAL_STEP: f64 = 0.005;
 pub const INTERSECTION_STEP: f64 = 0.01;
 const KDTREE_SEARCH_RADIUS: f64 = INTERSECTION_STEP * 5.0;
-const SILHOUETTE_GUIDE_POINT: Point3<f64> = point![-2.0, 0.0, 2.5];
 const PERTURBATION: f64 = 0.1;
 const INTER_COOLDOWN: usize = 15;
 
 pub const BLOCK_SIZE: f32 = 150.0;
 pub const BLOCK_HEIGHT: f32 = 50.0;
 pub const BLOCK_BASE: f32 = 16.0;
+/// Height of the free moves above the top of the stock
+pub const SAFE_CLEARANCE: f32 = 16.0;
 
 pub const MODEL_SCALE: f32 = 30.0;
 
 const HEIGHTMAP_SAMPLING: usize = 200;
 const HEIGHTMAP_PARAMETER_SAMPLING: usize = 325;
+const DROP_CUTTER_TESSELLATION: usize = 150;
 const BLOCK_CONVERT: f32 = HEIGHTMAP_SAMPLING as f32 / BLOCK_SIZE;
 
-pub const BODY_ID: usize = 181;
-pub const LEFT_SHACKLE_ID: usize = 210;
-pub const RIGHT_SHACKLE_ID: usize = 239;
-pub const LEFT_SHIELD_ID: usize = 273;
-pub const RIGHT_SHIELD_ID: usize = 256;
-pub const LEFT_SCREW_ID: usize = 307;
-pub const RIGHT_SCREW_ID: usize = 290;
-
-pub const INTERSECTIONS: [InterGuide; 8] = [
-    InterGuide {
-        id_0: BODY_ID,
-        id_1: LEFT_SHIELD_ID,
-        guide: point![0.0, 1.0, 3.0],
-        shifted_sign_0: 1.0,
-        shifted_sign_1: 1.0,
-    },
-    InterGuide {
-        id_0: BODY_ID,
-        id_1: RIGHT_SHIELD_ID,
-        guide: point![0.0, 1.0, 1.0],
-        shifted_sign_0: 1.0,
-        shifted_sign_1: 1.0,
-    },
-    InterGuide {
-        id_0: LEFT_SHIELD_ID,
-        id_1: LEFT_SCREW_ID,
-        guide: point![0.0, 1.0, 3.5],
-        shifted_sign_0: 1.0,
-        shifted_sign_1: -1.0,
-    },
-    InterGuide {
-        id_0: RIGHT_SHIELD_ID,
-        id_1: RIGHT_SCREW_ID,
-        guide: point![0.0, 1.0, 1.5],
-        shifted_sign_0: 1.0,
-        shifted_sign_1: -1.0,
-    },
-    InterGuide {
-        id_0: BODY_ID,
-        id_1: LEFT_SHACKLE_ID,
-        guide: point![-1.0, 1.0, 4.0],
-        shifted_sign_0: 1.0,
-        shifted_sign_1: 1.0,
-    },
-    InterGuide {
-        id_0: BODY_ID,
-        id_1: LEFT_SHACKLE_ID,
-        guide: point![-1.0, 1.0, 3.0],
-        shifted_sign_0: 1.0,
-        shifted_sign_1: 1.0,
-    },
-    InterGuide {
-        id_0: BODY_ID,
-        id_1: RIGHT_SHACKLE_ID,
-        guide: point![-1.0, 1.0, 2.0],
-        shifted_sign_0: 1.0,
-        shifted_sign_1: 1.0,
-    },
-    InterGuide {
-        id_0: BODY_ID,
-        id_1: RIGHT_SHACKLE_ID,
-        guide: point![-1.0, 1.0, 1.0],
-        shifted_sign_0: 1.0,
-        shifted_sign_1: 1.0,
-    },
-];
-
-pub const LEFT_SHACKLE_INTERS: [usize; 2] = [4, 5];
-pub const RIGHT_SHACKLE_INTERS: [usize; 2] = [6, 7];
-pub const LEFT_SCREW_INTER: usize = 2;
-pub const RIGHT_SCREW_INTER: usize = 3;
-pub const LEFT_SHIELD_INTER: usize = 0;
-pub const RIGHT_SHIELD_INTER: usize = 1;
-
-const HOLE_INTERSECTIONS: [InterPlaneGuide; 2] = [
-    InterPlaneGuide {
-        id: LEFT_SHACKLE_ID,
-        guide: point![-1.25, 0.0, 3.5],
-    },
-    InterPlaneGuide {
-        id: RIGHT_SHACKLE_ID,
-        guide: point![-1.25, 0.0, 1.5],
-    },
-];
-
+/// Surfaces of the parts listed in the job description, in the same order
 pub struct Model {
-    pub surfaces: HashMap<usize, Box<dyn DifferentialParametricForm<2, 3> + Send + Sync>>,
+    pub job: JobDescription,
+    pub surfaces: Vec<Box<dyn DifferentialParametricForm<2, 3> + Send + Sync>>,
 }
 
 impl Model {
     pub fn new(
+        job: JobDescription,
         surfaces: Vec<Box<dyn DifferentialParametricForm<2, 3> + Send + Sync>>,
-        ids: Vec<usize>,
-    ) -> Self {
-        Self {
-            surfaces: HashMap::from_iter(ids.into_iter().zip(surfaces)),
+    ) -> Result<Self, JobError> {
+        assert_eq!(job.parts.len(), surfaces.len());
+        job.validate()?;
+        Ok(Self { job, surfaces })
+    }
+
+    fn part_idx(&self, name: &str) -> usize {
+        self.job
+            .part_idx(name)
+            .expect("Part names are validated when the model is created")
+    }
+
+    pub fn part(&self, name: &str) -> &dyn DifferentialParametricForm<2, 3> {
+        self.surfaces[self.part_idx(name)].as_ref()
+    }
+
+    /// Sign of the offset moving the cutter center out of the material of the part
+    pub fn shift_sign(&self, name: &str) -> f64 {
+        if self.job.parts[self.part_idx(name)].inverted {
+            -1.0
+        } else {
+            1.0
         }
     }
 
+    pub fn rough_radius(&self) -> f32 {
+        0.5 * self.job.cutters.rough
+    }
+
+    pub fn detail_radius(&self) -> f32 {
+        0.5 * self.job.cutters.detail
+    }
+
     pub fn sampled_block(&self) -> Block {
         let mut block = Block::new(
             vector![HEIGHTMAP_SAMPLING, HEIGHTMAP_SAMPLING],
@@ -150,26 +101,115 @@ impl Model {
             }
         }
 
-        for (id, surface) in &self.surfaces {
-            let multiplier = if *id == LEFT_SCREW_ID || *id == RIGHT_SCREW_ID {
-                -1.0
-            } else {
-                1.0
-            };
+        let radius = self.rough_radius();
+        for (part, surface) in self.job.parts.iter().zip(&self.surfaces) {
+            let multiplier = if part.inverted { -1.0 } else { 1.0 };
 
-            let shifted = ShiftedSurface::new(
-                surface.as_ref(),
-                multiplier * (CUTTER_RADIUS_ROUGH / MODEL_SCALE) as f64,
-            );
+            let shifted =
+                ShiftedSurface::new(surface.as_ref(), multiplier * (radius / MODEL_SCALE) as f64);
 
-            Self::create_height(&shifted, 0.0, &mut block);
-            Self::create_height(surface.as_ref(), CUTTER_RADIUS_ROUGH, &mut block);
+            Self::create_height(&shifted, 0.0, radius, &mut block);
+            Self::create_height(surface.as_ref(), radius, radius, &mut block);
         }
 
         block
     }
 
-    fn create_height(surface: &dyn DifferentialParametricForm<2, 3>, bump: f32, block: &mut Block) {
+    /// Heightmap of the lowest positions of the `cutter` tip not gouging any part, the block is
+    /// as high as the stock
+    pub fn drop_cutter_block(
+        &self,
+        cutter: &Cutter,
+        progress: &Progress,
+    ) -> Result<Block, GenerationError> {
+        let triangles = self.triangles();
+        let height = Self::stock_height_around(&triangles);
+
+        drop_cutter::drop_cutter(
+            &triangles,
+            cutter,
+            vector![HEIGHTMAP_SAMPLING, HEIGHTMAP_SAMPLING],
+            vector![BLOCK_SIZE, BLOCK_SIZE, height],
+            BLOCK_BASE,
+            progress,
+        )
+    }
+
+    /// Height of the stock the model is milled from, which is raised for the models higher than
+    /// the default block
+    pub fn stock_height(&self) -> f32 {
+        Self::stock_height_around(&self.triangles())
+    }
+
+    fn stock_height_around(triangles: &[Triangle]) -> f32 {
+        triangles
+            .iter()
+            .flatten()
+            .map(|p| p.z)
+            .fold(BLOCK_HEIGHT, f32::max)
+    }
+
+    /// Height at which the cutter moves freely above the stock
+    pub fn safe_height(&self) -> f32 {
+        self.stock_height() + SAFE_CLEARANCE
+    }
+
+    /// Heightmap of the highest points of the parts, on the base outside of them
+    pub fn surface_block(&self, progress: &Progress) -> Result<Block, GenerationError> {
+        self.drop_cutter_block(
+            &Cutter {
+                height: 0.0,
+                diameter: 0.0,
+                shape: CutterShape::Cylinder,
+            },
+            progress,
+        )
+    }
+
+    /// Tessellation of all parts in mill coordinates
+    fn triangles(&self) -> Vec<Triangle> {
+        const N: usize = DROP_CUTTER_TESSELLATION;
+
+        self.surfaces
+            .par_iter()
+            .flat_map_iter(|surface| {
+                let bounds = surface.bounds();
+                let points: Vec<Vector3<f32>> = (0..=N)
+                    .flat_map(|i| (0..=N).map(move |j| (i, j)))
+                    .map(|(i, j)| {
+                        let u = bounds.x.0 + (bounds.x.1 - bounds.x.0) * i as f64 / N as f64;
+                        let v = bounds.y.0 + (bounds.y.1 - bounds.y.0) * j as f64 / N as f64;
+                        let value =
+                            vec_64_to_32(surface.value(&vector![u, v]).coords - PLANE_CENTER)
+                                * MODEL_SCALE;
+
+                        vector![value.x, value.z, value.y + BLOCK_BASE]
+                    })
+                    .collect();
+
+                (0..N)
+                    .flat_map(|i| (0..N).map(move |j| (i, j)))
+                    .flat_map(move |(i, j)| {
+                        let idx = |i: usize, j: usize| i * (N + 1) + j;
+                        let [a, b, c, d] = [
+                            points[idx(i, j)],
+                            points[idx(i + 1, j)],
+                            points[idx(i + 1, j + 1)],
+                            points[idx(i, j + 1)],
+                        ];
+
+                        [[a, b, c], [a, c, d]]
+                    })
+            })
+            .collect()
+    }
+
+    fn create_height(
+        surface: &dyn DifferentialParametricForm<2, 3>,
+        bump: f32,
+        radius: f32,
+        block: &mut Block,
+    ) {
         let bounds = surface.bounds();
         let u_step = (bounds.x.1 - bounds.x.0) / HEIGHTMAP_PARAMETER_SAMPLING as f64;
         let v_step = (bounds.y.1 - bounds.y.0) / HEIGHTMAP_PARAMETER_SAMPLING as f64;
@@ -192,10 +232,9 @@ impl Model {
                     && y >= 0
                     && x < block.sampling().x as i64
                     && y < block.sampling().y as i64
-                    && block.height(x as usize, y as usize) < value.y as f32 - CUTTER_RADIUS_ROUGH
+                    && block.height(x as usize, y as usize) < value.y as f32 - radius
                 {
-                    *block.height_mut(x as usize, y as usize) =
-                        value.y as f32 - CUTTER_RADIUS_ROUGH;
+                    *block.height_mut(x as usize, y as usize) = value.y as f32 - radius;
                 }
 
                 v += v_step;
@@ -205,17 +244,25 @@ impl Model {
         }
     }
 
+    fn silhouette_surfaces(&self) -> Vec<&dyn DifferentialParametricForm<2, 3>> {
+        self.job
+            .silhouette
+            .iter()
+            .map(|name| self.part(name))
+            .collect()
+    }
+
     pub fn silhouette(&self) -> Option<Intersection> {
         let plane = Self::plane();
 
-        let intersections = [BODY_ID, LEFT_SHACKLE_ID, RIGHT_SHACKLE_ID]
-            .map(|id| &self.surfaces[&id])
-            .iter()
+        let intersections = self
+            .silhouette_surfaces()
+            .into_iter()
             .filter_map(|s| {
-                let mut finder = IntersectionFinder::new(&plane, s.as_ref());
+                let mut finder = IntersectionFinder::new(&plane, s);
                 finder.numerical_step = NUMERICAL_STEP;
                 finder.intersection_step = INTERSECTION_STEP;
-                finder.guide_point = Some(SILHOUETTE_GUIDE_POINT);
+                finder.guide_point = Some(self.job.silhouette_guide());
                 finder.find()
             })
             .collect_vec();
@@ -226,82 +273,166 @@ impl Model {
     }
 
     pub fn elevated_silhouette(&self) -> Option<Intersection> {
-        let dist = (CUTTER_RADIUS_DETAIL / MODEL_SCALE) as f64;
+        let dist = (self.detail_radius() / MODEL_SCALE) as f64;
         let mut plane = Self::plane();
         plane.height(dist);
 
-        let intersections = [BODY_ID, LEFT_SHACKLE_ID, RIGHT_SHACKLE_ID]
-            .map(|id| &self.surfaces[&id])
-            .iter()
+        let intersections = self
+            .silhouette_surfaces()
+            .into_iter()
             .map(|s| {
-                let shifted = ShiftedSurface::new(s.as_ref(), dist);
+                let shifted = ShiftedSurface::new(s, dist);
                 let mut finder = IntersectionFinder::new(&plane, &shifted);
                 finder.numerical_step = NUMERICAL_STEP;
                 finder.intersection_step = INTERSECTION_STEP;
-                finder.guide_point = Some(SILHOUETTE_GUIDE_POINT);
-                let mut intersection = finder.find().unwrap();
+                finder.guide_point = Some(self.job.silhouette_guide());
+                let mut intersection = finder.find()?;
                 intersection
                     .points
                     .iter_mut()
                     .for_each(|p| p.point.y = dist);
-                intersection
+                Some(intersection)
             })
-            .collect_vec();
+            .collect::<Option<Vec<_>>>()?;
 
         intersections
             .into_iter()
             .reduce(|x, y| looped_outer_intersection_sum(x, y, true, false))
     }
 
-    pub fn find_model_intersections(&self) -> [Intersection; INTERSECTIONS.len()] {
-        INTERSECTIONS.map(|ig| {
-            let shifted_0 = ShiftedSurface::new(
-                self.surfaces[&ig.id_0].as_ref(),
-                ig.shifted_sign_0 * (CUTTER_RADIUS_DETAIL / MODEL_SCALE) as f64,
-            );
-            let shifted_1 = ShiftedSurface::new(
-                self.surfaces[&ig.id_1].as_ref(),
-                ig.shifted_sign_1 * (CUTTER_RADIUS_DETAIL / MODEL_SCALE) as f64,
-            );
-
-            let mut finder = IntersectionFinder::new(&shifted_0, &shifted_1);
-            finder.numerical_step = NUMERICAL_STEP;
-            finder.intersection_step = INTERSECTION_STEP;
-            finder.guide_point = Some(ig.guide);
-            let err = format!(
-                "Intersection between {} and {} not found!",
-                ig.id_0, ig.id_1
-            );
-            finder.find().expect(&err)
-        })
+    /// Intersections of the cutter offsets of the parts, in the order of the job description
+    pub fn find_model_intersections(&self) -> Result<Vec<Intersection>, GenerationError> {
+        let dist = (self.detail_radius() / MODEL_SCALE) as f64;
+
+        self.job
+            .intersections
+            .iter()
+            .map(|description| {
+                let [name_0, name_1] = &description.parts;
+                let [sign_0, sign_1] = match description.sides {
+                    Some(sides) => sides.map(Side::sign),
+                    None => [self.shift_sign(name_0), self.shift_sign(name_1)],
+                };
+                let shifted_0 = ShiftedSurface::new(self.part(name_0), sign_0 * dist);
+                let shifted_1 = ShiftedSurface::new(self.part(name_1), sign_1 * dist);
+
+                let mut finder = IntersectionFinder::new(&shifted_0, &shifted_1);
+                finder.numerical_step = NUMERICAL_STEP;
+                finder.intersection_step = INTERSECTION_STEP;
+                finder.guide_point = Some(job::guide_point(&description.guide));
+                finder
+                    .find()
+                    .ok_or_else(|| GenerationError::NoIntersection(name_0.clone(), name_1.clone()))
+            })
+            .collect()
     }
 
-    pub fn find_holes(&self) -> [Intersection; HOLE_INTERSECTIONS.len()] {
-        let dist = (CUTTER_RADIUS_DETAIL / MODEL_SCALE) as f64;
+    /// Contours of the holes, in the order of the job description
+    pub fn find_holes(&self) -> Result<Vec<Intersection>, GenerationError> {
+        let dist = (self.detail_radius() / MODEL_SCALE) as f64;
         let mut plane = Self::plane();
         plane.height(dist);
 
-        let shifted_body = ShiftedSurface::new(self.surfaces[&BODY_ID].as_ref(), dist);
-
-        let mut finder = IntersectionFinder::new(&plane, &shifted_body);
-        finder.numerical_step = NUMERICAL_STEP;
-        finder.intersection_step = INTERSECTION_STEP;
-        finder.guide_point = Some(SILHOUETTE_GUIDE_POINT);
-        let mut body_inter = finder
-            .find()
-            .expect("Could not find intersection of the main body with the plane");
-        body_inter.reverse();
-
-        HOLE_INTERSECTIONS.map(|ig| {
-            let shifted = ShiftedSurface::new(self.surfaces[&ig.id].as_ref(), dist);
-            let mut finder = IntersectionFinder::new(&plane, &shifted);
-            finder.numerical_step = NUMERICAL_STEP;
-            finder.intersection_step = INTERSECTION_STEP;
-            finder.guide_point = Some(ig.guide);
-            let err = format!("Intersection between {} and the plane not found!", ig.id);
-            let inter = finder.find().expect(&err);
-            looped_outer_intersection_sum(inter, body_inter.clone(), true, true)
-        })
+        self.job
+            .holes
+            .iter()
+            .map(|description| {
+                let missing = || {
+                    GenerationError::NoHole(description.part.clone(), description.around.clone())
+                };
+                let shifted_around = ShiftedSurface::new(self.part(&description.around), dist);
+
+                let mut finder = IntersectionFinder::new(&plane, &shifted_around);
+                finder.numerical_step = NUMERICAL_STEP;
+                finder.intersection_step = INTERSECTION_STEP;
+                finder.guide_point = Some(self.job.silhouette_guide());
+                let mut around_inter = finder.find().ok_or_else(missing)?;
+                around_inter.reverse();
+
+                let shifted = ShiftedSurface::new(self.part(&description.part), dist);
+                let mut finder = IntersectionFinder::new(&plane, &shifted);
+                finder.numerical_step = NUMERICAL_STEP;
+                finder.intersection_step = INTERSECTION_STEP;
+                finder.guide_point = Some(job::guide_point(&description.guide));
+                let inter = finder.find().ok_or_else(missing)?;
+                Ok(looped_outer_intersection_sum(
+                    inter,
+                    around_inter,
+                    true,
+                    true,
+                ))
+            })
+            .collect()
+    }
+
+    /// Closed contours of the cutter center around the parts at the world `height`. Pieces of
+    /// the contours inside the offsets of other parts are removed and the rest is chained into
+    /// loops.
+    pub fn waterline(&self, height: f64) -> Vec<Vec<Point3<f64>>> {
+        let dist = (self.detail_radius() / MODEL_SCALE) as f64;
+        let mut plane = Self::plane();
+        plane.height(height);
+        let mut guide = self.job.silhouette_guide();
+        guide.y = height;
+
+        let contours = self
+            .job
+            .parts
+            .iter()
+            .zip(&self.surfaces)
+            .filter_map(|(part, surface)| {
+                let shifted =
+                    ShiftedSurface::new(surface.as_ref(), self.shift_sign(&part.name) * dist);
+                let mut finder = IntersectionFinder::new(&plane, &shifted);
+                finder.numerical_step = NUMERICAL_STEP;
+                finder.intersection_step = INTERSECTION_STEP;
+                finder.guide_point = Some(guide);
+                finder.find()
+            })
+            .collect_vec();
+
+        let mut loops = Vec::new();
+        let mut pieces = Vec::new();
+        for (i, contour) in contours.iter().enumerate() {
+            let outside = contour
+                .points
+                .iter()
+                .map(|p| {
+                    !contours
+                        .iter()
+                        .enumerate()
+                        .any(|(j, other)| j != i && other.looped && xz_contains(other, &p.point))
+                })
+                .collect_vec();
+
+            if contour.looped && outside.iter().all(|&o| o) {
+                loops.push(contour.points.iter().map(|p| p.point).collect_vec());
+                continue;
+            }
+
+            // Start at a removed point so that no piece wraps around the end of a loop
+            let start = if contour.looped {
+                outside.iter().position(|&o| !o).unwrap_or(0)
+            } else {
+                0
+            };
+
+            let mut piece = Vec::new();
+            for idx in (0..contour.points.len()).map(|k| (k + start) % contour.points.len()) {
+                if outside[idx] {
+                    piece.push(contour.points[idx].point);
+                } else if !piece.is_empty() {
+                    pieces.push(std::mem::take(&mut piece));
+                }
+            }
+
+            if !piece.is_empty() {
+                pieces.push(piece);
+            }
+        }
+
+        loops.extend(chain_pieces(pieces));
+        loops
     }
 
     pub fn plane() -> XZPlane {
@@ -410,6 +541,64 @@ fn looped_outer_intersection_sum(
     }
 }
 
+/// Whether the looped intersection with a horizontal plane encloses `point` in the XZ plane
+fn xz_contains(intersection: &Intersection, point: &Point3<f64>) -> bool {
+    let mut inside = false;
+
+    for (a, b) in intersection.points.iter().circular_tuple_windows() {
+        let (a, b) = (a.point, b.point);
+        if (a.z > point.z) != (b.z > point.z)
+            && point.x < a.x + (point.z - a.z) / (b.z - a.z) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+/// Joins the pieces whose ends are close to each other into loops
+fn chain_pieces(mut pieces: Vec<Vec<Point3<f64>>>) -> Vec<Vec<Point3<f64>>> {
+    const CHAIN_DISTANCE: f64 = INTERSECTION_STEP * 10.0;
+
+    let mut chains = Vec::new();
+    while let Some(mut chain) = pieces.pop() {
+        loop {
+            let end = *chain.last().unwrap();
+            if chain.len() > 2 && (chain[0] - end).norm() <= CHAIN_DISTANCE {
+                break;
+            }
+
+            let next = pieces
+                .iter()
+                .enumerate()
+                .flat_map(|(idx, piece)| {
+                    [
+                        (idx, false, (piece[0] - end).norm()),
+                        (idx, true, (piece[piece.len() - 1] - end).norm()),
+                    ]
+                })
+                .filter(|(_, _, distance)| *distance <= CHAIN_DISTANCE)
+                .min_by(|a, b| a.2.total_cmp(&b.2));
+
+            let Some((idx, reversed, _)) = next else {
+                break;
+            };
+
+            let mut piece = pieces.swap_remove(idx);
+            if reversed {
+                piece.reverse();
+            }
+
+            chain.extend(piece);
+        }
+
+        chains.push(chain);
+    }
+
+    chains
+}
+
 fn intersection_kdtree(intersection: &Intersection) -> KdTree<f64, 2> {
     let mut kdtree = KdTree::new();
     let rot = Rotation2::new(PERTURBATION);
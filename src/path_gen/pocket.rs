@@ -0,0 +1,458 @@
+use super::job::{MillingDirection, PocketingParameters};
+use nalgebra::{vector, Vector2, Vector3};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// Cells of the distance field per stepover
+const FIELD_REFINEMENT: f32 = 8.0;
+/// Loops further apart than this many stepovers are linked with a retract
+const LINK_DISTANCE: f32 = 1.5;
+
+/// Area between the closed `boundary` and the closed `islands`, in mill coordinates
+#[derive(Clone, Debug)]
+pub struct Pocket {
+    pub boundary: Vec<Vector2<f32>>,
+    pub islands: Vec<Vec<Vector2<f32>>>,
+}
+
+/// Closed outlines cut around from the outside or from the inside with the cutter side, nested
+/// outlines are holes
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub outlines: Vec<Vec<Vector2<f32>>>,
+    pub outside: bool,
+}
+
+/// Edge of the field grid, vertical edges go up from the sample, horizontal ones go right
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct GridEdge {
+    x: usize,
+    y: usize,
+    vertical: bool,
+}
+
+/// Signed distance from the pocket walls sampled on a grid, positive inside of the pocket
+struct DistanceField {
+    origin: Vector2<f32>,
+    cell: f32,
+    sampling: Vector2<usize>,
+    values: Vec<f32>,
+}
+
+impl Pocket {
+    pub fn new(boundary: Vec<Vector2<f32>>) -> Self {
+        Self {
+            boundary,
+            islands: Vec::new(),
+        }
+    }
+
+    pub fn with_island(mut self, island: Vec<Vector2<f32>>) -> Self {
+        self.islands.push(island);
+        self
+    }
+
+    fn walls(&self) -> impl Iterator<Item = &[Vector2<f32>]> {
+        std::iter::once(self.boundary.as_slice()).chain(self.islands.iter().map(|i| i.as_slice()))
+    }
+
+    fn contains(&self, point: &Vector2<f32>) -> bool {
+        polygon_contains(&self.boundary, point)
+            && !self
+                .islands
+                .iter()
+                .any(|island| polygon_contains(island, point))
+    }
+
+    /// Offsets of the walls by the cutter radius and successive stepovers, cut from the middle
+    /// outwards or from the walls inwards at `height`. Loops are linked directly when they are
+    /// close enough, otherwise the cutter is retracted to `safe_height`. Starts and ends at the
+    /// cutting height.
+    pub fn paths(
+        &self,
+        radius: f32,
+        height: f32,
+        safe_height: f32,
+        params: &PocketingParameters,
+    ) -> Vec<Vector3<f32>> {
+        let cell = params.stepover / FIELD_REFINEMENT;
+        let (min, max) = bounds(&self.boundary);
+        let field = DistanceField::new(min, max, cell, radius, |point| {
+            let distance = walls_distance(self.walls(), point);
+            if self.contains(point) {
+                distance
+            } else {
+                -distance
+            }
+        });
+        let max = field.values.iter().copied().fold(0.0, f32::max);
+
+        let mut levels = Vec::new();
+        let mut level = radius;
+        while level < max {
+            levels.push(level);
+            level += params.stepover;
+        }
+
+        if params.inside_out {
+            levels.reverse();
+        }
+
+        // The uncut material is towards the middle when cutting from the walls inwards
+        let higher_on_left = params.direction.material_on_left() != params.inside_out;
+
+        let mut locs: Vec<Vector3<f32>> = Vec::new();
+        for level in levels {
+            let loops = field.oriented_contours(level, higher_on_left);
+            extend_loops(
+                &mut locs,
+                loops,
+                height,
+                safe_height,
+                LINK_DISTANCE * params.stepover,
+                |from, to| field.clear(from, to, radius),
+            );
+        }
+
+        locs
+    }
+}
+
+impl Profile {
+    pub fn new(outlines: Vec<Vec<Vector2<f32>>>, outside: bool) -> Self {
+        Self { outlines, outside }
+    }
+
+    fn contains(&self, point: &Vector2<f32>) -> bool {
+        self.outlines
+            .iter()
+            .filter(|outline| polygon_contains(outline, point))
+            .count()
+            % 2
+            == 1
+    }
+
+    /// Path of the cutter center going around the outlines at `height`, the cutter is retracted
+    /// to `safe_height` between the loops
+    pub fn paths(
+        &self,
+        radius: f32,
+        height: f32,
+        safe_height: f32,
+        direction: MillingDirection,
+    ) -> Vec<Vector3<f32>> {
+        let cell = radius / FIELD_REFINEMENT;
+        let (min, max) = bounds(self.outlines.iter().flatten());
+
+        // The distance grows away from the part, which is the material left by the cutter
+        let field = DistanceField::new(min, max, cell, radius + cell, |point| {
+            let distance = walls_distance(self.outlines.iter().map(|o| o.as_slice()), point);
+            if self.contains(point) != self.outside {
+                distance
+            } else {
+                -distance
+            }
+        });
+
+        let loops = field.oriented_contours(radius, !direction.material_on_left());
+
+        let mut locs = Vec::new();
+        extend_loops(&mut locs, loops, height, safe_height, 0.0, |_, _| false);
+        locs
+    }
+}
+
+impl DistanceField {
+    /// Samples the `distance` in the rectangle from `min` to `max` extended by `margin` and a cell
+    fn new(
+        min: Vector2<f32>,
+        max: Vector2<f32>,
+        cell: f32,
+        margin: f32,
+        distance: impl Fn(&Vector2<f32>) -> f32 + Sync,
+    ) -> Self {
+        // Samples on the border are always outside, so that all contours are closed
+        let origin = min - vector![margin + cell, margin + cell];
+        let size = max - min + vector![2.0 * (margin + cell), 2.0 * (margin + cell)];
+        let sampling = vector![
+            (size.x / cell).ceil() as usize + 1,
+            (size.y / cell).ceil() as usize + 1
+        ];
+
+        let values = (0..sampling.y)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                let distance = &distance;
+                (0..sampling.x)
+                    .map(move |x| distance(&(origin + vector![x as f32, y as f32] * cell)))
+            })
+            .collect();
+
+        Self {
+            origin,
+            cell,
+            sampling,
+            values,
+        }
+    }
+
+    fn value(&self, x: usize, y: usize) -> f32 {
+        self.values[x + y * self.sampling.x]
+    }
+
+    fn position(&self, x: usize, y: usize) -> Vector2<f32> {
+        self.origin + vector![x as f32, y as f32] * self.cell
+    }
+
+    fn interpolate(&self, point: &Vector2<f32>) -> f32 {
+        let local = (point - self.origin) / self.cell;
+        let x = (local.x.floor().max(0.0) as usize).min(self.sampling.x - 2);
+        let y = (local.y.floor().max(0.0) as usize).min(self.sampling.y - 2);
+        let tx = local.x - x as f32;
+        let ty = local.y - y as f32;
+
+        let bottom = self.value(x, y) * (1.0 - tx) + self.value(x + 1, y) * tx;
+        let top = self.value(x, y + 1) * (1.0 - tx) + self.value(x + 1, y + 1) * tx;
+        bottom * (1.0 - ty) + top * ty
+    }
+
+    /// Whether the field stays at least at `level` along the segment, up to the sampling error
+    fn clear(&self, from: &Vector2<f32>, to: &Vector2<f32>, level: f32) -> bool {
+        let samples = ((to - from).norm() / self.cell).ceil().max(1.0) as usize;
+        (0..=samples).all(|s| {
+            let point = from.lerp(to, s as f32 / samples as f32);
+            self.interpolate(&point) >= level - 0.1 * self.cell
+        })
+    }
+
+    /// Point where the contour at `level` crosses the edge
+    fn crossing(&self, edge: GridEdge, level: f32) -> Vector2<f32> {
+        let (x, y) = if edge.vertical {
+            (edge.x, edge.y + 1)
+        } else {
+            (edge.x + 1, edge.y)
+        };
+
+        let a = self.value(edge.x, edge.y);
+        let b = self.value(x, y);
+        let t = ((level - a) / (b - a)).clamp(0.0, 1.0);
+        self.position(edge.x, edge.y).lerp(&self.position(x, y), t)
+    }
+
+    /// Closed contours of the field at `level` found with marching squares
+    fn contours(&self, level: f32) -> Vec<Vec<Vector2<f32>>> {
+        let mut neighbours: HashMap<GridEdge, Vec<GridEdge>> = HashMap::new();
+
+        for y in 0..self.sampling.y - 1 {
+            for x in 0..self.sampling.x - 1 {
+                let bottom = GridEdge {
+                    x,
+                    y,
+                    vertical: false,
+                };
+                let right = GridEdge {
+                    x: x + 1,
+                    y,
+                    vertical: true,
+                };
+                let top = GridEdge {
+                    x,
+                    y: y + 1,
+                    vertical: false,
+                };
+                let left = GridEdge {
+                    x,
+                    y,
+                    vertical: true,
+                };
+
+                let above = [
+                    self.value(x, y) > level,
+                    self.value(x + 1, y) > level,
+                    self.value(x + 1, y + 1) > level,
+                    self.value(x, y + 1) > level,
+                ];
+                let case = above
+                    .iter()
+                    .enumerate()
+                    .fold(0, |case, (i, &a)| case | (a as usize) << i);
+
+                let center = 0.25
+                    * (self.value(x, y)
+                        + self.value(x + 1, y)
+                        + self.value(x + 1, y + 1)
+                        + self.value(x, y + 1))
+                    > level;
+
+                let segments: &[(GridEdge, GridEdge)] = match case {
+                    0 | 15 => &[],
+                    1 | 14 => &[(left, bottom)],
+                    2 | 13 => &[(bottom, right)],
+                    3 | 12 => &[(left, right)],
+                    4 | 11 => &[(right, top)],
+                    6 | 9 => &[(bottom, top)],
+                    7 | 8 => &[(left, top)],
+                    5 if center => &[(bottom, right), (top, left)],
+                    5 => &[(left, bottom), (right, top)],
+                    10 if center => &[(left, bottom), (right, top)],
+                    10 => &[(bottom, right), (top, left)],
+                    _ => unreachable!(),
+                };
+
+                for &(a, b) in segments {
+                    neighbours.entry(a).or_default().push(b);
+                    neighbours.entry(b).or_default().push(a);
+                }
+            }
+        }
+
+        let mut visited = HashSet::new();
+        let mut contours = Vec::new();
+        for &start in neighbours.keys() {
+            if !visited.insert(start) {
+                continue;
+            }
+
+            let mut contour = vec![self.crossing(start, level)];
+            let mut previous = start;
+            let mut current = neighbours[&start][0];
+
+            while current != start && visited.insert(current) {
+                contour.push(self.crossing(current, level));
+
+                let Some(next) = neighbours[&current]
+                    .iter()
+                    .copied()
+                    .find(|&e| e != previous)
+                else {
+                    break;
+                };
+
+                previous = current;
+                current = next;
+            }
+
+            if contour.len() > 2 {
+                contours.push(contour);
+            }
+        }
+
+        contours
+    }
+
+    /// Contours at `level` turned so that the higher values are on the left or on the right
+    fn oriented_contours(&self, level: f32, higher_on_left: bool) -> Vec<Vec<Vector2<f32>>> {
+        let mut loops = self.contours(level);
+        for contour in &mut loops {
+            if self.higher_on_left(contour, level) != higher_on_left {
+                contour.reverse();
+            }
+        }
+
+        loops
+    }
+
+    fn higher_on_left(&self, contour: &[Vector2<f32>], level: f32) -> bool {
+        let direction = contour[1] - contour[0];
+        let left = vector![-direction.y, direction.x].normalize();
+        let middle = 0.5 * (contour[0] + contour[1]);
+        self.interpolate(&(middle + left * self.cell * 0.5)) > level
+    }
+}
+
+/// Cuts the closed loops at `height` starting each one from the point nearest to the cutter.
+/// Loops further than `link_distance` from the cutter or not reachable along a `clear` line are
+/// reached at `safe_height`.
+fn extend_loops(
+    locs: &mut Vec<Vector3<f32>>,
+    mut loops: Vec<Vec<Vector2<f32>>>,
+    height: f32,
+    safe_height: f32,
+    link_distance: f32,
+    clear: impl Fn(&Vector2<f32>, &Vector2<f32>) -> bool,
+) {
+    while !loops.is_empty() {
+        let current = locs.last().map_or(Vector2::zeros(), |p| p.xy());
+        let (idx, start) = nearest_loop(&loops, &current);
+        let mut contour = loops.swap_remove(idx);
+        contour.rotate_left(start);
+        contour.push(contour[0]);
+
+        let start = contour[0];
+        if let Some(last) = locs.last().copied() {
+            if (last.xy() - start).norm() > link_distance || !clear(&last.xy(), &start) {
+                locs.push(vector![last.x, last.y, safe_height]);
+                locs.push(vector![start.x, start.y, safe_height]);
+            }
+        }
+
+        locs.extend(contour.iter().map(|p| vector![p.x, p.y, height]));
+    }
+}
+
+/// Index of the loop closest to `point` and of its vertex closest to it
+fn nearest_loop(loops: &[Vec<Vector2<f32>>], point: &Vector2<f32>) -> (usize, usize) {
+    loops
+        .iter()
+        .enumerate()
+        .flat_map(|(l, contour)| {
+            contour
+                .iter()
+                .enumerate()
+                .map(move |(v, p)| (l, v, (p - point).norm_squared()))
+        })
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(l, v, _)| (l, v))
+        .unwrap()
+}
+
+fn bounds<'a>(points: impl IntoIterator<Item = &'a Vector2<f32>>) -> (Vector2<f32>, Vector2<f32>) {
+    points.into_iter().fold(
+        (
+            vector![f32::INFINITY, f32::INFINITY],
+            vector![-f32::INFINITY, -f32::INFINITY],
+        ),
+        |(min, max), p| (min.inf(p), max.sup(p)),
+    )
+}
+
+/// Distance from the nearest edge of the closed polygons
+fn walls_distance<'a>(
+    walls: impl Iterator<Item = &'a [Vector2<f32>]>,
+    point: &Vector2<f32>,
+) -> f32 {
+    walls
+        .flat_map(|wall| {
+            wall.iter()
+                .zip(wall.iter().cycle().skip(1))
+                .map(|(a, b)| segment_distance(a, b, point))
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+fn segment_distance(a: &Vector2<f32>, b: &Vector2<f32>, point: &Vector2<f32>) -> f32 {
+    let ab = b - a;
+    let length = ab.norm_squared();
+    let t = if length > 0.0 {
+        ((point - a).dot(&ab) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (a + ab * t - point).norm()
+}
+
+/// Even-odd test of the point against the closed polygon
+pub fn polygon_contains(polygon: &[Vector2<f32>], point: &Vector2<f32>) -> bool {
+    let mut inside = false;
+
+    for (a, b) in polygon.iter().zip(polygon.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
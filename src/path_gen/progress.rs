@@ -0,0 +1,47 @@
+use super::gen::GenerationError;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+};
+
+/// Progress of the path generation shared with the thread showing it, which can also cancel it
+#[derive(Clone, Default)]
+pub struct Progress {
+    /// Bits of the finished fraction
+    fraction: Arc<AtomicU32>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fraction(&self) -> f32 {
+        f32::from_bits(self.fraction.load(Ordering::Relaxed))
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails if the generation was cancelled
+    pub fn check(&self) -> Result<(), GenerationError> {
+        if self.is_cancelled() {
+            Err(GenerationError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Records `done` out of `total` steps and fails if the generation was cancelled
+    pub fn update(&self, done: usize, total: usize) -> Result<(), GenerationError> {
+        let fraction = (done as f32 / total.max(1) as f32).clamp(0.0, 1.0);
+        self.fraction.store(fraction.to_bits(), Ordering::Relaxed);
+        self.check()
+    }
+}
@@ -0,0 +1,100 @@
+use super::job::RestParameters;
+use crate::cnc::{block::Block, mill::Cutter};
+use nalgebra::{vector, Vector2, Vector3};
+
+/// Passes along the Y axis over the places where the `stock` is thicker than the threshold above
+/// the `target` heights of the cutter tip. The thick leftovers are removed in layers, which are
+/// returned from the top, each with its passes as separate segments.
+pub fn rest_layers(
+    stock: &Block,
+    target: &Block,
+    cutter: &Cutter,
+    params: &RestParameters,
+) -> Vec<Vec<Vec<Vector3<f32>>>> {
+    let size = *target.size();
+    let sample = target.sample_size().min();
+    let disc = disc_offsets(0.5 * cutter.diameter, sample);
+
+    let lines = (size.x / params.stepover).ceil() as usize;
+    let samples = (size.y / sample).ceil() as usize;
+
+    let mut layers: Vec<Vec<Vec<Vector3<f32>>>> = Vec::new();
+    for line in 0..lines {
+        let x = (line as f32 + 0.5) * params.stepover - 0.5 * size.x;
+        let points = (0..samples)
+            .filter_map(|s| {
+                let point = vector![x, (s as f32 + 0.5) * sample - 0.5 * size.y];
+                let tip = target.height_at(&point)?;
+                Some((point, tip, leftover(stock, cutter, &disc, &point, tip)))
+            })
+            .collect::<Vec<_>>();
+
+        let thickest = points.iter().map(|p| p.2).fold(0.0, f32::max);
+        let count = ((thickest - params.threshold) / params.step_down)
+            .ceil()
+            .max(0.0) as usize;
+        if layers.len() < count {
+            layers.resize(count, Vec::new());
+        }
+
+        for (layer, segments) in layers.iter_mut().enumerate().take(count) {
+            let offset = layer as f32 * params.step_down;
+            let mut run: Vec<Vector3<f32>> = Vec::new();
+
+            for (i, (point, tip, _)) in points.iter().enumerate() {
+                let needed = |idx: usize| {
+                    points
+                        .get(idx)
+                        .is_some_and(|p| p.2 > offset + params.threshold)
+                };
+
+                // One sample before and after the leftover so that it is fully removed
+                if needed(i) || needed(i + 1) || (i > 0 && needed(i - 1)) {
+                    let new = vector![point.x, point.y, tip + offset];
+                    let len = run.len();
+
+                    if len >= 2 && run[len - 1].z == new.z && run[len - 2].z == new.z {
+                        run[len - 1] = new;
+                    } else {
+                        run.push(new);
+                    }
+                } else if !run.is_empty() {
+                    segments.push(std::mem::take(&mut run));
+                }
+            }
+
+            if !run.is_empty() {
+                segments.push(run);
+            }
+        }
+    }
+
+    layers.reverse();
+    layers
+}
+
+/// Offsets of the samples under the cutter
+fn disc_offsets(radius: f32, sample: f32) -> Vec<Vector2<f32>> {
+    let reach = (radius / sample).ceil() as i32;
+
+    (-reach..=reach)
+        .flat_map(|x| (-reach..=reach).map(move |y| vector![x as f32, y as f32] * sample))
+        .filter(|offset| offset.norm() <= radius)
+        .collect()
+}
+
+/// Thickness of the stock the cutter with its tip at `tip` above `point` would remove
+fn leftover(
+    stock: &Block,
+    cutter: &Cutter,
+    disc: &[Vector2<f32>],
+    point: &Vector2<f32>,
+    tip: f32,
+) -> f32 {
+    disc.iter()
+        .filter_map(|offset| {
+            let height = stock.height_at(&(point + offset))?;
+            Some(height - tip - cutter.profile_height(offset.norm()))
+        })
+        .fold(0.0, f32::max)
+}
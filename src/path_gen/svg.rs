@@ -1,119 +1,723 @@
-use nalgebra::{vector, Vector2, Vector3};
-
-const SAFE_HEIGHT: f32 = 17.0;
-const WRIT_HEIGHT: f32 = 15.0;
-const WIDTH: f32 = 12.5;
-const HEIGHT: f32 = 14.0;
-const POINTS: [Vector2<f32>; 9] = [
-    vector![-5.0, -5.0],
-    vector![-5.0, 0.0],
-    vector![-5.0, 5.0],
-    vector![0.0, 5.0],
-    vector![5.0, 5.0],
-    vector![5.0, 0.0],
-    vector![5.0, -5.0],
-    vector![0.0, -5.0],
-    vector![0.0, 0.0],
-];
-
-const SEGMENTS: [[usize; 2]; 16] = [
-    // Outer 0-7
-    [0, 1],
-    [1, 2],
-    [2, 3],
-    [3, 4],
-    [4, 5],
-    [5, 6],
-    [6, 7],
-    [7, 0],
-    // Inside 8-15
-    [0, 8],
-    [1, 8],
-    [2, 8],
-    [3, 8],
-    [4, 8],
-    [5, 8],
-    [6, 8],
-    [7, 8],
-];
-
-const LETTERS: [u16; 27] = [
-    0b1111001100010001, // A
-    0b1111110001010100, // B
-    0b1100111100000000, // C
-    0b1111110001000100, // D
-    0b1100111100000001, // E
-    0b1100001100000010, // F
-    0b1101111100010000, // G
-    0b0011001100010001, // H
-    0b1100110001000100, // I
-    0b0011111000000000, // J
-    0b0000001100101001, // K
-    0b0000111100000000, // L
-    0b0011001110100000, // M
-    0b0011001110001000, // N
-    0b1111111100000000, // O
-    0b1110001100010001, // P
-    0b1111111100001000, // Q
-    0b1110001100011001, // R
-    0b1101110100010001, // S
-    0b1100000001000100, // T
-    0b0011111100000000, // U
-    0b0000001100100010, // V
-    0b0011001100001010, // W,
-    0b0000000010101010, // X,
-    0b0000000010100100, // Y,
-    0b1100110000100010, // Z
-    0b0000000000000010, // { (/)
-];
-
-fn parse_letter(c: u8) -> Vec<Vector3<f32>> {
-    let mut locs = Vec::new();
-
-    let segments = LETTERS[c as usize - b'a' as usize];
-    for (i, &segment) in SEGMENTS.iter().enumerate() {
-        if (segments << i) & 0x8000 == 0x8000 {
-            let [p_0, p_1] = segment;
-            let p_0 = POINTS[p_0];
-            let p_1 = POINTS[p_1];
-
-            locs.push(vector![p_0.x, p_0.y, SAFE_HEIGHT]);
-            locs.push(vector![p_0.x, p_0.y, WRIT_HEIGHT]);
-            locs.push(vector![p_1.x, p_1.y, WRIT_HEIGHT]);
-            locs.push(vector![p_1.x, p_1.y, SAFE_HEIGHT]);
+use super::{
+    job::{PocketingParameters, SvgOperation},
+    pocket::{self, Pocket, Profile},
+};
+use nalgebra::{matrix, vector, Matrix3, Rotation2, Vector2, Vector3};
+use std::{f64::consts::PI, path::Path};
+use thiserror::Error;
+
+/// Largest distance between the points of the flattened curves in mm
+const CURVE_STEP: f32 = 0.25;
+/// Polyline ends closer than this many mm are cut without raising the cutter
+const LINK_TOLERANCE: f32 = 1e-3;
+
+#[derive(Error, Debug)]
+pub enum SvgError {
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+    #[error("no <path> elements in the file")]
+    NoPaths,
+    #[error("invalid path data at character {0}")]
+    InvalidPathData(usize),
+    #[error("invalid transform {0:?}")]
+    InvalidTransform(String),
+}
+
+/// Subpath of an SVG `<path>` flattened to a polyline, in SVG user units
+#[derive(Clone, Debug)]
+pub struct SvgPath {
+    pub points: Vec<Vector2<f64>>,
+    pub closed: bool,
+}
+
+/// Position of the SVG drawing on the stock. The SVG Y axis points down, so the drawing is
+/// mirrored to be seen as in a viewer when looking at the stock from above.
+#[derive(Clone, Copy, Debug)]
+pub struct SvgPlacement {
+    /// Millimeters per SVG user unit
+    pub scale: f32,
+    /// Counterclockwise rotation in degrees
+    pub rotation: f32,
+    /// Position of the SVG origin in mill coordinates
+    pub offset: Vector2<f32>,
+}
+
+impl SvgPlacement {
+    pub fn apply(&self, point: &Vector2<f64>) -> Vector2<f32> {
+        let point = vector![point.x as f32, -point.y as f32] * self.scale;
+        Rotation2::new(self.rotation.to_radians()) * point + self.offset
+    }
+}
+
+/// Subpaths of all `<path>` elements of the file
+pub fn load(path: &Path, placement: &SvgPlacement) -> Result<Vec<SvgPath>, SvgError> {
+    let svg = std::fs::read_to_string(path).map_err(SvgError::Io)?;
+    parse(&svg, (CURVE_STEP / placement.scale) as f64)
+}
+
+/// Subpaths of all `<path>` elements transformed to the user units of the document, with the
+/// curves flattened to segments not longer than `step`. Transforms of the `<path>` and `<g>`
+/// elements are applied.
+pub fn parse(svg: &str, step: f64) -> Result<Vec<SvgPath>, SvgError> {
+    let mut paths = Vec::new();
+    let mut groups: Vec<Matrix3<f64>> = vec![Matrix3::identity()];
+    let mut rest = svg;
+
+    while let Some(start) = rest.find('<') {
+        let element = &rest[start..];
+        let end = element.find('>').unwrap_or(element.len());
+        let tag = &element[..end];
+        let parent = *groups.last().unwrap();
+
+        if is_element(tag, "</g") {
+            if groups.len() > 1 {
+                groups.pop();
+            }
+        } else if is_element(tag, "<g") && !tag.ends_with('/') {
+            groups.push(parent * element_transform(tag)?);
+        } else if is_element(tag, "<path") {
+            if let Some(data) = attribute(tag, "d") {
+                let transform = parent * element_transform(tag)?;
+                // Flattened in the units of the path, which are stretched by the transform
+                let linear = transform.fixed_view::<2, 2>(0, 0);
+                let stretch = linear.column(0).norm().max(linear.column(1).norm());
+
+                paths.extend(
+                    parse_path_data(data, step / stretch.max(f64::EPSILON))?
+                        .into_iter()
+                        .map(|path| SvgPath {
+                            points: path
+                                .points
+                                .iter()
+                                .map(|p| (transform * p.push(1.0)).xy())
+                                .collect(),
+                            closed: path.closed,
+                        }),
+                );
+            }
         }
+
+        rest = &element[end..];
     }
 
-    locs
+    if paths.is_empty() {
+        return Err(SvgError::NoPaths);
+    }
+
+    Ok(paths)
+}
+
+fn is_element(tag: &str, name: &str) -> bool {
+    tag.strip_prefix(name).is_some_and(|rest| {
+        rest.chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace() || c == '/')
+    })
+}
+
+fn element_transform(tag: &str) -> Result<Matrix3<f64>, SvgError> {
+    attribute(tag, "transform").map_or(Ok(Matrix3::identity()), parse_transform)
 }
 
-pub fn parse_signature(string: &str, pos: &Vector3<f32>) -> Vec<Vector3<f32>> {
-    let mut locs = Vec::new();
-    let mut cursor = *pos;
+/// Affine matrix of the `transform` attribute, the transforms of the list are applied from the
+/// last one
+pub fn parse_transform(transform: &str) -> Result<Matrix3<f64>, SvgError> {
+    let invalid = || SvgError::InvalidTransform(transform.to_string());
+    let mut result = Matrix3::identity();
+    let mut rest = transform;
 
-    for &c in string.as_bytes() {
-        match c {
-            b'\n' => {
-                cursor.x += HEIGHT;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+
+        let open = rest.find('(').ok_or_else(invalid)?;
+        let close = rest.find(')').ok_or_else(invalid)?;
+        let arguments = rest.get(open + 1..close).ok_or_else(invalid)?;
+
+        let mut reader = PathData {
+            data: arguments.as_bytes(),
+            pos: 0,
+        };
+        let mut values = Vec::new();
+        loop {
+            reader.skip_separators();
+            if reader.pos >= reader.data.len() {
+                break;
             }
-            b'\r' => {
-                cursor.y = pos.y;
+            values.push(reader.number().map_err(|_| invalid())?);
+        }
+
+        let translation = |x: f64, y: f64| matrix![1.0, 0.0, x; 0.0, 1.0, y; 0.0, 0.0, 1.0];
+        let rotation = |angle: f64| {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            matrix![cos, -sin, 0.0; sin, cos, 0.0; 0.0, 0.0, 1.0]
+        };
+
+        result *= match (rest[..open].trim(), values.as_slice()) {
+            ("matrix", &[a, b, c, d, e, f]) => matrix![a, c, e; b, d, f; 0.0, 0.0, 1.0],
+            ("translate", &[x]) => translation(x, 0.0),
+            ("translate", &[x, y]) => translation(x, y),
+            ("scale", &[s]) => Matrix3::new_nonuniform_scaling(&vector![s, s]),
+            ("scale", &[x, y]) => Matrix3::new_nonuniform_scaling(&vector![x, y]),
+            ("rotate", &[angle]) => rotation(angle),
+            ("rotate", &[angle, x, y]) => translation(x, y) * rotation(angle) * translation(-x, -y),
+            ("skewX", &[angle]) => {
+                matrix![1.0, angle.to_radians().tan(), 0.0; 0.0, 1.0, 0.0; 0.0, 0.0, 1.0]
             }
-            b'\x08' => {
-                // Backspace
-                cursor.y -= WIDTH;
+            ("skewY", &[angle]) => {
+                matrix![1.0, 0.0, 0.0; angle.to_radians().tan(), 1.0, 0.0; 0.0, 0.0, 1.0]
             }
-            _ => {
-                locs.extend(
-                    parse_letter(c)
-                        .iter()
-                        .map(|v| cursor + vector![v.x, v.y, v.z]),
-                );
+            _ => return Err(invalid()),
+        };
+
+        rest = &rest[close + 1..];
+    }
+
+    Ok(result)
+}
+
+fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = element;
+
+    while let Some(idx) = rest.find(name) {
+        let preceded_by_space = rest[..idx]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace);
+        let after = rest[idx + name.len()..].trim_start();
+
+        if let (true, Some(value)) = (preceded_by_space, after.strip_prefix('=')) {
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            let value = &value[1..];
+            return value.find(quote).map(|end| &value[..end]);
+        }
 
-                cursor.y += WIDTH;
+        rest = &rest[idx + name.len()..];
+    }
+
+    None
+}
+
+/// Reader of the numbers and commands of the path data
+struct PathData<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl PathData<'_> {
+    fn skip_separators(&mut self) {
+        while self
+            .data
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_whitespace() || *c == b',')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn command(&mut self) -> Option<u8> {
+        self.skip_separators();
+        let c = *self.data.get(self.pos)?;
+        c.is_ascii_alphabetic().then(|| {
+            self.pos += 1;
+            c
+        })
+    }
+
+    fn number(&mut self) -> Result<f64, SvgError> {
+        self.skip_separators();
+        let start = self.pos;
+        let digits = |data: &PathData, pos: &mut usize| {
+            while data.data.get(*pos).is_some_and(u8::is_ascii_digit) {
+                *pos += 1;
             }
+        };
+
+        let mut pos = self.pos;
+        if self.data.get(pos).is_some_and(|c| b"+-".contains(c)) {
+            pos += 1;
+        }
+        digits(self, &mut pos);
+        if self.data.get(pos) == Some(&b'.') {
+            pos += 1;
+            digits(self, &mut pos);
         }
+        if self.data.get(pos).is_some_and(|c| b"eE".contains(c)) {
+            pos += 1;
+            if self.data.get(pos).is_some_and(|c| b"+-".contains(c)) {
+                pos += 1;
+            }
+            digits(self, &mut pos);
+        }
+
+        self.pos = pos;
+        std::str::from_utf8(&self.data[start..pos])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .ok_or(SvgError::InvalidPathData(start))
+    }
+
+    fn point(&mut self) -> Result<Vector2<f64>, SvgError> {
+        Ok(vector![self.number()?, self.number()?])
+    }
+
+    /// Arc flags may be written without separators
+    fn flag(&mut self) -> Result<bool, SvgError> {
+        self.skip_separators();
+        let flag = match self.data.get(self.pos) {
+            Some(b'0') => false,
+            Some(b'1') => true,
+            _ => return Err(SvgError::InvalidPathData(self.pos)),
+        };
+
+        self.pos += 1;
+        Ok(flag)
+    }
+}
+
+/// Subpaths of the `d` attribute of a `<path>` element
+pub fn parse_path_data(data: &str, step: f64) -> Result<Vec<SvgPath>, SvgError> {
+    let mut reader = PathData {
+        data: data.as_bytes(),
+        pos: 0,
+    };
+
+    let mut paths: Vec<SvgPath> = Vec::new();
+    let mut current = Vector2::zeros();
+    let mut start = Vector2::zeros();
+    // Second control point of the last curve, reflected by the smooth curves
+    let mut last_control: Option<(u8, Vector2<f64>)> = None;
+    let mut command = None;
+
+    loop {
+        let next = match reader.command() {
+            Some(c) => c,
+            None if reader.pos >= reader.data.len() => break,
+            // Repeated command, a move is followed by lines
+            None => match command {
+                Some(b'M') => b'L',
+                Some(b'm') => b'l',
+                Some(c) if c != b'Z' && c != b'z' => c,
+                _ => return Err(SvgError::InvalidPathData(reader.pos)),
+            },
+        };
+        command = Some(next);
+
+        let relative = next.is_ascii_lowercase();
+        let origin = if relative { current } else { Vector2::zeros() };
+        let upper = next.to_ascii_uppercase();
+
+        let mut points = Vec::new();
+        match upper {
+            b'M' => {
+                current = origin + reader.point()?;
+                start = current;
+                paths.push(SvgPath {
+                    points: vec![current],
+                    closed: false,
+                });
+            }
+            b'Z' => {
+                current = start;
+                if let Some(path) = paths.last_mut() {
+                    path.closed = true;
+                    if path.points.last() != Some(&start) {
+                        path.points.push(start);
+                    }
+                }
+            }
+            b'L' => points.push(origin + reader.point()?),
+            b'H' => points.push(vector![origin.x + reader.number()?, current.y]),
+            b'V' => points.push(vector![current.x, origin.y + reader.number()?]),
+            b'C' | b'S' => {
+                let control_0 = if upper == b'C' {
+                    origin + reader.point()?
+                } else {
+                    reflected(last_control, b'C', current)
+                };
+                let control_1 = origin + reader.point()?;
+                let end = origin + reader.point()?;
+
+                points = flatten(step, &[current, control_0, control_1, end], |t| {
+                    let s = 1.0 - t;
+                    current * s * s * s
+                        + control_0 * 3.0 * s * s * t
+                        + control_1 * 3.0 * s * t * t
+                        + end * t * t * t
+                });
+                last_control = Some((b'C', control_1));
+            }
+            b'Q' | b'T' => {
+                let control = if upper == b'Q' {
+                    origin + reader.point()?
+                } else {
+                    reflected(last_control, b'Q', current)
+                };
+                let end = origin + reader.point()?;
+
+                points = flatten(step, &[current, control, end], |t| {
+                    let s = 1.0 - t;
+                    current * s * s + control * 2.0 * s * t + end * t * t
+                });
+                last_control = Some((b'Q', control));
+            }
+            b'A' => {
+                let radii = vector![reader.number()?.abs(), reader.number()?.abs()];
+                let rotation = reader.number()?.to_radians();
+                let large_arc = reader.flag()?;
+                let sweep = reader.flag()?;
+                let end = origin + reader.point()?;
+
+                points = arc(current, end, radii, rotation, large_arc, sweep, step);
+            }
+            _ => return Err(SvgError::InvalidPathData(reader.pos - 1)),
+        }
+
+        if !matches!(upper, b'C' | b'S' | b'Q' | b'T') {
+            last_control = None;
+        }
+
+        if !points.is_empty() {
+            if paths.is_empty() {
+                paths.push(SvgPath {
+                    points: vec![current],
+                    closed: false,
+                });
+            }
+
+            current = *points.last().unwrap();
+            paths.last_mut().unwrap().points.extend(points);
+        }
+
+        // Drawing after a close starts a new subpath at the start of the closed one
+        if upper == b'Z' {
+            paths.push(SvgPath {
+                points: vec![start],
+                closed: false,
+            });
+        }
+    }
+
+    Ok(paths.into_iter().filter(|p| p.points.len() > 1).collect())
+}
+
+/// First control point of a smooth curve
+fn reflected(last: Option<(u8, Vector2<f64>)>, kind: u8, current: Vector2<f64>) -> Vector2<f64> {
+    match last {
+        Some((k, control)) if k == kind => 2.0 * current - control,
+        _ => current,
+    }
+}
+
+/// Points of the curve after its start, with the count based on the length of the control polygon
+fn flatten(
+    step: f64,
+    controls: &[Vector2<f64>],
+    curve: impl Fn(f64) -> Vector2<f64>,
+) -> Vec<Vector2<f64>> {
+    let length: f64 = controls.windows(2).map(|w| (w[1] - w[0]).norm()).sum();
+    let samples = (length / step).ceil().max(1.0) as usize;
+
+    (1..=samples)
+        .map(|i| curve(i as f64 / samples as f64))
+        .collect()
+}
+
+/// Points of the elliptical arc after its start, see the implementation notes of the SVG
+/// specification for the conversion to the center parametrization
+fn arc(
+    from: Vector2<f64>,
+    to: Vector2<f64>,
+    mut radii: Vector2<f64>,
+    rotation: f64,
+    large_arc: bool,
+    sweep: bool,
+    step: f64,
+) -> Vec<Vector2<f64>> {
+    if radii.x == 0.0 || radii.y == 0.0 || from == to {
+        return vec![to];
+    }
+
+    let rotate = Rotation2::new(rotation);
+    let p = rotate.inverse() * (0.5 * (from - to));
+
+    let lambda = (p.x / radii.x).powi(2) + (p.y / radii.y).powi(2);
+    if lambda > 1.0 {
+        radii *= lambda.sqrt();
+    }
+
+    let (rx, ry) = (radii.x, radii.y);
+    let numerator = (rx * rx * ry * ry - rx * rx * p.y * p.y - ry * ry * p.x * p.x).max(0.0);
+    let denominator = rx * rx * p.y * p.y + ry * ry * p.x * p.x;
+    let mut coefficient = (numerator / denominator).sqrt();
+    if large_arc == sweep {
+        coefficient = -coefficient;
+    }
+
+    let center_p = coefficient * vector![rx * p.y / ry, -ry * p.x / rx];
+    let center = rotate * center_p + 0.5 * (from + to);
+
+    let angle = |v: Vector2<f64>| v.y.atan2(v.x);
+    let start = angle(vector![(p.x - center_p.x) / rx, (p.y - center_p.y) / ry]);
+    let end = angle(vector![(-p.x - center_p.x) / rx, (-p.y - center_p.y) / ry]);
+
+    let mut delta = end - start;
+    if sweep && delta < 0.0 {
+        delta += 2.0 * PI;
+    } else if !sweep && delta > 0.0 {
+        delta -= 2.0 * PI;
+    }
+
+    let samples = (delta.abs() * rx.max(ry) / step).ceil().max(1.0) as usize;
+    let mut points: Vec<_> = (1..samples)
+        .map(|i| {
+            let theta = start + delta * i as f64 / samples as f64;
+            center + rotate * vector![rx * theta.cos(), ry * theta.sin()]
+        })
+        .collect();
+
+    points.push(to);
+    points
+}
+
+/// Path of the cutter center for the subpaths placed on the stock with the top at `height`.
+/// Engraving follows all subpaths, the profiles and the pocket use only the closed ones and are
+/// cut in passes not deeper than `step_down`.
+#[allow(clippy::too_many_arguments)]
+pub fn toolpaths(
+    paths: &[SvgPath],
+    placement: &SvgPlacement,
+    operation: SvgOperation,
+    radius: f32,
+    height: f32,
+    depth: f32,
+    step_down: Option<f32>,
+    safe_height: f32,
+    pocketing: &PocketingParameters,
+) -> Vec<Vector3<f32>> {
+    let bottom = height - depth;
+    let placed = paths.iter().map(|p| {
+        p.points
+            .iter()
+            .map(|v| placement.apply(v))
+            .collect::<Vec<_>>()
+    });
+
+    match operation {
+        SvgOperation::Engrave => engrave(placed.collect(), bottom, safe_height),
+        SvgOperation::ProfileOutside | SvgOperation::ProfileInside => {
+            let outlines = closed_outlines(paths, placed);
+            let profile = Profile::new(outlines, operation == SvgOperation::ProfileOutside);
+            let mut locs = Vec::new();
+
+            for level in levels(height, bottom, step_down) {
+                let path = profile.paths(radius, level, safe_height, pocketing.direction);
+                extend_retracted(&mut locs, path, safe_height);
+            }
+
+            locs
+        }
+        SvgOperation::Pocket => {
+            let outlines = closed_outlines(paths, placed);
+            let levels = levels(height, bottom, step_down);
+            let mut locs = Vec::new();
+
+            for pocket in pockets(outlines) {
+                for &level in &levels {
+                    let path = pocket.paths(radius, level, safe_height, pocketing);
+                    extend_retracted(&mut locs, path, safe_height);
+                }
+            }
+
+            locs
+        }
+    }
+}
+
+/// Heights of the passes from the `top` down to the `bottom`
+fn levels(top: f32, bottom: f32, step_down: Option<f32>) -> Vec<f32> {
+    let mut levels = Vec::new();
+
+    if let Some(step_down) = step_down {
+        let mut level = top - step_down;
+        while level > bottom {
+            levels.push(level);
+            level -= step_down;
+        }
+    }
+
+    levels.push(bottom);
+    levels
+}
+
+/// Extends `locs` with the path, moving between them at the `safe_height`
+fn extend_retracted(locs: &mut Vec<Vector3<f32>>, path: Vec<Vector3<f32>>, safe_height: f32) {
+    if let (Some(last), Some(first)) = (locs.last().copied(), path.first()) {
+        locs.push(vector![last.x, last.y, safe_height]);
+        locs.push(vector![first.x, first.y, safe_height]);
+    }
+
+    locs.extend(path);
+}
+
+fn closed_outlines(
+    paths: &[SvgPath],
+    placed: impl Iterator<Item = Vec<Vector2<f32>>>,
+) -> Vec<Vec<Vector2<f32>>> {
+    paths
+        .iter()
+        .zip(placed)
+        .filter(|(p, _)| p.closed)
+        .map(|(_, mut outline)| {
+            // The closing point repeats the start
+            if outline.len() > 1
+                && (outline[0] - outline[outline.len() - 1]).norm() < LINK_TOLERANCE
+            {
+                outline.pop();
+            }
+            outline
+        })
+        .filter(|o| o.len() > 2)
+        .collect()
+}
+
+/// Polylines cut one after another, joined without retracts when they touch
+fn engrave(polylines: Vec<Vec<Vector2<f32>>>, bottom: f32, safe_height: f32) -> Vec<Vector3<f32>> {
+    let mut locs: Vec<Vector3<f32>> = Vec::new();
+
+    for polyline in polylines {
+        let first = polyline[0];
+        let linked = locs
+            .last()
+            .is_some_and(|last| (last.xy() - first).norm() < LINK_TOLERANCE);
+
+        if !linked {
+            if let Some(&last) = locs.last() {
+                locs.push(vector![last.x, last.y, safe_height]);
+            }
+            locs.push(vector![first.x, first.y, safe_height]);
+        }
+
+        locs.extend(polyline.iter().map(|p| vector![p.x, p.y, bottom]));
     }
 
     locs
 }
+
+/// Pockets of the outlines, an outline inside of an odd number of others is an island of the
+/// smallest one containing it
+fn pockets(outlines: Vec<Vec<Vector2<f32>>>) -> Vec<Pocket> {
+    let area = |outline: &[Vector2<f32>]| {
+        0.5 * outline
+            .iter()
+            .zip(outline.iter().cycle().skip(1))
+            .map(|(a, b)| a.x * b.y - b.x * a.y)
+            .sum::<f32>()
+            .abs()
+    };
+    let containing = |idx: usize| -> Vec<usize> {
+        (0..outlines.len())
+            .filter(|&other| {
+                other != idx && pocket::polygon_contains(&outlines[other], &outlines[idx][0])
+            })
+            .collect()
+    };
+
+    let mut pockets: Vec<(usize, Pocket)> = Vec::new();
+    let mut islands = Vec::new();
+    for idx in 0..outlines.len() {
+        let parents = containing(idx);
+        if parents.len() % 2 == 0 {
+            pockets.push((idx, Pocket::new(outlines[idx].clone())));
+        } else {
+            let parent = parents
+                .into_iter()
+                .min_by(|&a, &b| area(&outlines[a]).total_cmp(&area(&outlines[b])))
+                .unwrap();
+            islands.push((parent, idx));
+        }
+    }
+
+    for (parent, idx) in islands {
+        if let Some((_, pocket)) = pockets.iter_mut().find(|(p, _)| *p == parent) {
+            pocket.islands.push(outlines[idx].clone());
+        }
+    }
+
+    pockets.into_iter().map(|(_, pocket)| pocket).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arc_flags_need_no_separators() {
+        let swept = parse_path_data("M0 0a5 5 0 1110 0", 1.0).unwrap();
+        let reversed = parse_path_data("M0 0a5 5 0 0010 0", 1.0).unwrap();
+
+        assert_eq!(swept[0].points.last(), Some(&vector![10.0, 0.0]));
+        assert_eq!(reversed[0].points.last(), Some(&vector![10.0, 0.0]));
+        // The sweep flag picks the half of the circle
+        assert!(swept[0].points.iter().any(|p| p.y < -4.9));
+        assert!(reversed[0].points.iter().any(|p| p.y > 4.9));
+    }
+
+    #[test]
+    fn coordinates_after_move_are_lines() {
+        let paths = parse_path_data("M 0,0 10,0 10,10 m 5 5 1 0 z", 1.0).unwrap();
+
+        assert_eq!(paths.len(), 2);
+        assert_eq!(
+            paths[0].points,
+            vec![vector![0.0, 0.0], vector![10.0, 0.0], vector![10.0, 10.0]]
+        );
+        assert!(!paths[0].closed);
+        assert_eq!(
+            paths[1].points,
+            vec![
+                vector![15.0, 15.0],
+                vector![16.0, 15.0],
+                vector![15.0, 15.0]
+            ]
+        );
+        assert!(paths[1].closed);
+    }
+
+    #[test]
+    fn numbers_split_on_second_decimal_point() {
+        let paths = parse_path_data("M.5.5L-1-.5e1", 1.0).unwrap();
+
+        assert_eq!(
+            paths[0].points,
+            vec![vector![0.5, 0.5], vector![-1.0, -5.0]]
+        );
+    }
+
+    #[test]
+    fn group_and_path_transforms_are_applied() {
+        let svg = r#"<svg><g transform="translate(10 0)"><path transform="scale(2)" d="M1 1L2 1"/></g><path d="M0 0L1 0"/></svg>"#;
+        let paths = parse(svg, 1.0).unwrap();
+
+        assert_eq!(
+            paths[0].points,
+            vec![vector![12.0, 2.0], vector![14.0, 2.0]]
+        );
+        assert_eq!(paths[1].points, vec![vector![0.0, 0.0], vector![1.0, 0.0]]);
+    }
+
+    #[test]
+    fn transform_lists_apply_the_last_one_first() {
+        let transform = parse_transform("translate(5,0) rotate(90)").unwrap();
+        let point = transform * vector![1.0, 0.0, 1.0];
+
+        assert!((point - vector![5.0, 1.0, 1.0]).norm() < 1e-9);
+        assert!(matches!(
+            parse_transform("perspective(1)"),
+            Err(SvgError::InvalidTransform(_))
+        ));
+    }
+}
@@ -2,18 +2,124 @@ use crate::{main_control::MainControl, state::State};
 use kalimorfia::{
     cnc::{
         block::Block, mill::Mill, milling_player::MillingPlayer, milling_process::MillingProcess,
+        program::Program,
     },
-    entities::cnc_block::{CNCBlock, CNCBlockArgs},
+    entities::cnc_block::{CNCBlock, CNCBlockArgs, StockShape},
     path_gen::gen::*,
     path_gen::model::*,
+    path_gen::progress::Progress,
 };
 use nalgebra::vector;
 use std::path::Path;
 use std::rc::Rc;
+use std::sync::mpsc;
 
 const SAVE_PATH: &str = "gen-paths";
 const TEST_SAMPLING: i32 = 1500;
 
+/// Programs with the names of the files they are saved to, without the extension naming the
+/// cutter
+type Generated = Result<Vec<(Program, String)>, GenerationError>;
+type GenerateFn = Box<dyn FnOnce(&Progress) -> Generated + Send>;
+
+/// Path generation running on a worker thread, so that the UI stays responsive
+pub struct PathGeneration {
+    running: Option<RunningGeneration>,
+    /// Saved files or the error of the last finished generation
+    outcome: Option<Result<String, String>>,
+}
+
+struct RunningGeneration {
+    name: &'static str,
+    progress: Progress,
+    receiver: mpsc::Receiver<Result<Vec<String>, GenerationError>>,
+}
+
+impl PathGeneration {
+    pub fn new() -> Self {
+        Self {
+            running: None,
+            outcome: None,
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Generates the programs on a worker thread and saves them in the `SAVE_PATH` directory
+    fn start(&mut self, name: &'static str, generate: GenerateFn) {
+        let progress = Progress::new();
+        let (sender, receiver) = mpsc::channel();
+
+        let worker_progress = progress.clone();
+        std::thread::spawn(move || {
+            let saved = generate(&worker_progress).and_then(|programs| {
+                programs
+                    .into_iter()
+                    .map(|(program, name)| save_program(&program, &name))
+                    .collect()
+            });
+
+            let _ = sender.send(saved);
+        });
+
+        self.running = Some(RunningGeneration {
+            name,
+            progress,
+            receiver,
+        });
+        self.outcome = None;
+    }
+
+    /// Shows the error of a generation which could not be started
+    pub fn fail(&mut self, error: String) {
+        self.outcome = Some(Err(error));
+    }
+
+    /// Shows the progress of the running generation, returns whether it has just succeeded
+    fn progress_ui(&mut self, ui: &imgui::Ui) -> bool {
+        let Some(running) = &self.running else {
+            match &self.outcome {
+                Some(Ok(files)) => ui.text(format!("Saved {files}")),
+                Some(Err(err)) => ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Error: {err}")),
+                None => {}
+            }
+
+            return false;
+        };
+
+        ui.text(format!("Generating {}", running.name));
+        imgui::ProgressBar::new(running.progress.fraction()).build(ui);
+
+        if running.progress.is_cancelled() {
+            ui.text("Cancelling...");
+        } else if ui.button("Cancel") {
+            running.progress.cancel();
+        }
+
+        let outcome = match running.receiver.try_recv() {
+            Ok(Ok(files)) => Ok(files.join(", ")),
+            Ok(Err(err)) => Err(err.to_string()),
+            Err(mpsc::TryRecvError::Empty) => return false,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                Err(String::from("generation thread panicked"))
+            }
+        };
+
+        let succeeded = outcome.is_ok();
+        self.outcome = Some(outcome);
+        self.running = None;
+        succeeded
+    }
+}
+
+impl Default for PathGeneration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub fn path_gen_ui(ui: &imgui::Ui, state: &mut State, control: &mut MainControl) {
     ui.window("Path generation control")
         .size([500.0, 300.0], imgui::Condition::FirstUseEver)
@@ -23,43 +129,23 @@ pub fn path_gen_ui(ui: &imgui::Ui, state: &mut State, control: &mut MainControl)
             ui.text("Generation");
             ui.separator();
 
-            let mut add_block = false;
-
-            if ui.button("Rough paths") {
-                rough(&get_model(state, control))
-                    .save_to_file(Path::new(&format!("{SAVE_PATH}/1.k16")));
-                add_block = true;
-            }
-
-            if ui.button("Flat paths") {
-                if let Some(prog) = flat(&get_model(state, control)) {
-                    prog.save_to_file(Path::new(&format!("{SAVE_PATH}/2.f10")));
-                } else {
-                    println!("Failed to find flat paths -- try again");
-                }
-
-                add_block = true;
-            }
+            let add_block = control.path_generation.progress_ui(ui);
 
-            if ui.button("Detailed paths") {
-                detail(&get_model(state, control))
-                    .save_to_file(Path::new(&format!("{SAVE_PATH}/3.k08")));
-                add_block = true;
-            }
-
-            if ui.button("Signature paths") {
-                signa().save_to_file(Path::new(&format!("{SAVE_PATH}/4.k01")));
-                add_block = true;
+            if !control.path_generation.running() {
+                generation_buttons(ui, state, control);
             }
 
             if add_block {
-                control.add_cnc_block(
-                    state,
-                    CNCBlockArgs {
-                        size: vector![BLOCK_SIZE, BLOCK_SIZE, BLOCK_HEIGHT],
-                        sampling: vector![TEST_SAMPLING, TEST_SAMPLING],
-                    },
-                );
+                control
+                    .add_cnc_block(
+                        state,
+                        CNCBlockArgs {
+                            size: vector![BLOCK_SIZE, BLOCK_SIZE, BLOCK_HEIGHT],
+                            sampling: vector![TEST_SAMPLING, TEST_SAMPLING],
+                            shape: StockShape::Box,
+                        },
+                    )
+                    .expect("Box stock creation failed");
             }
 
             ui.separator();
@@ -86,27 +172,155 @@ pub fn path_gen_ui(ui: &imgui::Ui, state: &mut State, control: &mut MainControl)
                 test_holes(state, control);
             }
 
-            if ui.button("Rough-Flat and save Detailed") {
+            if ui.button("Rough-Flat and save Rest") {
                 test_rough_flat(state, control);
             }
         });
 }
 
-fn get_model(state: &mut State, control: &mut MainControl) -> Model {
-    let manager = control.entity_manager.borrow();
-    let (targets, ids) = state
-        .selector
-        .selected()
-        .iter()
-        .copied()
-        .filter_map(|id| manager.get_entity(id).as_parametric_2_to_3().zip(Some(id)))
-        .unzip();
-
-    Model::new(targets, ids)
+fn generation_buttons(ui: &imgui::Ui, state: &mut State, control: &mut MainControl) {
+    let mut generation = None;
+
+    if ui.button("Rough paths") {
+        generation = get_model(state, control).map(|model| {
+            let generate =
+                move |progress: &Progress| Ok(vec![(rough(&model, progress)?, String::from("1"))]);
+            ("rough paths", Box::new(generate) as GenerateFn)
+        });
+    }
+
+    if ui.button("Flat paths") {
+        generation = get_model(state, control).map(|model| {
+            let generate =
+                move |progress: &Progress| Ok(vec![(flat(&model, progress)?, String::from("2"))]);
+            ("flat paths", Box::new(generate) as GenerateFn)
+        });
+    }
+
+    if ui.button("Flat pocket paths") {
+        generation = get_model(state, control).map(|model| {
+            let generate = move |progress: &Progress| {
+                Ok(vec![(flat_pocket(&model, progress)?, String::from("2"))])
+            };
+            ("flat pocket paths", Box::new(generate) as GenerateFn)
+        });
+    }
+
+    if ui.button("Detailed paths") {
+        generation = get_model(state, control).map(|model| {
+            let generate =
+                move |progress: &Progress| Ok(vec![(detail(&model, progress)?, String::from("3"))]);
+            ("detailed paths", Box::new(generate) as GenerateFn)
+        });
+    }
+
+    if ui.button("Waterline paths") {
+        generation = get_model(state, control).map(|model| {
+            let generate = move |progress: &Progress| {
+                Ok(vec![(waterline(&model, progress)?, String::from("5"))])
+            };
+            ("waterline paths", Box::new(generate) as GenerateFn)
+        });
+    }
+
+    if ui.button("Engraving paths") {
+        generation = get_model(state, control).map(|model| {
+            let generate = move |_: &Progress| {
+                let program = engrave(&model).map_err(GenerationError::Engraving)?;
+                Ok(vec![(program, String::from("6"))])
+            };
+            ("engraving paths", Box::new(generate) as GenerateFn)
+        });
+    }
+
+    if ui.button("SVG paths") {
+        generation = get_model(state, control).map(|model| {
+            let generate = move |progress: &Progress| {
+                let programs = svg_programs(&model, progress)?;
+                Ok(programs
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, program)| (program, format!("7-{i}")))
+                    .collect())
+            };
+            ("SVG paths", Box::new(generate) as GenerateFn)
+        });
+    }
+
+    if ui.button("Rest paths") {
+        generation = get_model(state, control)
+            .zip(get_stock(state, control))
+            .map(|(model, stock)| {
+                let generate = move |progress: &Progress| {
+                    Ok(vec![(rest(&model, &stock, progress)?, String::from("8"))])
+                };
+                ("rest paths", Box::new(generate) as GenerateFn)
+            });
+    }
+
+    if ui.button("Signature paths") {
+        let generate = |_: &Progress| Ok(vec![(signa(), String::from("4"))]);
+        generation = Some(("signature paths", Box::new(generate) as GenerateFn));
+    }
+
+    if let Some((name, generate)) = generation {
+        control.path_generation.start(name, generate);
+    }
+}
+
+/// Saves the program in the `SAVE_PATH` directory, returns the name of the file. The extension
+/// names the cutter, so that it is simulated with the same one when loaded.
+fn save_program(program: &Program, name: &str) -> Result<String, GenerationError> {
+    let cutter = program.shape();
+    let extension = program
+        .file_extension()
+        .ok_or(GenerationError::UnnamedCutter(
+            cutter.shape,
+            cutter.diameter,
+        ))?;
+    let file = format!("{name}.{extension}");
+    program
+        .save_to_file(Path::new(&format!("{SAVE_PATH}/{file}")))
+        .map_err(GenerationError::Io)?;
+    Ok(file)
+}
+
+fn get_model(state: &mut State, control: &mut MainControl) -> Option<Model> {
+    match control.selected_model(state) {
+        Ok(model) => Some(model),
+        Err(err) => {
+            control
+                .path_generation
+                .fail(format!("Invalid model: {err}"));
+            None
+        }
+    }
+}
+
+/// Stock of the selected CNC block
+fn get_stock(state: &State, control: &mut MainControl) -> Option<Block> {
+    let stock = state.selector.selected().iter().find_map(|&id| {
+        control
+            .entity_manager
+            .borrow()
+            .get_entity(id)
+            .as_cnc_block()
+            .map(|block| block.stock_heightmap())
+    });
+
+    if stock.is_none() {
+        control.path_generation.fail(String::from(
+            "Select the CNC block with the simulated stock",
+        ));
+    }
+
+    stock
 }
 
 fn test_silhouette(state: &mut State, control: &mut MainControl) {
-    let model = get_model(state, control);
+    let Some(model) = get_model(state, control) else {
+        return;
+    };
     let Some(intersection) = model.silhouette() else {
         println!("Model has no intersection with the XZ plane");
         return;
@@ -115,7 +329,9 @@ fn test_silhouette(state: &mut State, control: &mut MainControl) {
 }
 
 fn test_elevated_silhouette(state: &mut State, control: &mut MainControl) {
-    let model = get_model(state, control);
+    let Some(model) = get_model(state, control) else {
+        return;
+    };
     let Some(intersection) = model.elevated_silhouette() else {
         println!("Model has no intersection with the XZ plane");
         return;
@@ -124,7 +340,9 @@ fn test_elevated_silhouette(state: &mut State, control: &mut MainControl) {
 }
 
 fn test_heightmap(state: &mut State, control: &mut MainControl) {
-    let model = get_model(state, control);
+    let Some(model) = get_model(state, control) else {
+        return;
+    };
     let block = model.sampled_block();
     let entity_block = Box::new(CNCBlock::with_block(
         control.gl,
@@ -137,16 +355,30 @@ fn test_heightmap(state: &mut State, control: &mut MainControl) {
 }
 
 fn test_intersections(state: &mut State, control: &mut MainControl) {
-    let model = get_model(state, control);
-    for intersection in model.find_model_intersections() {
-        control.add_intersection_curve(state, intersection);
+    let Some(model) = get_model(state, control) else {
+        return;
+    };
+    match model.find_model_intersections() {
+        Ok(intersections) => {
+            for intersection in intersections {
+                control.add_intersection_curve(state, intersection);
+            }
+        }
+        Err(err) => control.path_generation.fail(err.to_string()),
     }
 }
 
 fn test_holes(state: &mut State, control: &mut MainControl) {
-    let model = get_model(state, control);
-    for intersection in model.find_holes() {
-        control.add_intersection_curve(state, intersection);
+    let Some(model) = get_model(state, control) else {
+        return;
+    };
+    match model.find_holes() {
+        Ok(holes) => {
+            for hole in holes {
+                control.add_intersection_curve(state, hole);
+            }
+        }
+        Err(err) => control.path_generation.fail(err.to_string()),
     }
 }
 
@@ -156,11 +388,17 @@ fn test_rough_flat(state: &mut State, control: &mut MainControl) {
         vector![BLOCK_SIZE, BLOCK_SIZE, BLOCK_HEIGHT],
     );
 
-    let model = get_model(state, control);
-    let rough = rough(&model);
-    rough.save_to_file(Path::new(&format!("{SAVE_PATH}/1.k16")));
-    let flat = flat(&model).expect("Flat milling failed");
-    rough.save_to_file(Path::new(&format!("{SAVE_PATH}/2.f10")));
+    let Some(model) = get_model(state, control) else {
+        return;
+    };
+    let progress = Progress::new();
+    let rough = rough(&model, &progress).expect("Rough milling failed");
+    let flat = flat(&model, &progress).expect("Flat milling failed");
+    let saved = save_program(&rough, "1").and_then(|_| save_program(&flat, "2"));
+    if let Err(err) = saved {
+        control.path_generation.fail(err.to_string());
+        return;
+    }
 
     println!("Rough paths");
     let mut mill = Mill::new(rough.shape());
@@ -178,6 +416,8 @@ fn test_rough_flat(state: &mut State, control: &mut MainControl) {
     player.complete().expect("Milling error");
     let (_, _, block) = player.take().retake_all();
 
+    let rest = rest(&model, &block, &progress).expect("Rest milling failed");
+
     let block = Box::new(CNCBlock::with_block(
         control.gl,
         Rc::clone(&state.name_repo),
@@ -188,5 +428,7 @@ fn test_rough_flat(state: &mut State, control: &mut MainControl) {
     let id = control.entity_manager.borrow_mut().add_entity(block);
     state.selector.add_selectable(id);
 
-    detail(&get_model(state, control)).save_to_file(Path::new(&format!("{SAVE_PATH}/3.k08")));
+    if let Err(err) = save_program(&rest, "8") {
+        control.path_generation.fail(err.to_string());
+    }
 }
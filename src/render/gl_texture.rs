@@ -129,6 +129,39 @@ impl<'gl> GlTexture<'gl> {
         }
     }
 
+    /// Replaces a `rect_width` x `rect_height` rectangle starting at `x`, `y` of a float texture
+    /// loaded with `load_float`, `texture` contains the whole `width` wide texture
+    pub fn load_float_rect(
+        &self,
+        texture: &[f32],
+        width: usize,
+        x: usize,
+        y: usize,
+        rect_width: usize,
+        rect_height: usize,
+    ) {
+        let start = x + y * width;
+        let end = start + rect_width + (rect_height - 1) * width;
+
+        unsafe {
+            self.gl.bind_texture(glow::TEXTURE_2D, Some(self.handle));
+            self.gl
+                .pixel_store_i32(glow::UNPACK_ROW_LENGTH, width as i32);
+            self.gl.tex_sub_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                x as i32,
+                y as i32,
+                rect_width as i32,
+                rect_height as i32,
+                glow::RED,
+                glow::FLOAT,
+                glow::PixelUnpackData::Slice(utils::slice_as_raw(&texture[start..end])),
+            );
+            self.gl.pixel_store_i32(glow::UNPACK_ROW_LENGTH, 0);
+        }
+    }
+
     pub fn handle(&self) -> u32 {
         self.handle
     }